@@ -0,0 +1,119 @@
+//! RPC URL and keypair resolution: command-line flags first, then the Solana
+//! CLI config file, then the Solana CLI defaults.
+
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+const DEFAULT_URL: &str = "http://localhost:8899";
+
+/// The fields we read from `~/.config/solana/cli/config.yml`.
+#[derive(Debug, Default, Deserialize)]
+pub struct SolanaConfig {
+    pub json_rpc_url: Option<String>,
+    pub keypair_path: Option<String>,
+}
+
+impl SolanaConfig {
+    /// Reads `path`, or the default config location when `path` is `None`.
+    /// Only an explicitly requested file has to exist.
+    pub fn load(path: Option<PathBuf>, home: Option<&str>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => match home {
+                Some(home) => (
+                    PathBuf::from(home).join(".config/solana/cli/config.yml"),
+                    false,
+                ),
+                None => return Ok(SolanaConfig::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_yaml::from_str(&contents)
+                .with_context(|| format!("parsing {}", path.display())),
+            Err(_) if !required => Ok(SolanaConfig::default()),
+            Err(err) => Err(err).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub url: String,
+    pub keypair_path: String,
+}
+
+impl Settings {
+    pub fn resolve(
+        url: Option<&str>,
+        keypair: Option<&str>,
+        config: &SolanaConfig,
+        home: Option<&str>,
+    ) -> Self {
+        let url = match url {
+            Some(url) => normalize_url(url),
+            None => config
+                .json_rpc_url
+                .clone()
+                .unwrap_or_else(|| DEFAULT_URL.to_string()),
+        };
+        let keypair_path = keypair
+            .map(str::to_string)
+            .or_else(|| config.keypair_path.clone())
+            .unwrap_or_else(|| format!("{}/.config/solana/id.json", home.unwrap_or("~")));
+        Settings { url, keypair_path }
+    }
+}
+
+/// Expands the cluster monikers `solana --url` accepts.
+fn normalize_url(url: &str) -> String {
+    match url {
+        "l" | "localhost" => DEFAULT_URL,
+        "d" | "devnet" => "https://api.devnet.solana.com",
+        "t" | "testnet" => "https://api.testnet.solana.com",
+        "m" | "mainnet-beta" => "https://api.mainnet-beta.solana.com",
+        url => url,
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_config_and_defaults() {
+        let config: SolanaConfig = serde_yaml::from_str(
+            "json_rpc_url: http://validator:8899\n\
+             websocket_url: ''\n\
+             keypair_path: /keys/admin.json\n\
+             commitment: confirmed\n",
+        )
+        .unwrap();
+
+        let from_config = Settings::resolve(None, None, &config, Some("/home/me"));
+        assert_eq!(from_config.url, "http://validator:8899");
+        assert_eq!(from_config.keypair_path, "/keys/admin.json");
+
+        let from_flags = Settings::resolve(
+            Some("localhost"),
+            Some("/keys/other.json"),
+            &config,
+            Some("/home/me"),
+        );
+        assert_eq!(from_flags.url, DEFAULT_URL);
+        assert_eq!(from_flags.keypair_path, "/keys/other.json");
+
+        let defaults = Settings::resolve(None, None, &SolanaConfig::default(), Some("/home/me"));
+        assert_eq!(defaults.url, DEFAULT_URL);
+        assert_eq!(defaults.keypair_path, "/home/me/.config/solana/id.json");
+    }
+
+    #[test]
+    fn missing_default_config_is_not_an_error() {
+        let config = SolanaConfig::load(None, Some("/nonexistent")).unwrap();
+        assert!(config.json_rpc_url.is_none());
+        assert!(SolanaConfig::load(Some("/nonexistent/config.yml".into()), None).is_err());
+    }
+}
@@ -0,0 +1,583 @@
+//! `amm-cli`: create, fund, trade against and administer amm pools.
+
+mod config;
+
+use std::path::PathBuf;
+
+use amm_sdk::{
+    decode_pool, instructions, pda::fee_distribution_address, FeeDistribution, FeeShare, Pool,
+    PoolAddresses, SwapDirection, SwapQuote, UserAccounts,
+};
+use anchor_lang::{AccountDeserialize, AnchorDeserialize};
+use anchor_spl::{
+    associated_token::spl_associated_token_account::instruction::create_associated_token_account_idempotent,
+    token::{self, Mint, TokenAccount},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Parser, Subcommand, ValueEnum};
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signature},
+    signer::Signer,
+    transaction::Transaction,
+};
+
+use config::{Settings, SolanaConfig};
+
+#[derive(Parser)]
+#[command(
+    name = "amm-cli",
+    version,
+    about = "Manage amm pools from the command line"
+)]
+struct Cli {
+    /// RPC URL or moniker (localhost, devnet, testnet, mainnet-beta).
+    #[arg(long, short = 'u', global = true)]
+    url: Option<String>,
+
+    /// Fee payer and signing keypair.
+    #[arg(long, short = 'k', global = true)]
+    keypair: Option<String>,
+
+    /// Solana CLI config file to read defaults from.
+    #[arg(long, short = 'C', global = true)]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the pool for a mint pair.
+    Init {
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        /// Admin keypair; defaults to the signing keypair.
+        #[arg(long)]
+        admin: Option<String>,
+    },
+    /// Deposit liquidity from the signer's associated token accounts.
+    Deposit {
+        pool: Pubkey,
+        amount_a: u64,
+        amount_b: u64,
+        #[arg(long, default_value_t = 0)]
+        min_lp: u64,
+    },
+    /// Burn LP for a pro-rata share of both reserves.
+    Withdraw {
+        pool: Pubkey,
+        lp_amount: u64,
+        #[arg(long, default_value_t = 0)]
+        min_a: u64,
+        #[arg(long, default_value_t = 0)]
+        min_b: u64,
+    },
+    /// Swap between the pool's tokens.
+    Swap {
+        pool: Pubkey,
+        amount_in: u64,
+        #[arg(long, value_enum)]
+        direction: Direction,
+        #[arg(long, default_value_t = 0)]
+        min_out: u64,
+    },
+    /// Quote a swap by simulating `quote_swap` against current reserves.
+    Quote {
+        pool: Pubkey,
+        /// Input amount, or the desired output with `--exact-out`.
+        amount: u64,
+        #[arg(long, value_enum)]
+        direction: Direction,
+        #[arg(long)]
+        exact_out: bool,
+    },
+    /// Pause the pool, or resume it with `--unpause`.
+    Pause {
+        pool: Pubkey,
+        #[arg(long)]
+        unpause: bool,
+    },
+    /// Hand the pool to a new admin, who must also sign.
+    SetAdmin {
+        pool: Pubkey,
+        new_admin_keypair: String,
+    },
+    /// Send protocol fees to another owner's token accounts.
+    SetFeeRecipient { pool: Pubkey, fee_recipient: Pubkey },
+    /// Move protocol fees to the fee recipient's associated token accounts.
+    WithdrawFees {
+        pool: Pubkey,
+        amount_a: u64,
+        amount_b: u64,
+    },
+    /// Split protocol fees among owners, each given as `OWNER:BPS`. The
+    /// shares must sum to 10000.
+    SetFeeDistribution {
+        pool: Pubkey,
+        #[arg(required = true, value_parser = parse_fee_share)]
+        recipients: Vec<FeeShare>,
+    },
+    /// Pay out protocol fees to the fee distribution's associated token
+    /// accounts.
+    DistributeFees { pool: Pubkey },
+    /// Grow a pool created by an older program version to the current
+    /// layout.
+    MigratePool { pool: Pubkey },
+    /// Inspect pools.
+    Pool {
+        #[command(subcommand)]
+        command: PoolCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum PoolCommand {
+    /// Decode a pool and its vault balances.
+    Show { pool: Pubkey },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Direction {
+    AToB,
+    BToA,
+}
+
+fn parse_fee_share(arg: &str) -> Result<FeeShare> {
+    let (owner, share_bps) = arg
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected OWNER:BPS"))?;
+    Ok(FeeShare {
+        owner: owner.parse().context("owner")?,
+        share_bps: share_bps.parse().context("share bps")?,
+    })
+}
+
+impl From<Direction> for SwapDirection {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::AToB => SwapDirection::AtoB,
+            Direction::BToA => SwapDirection::BtoA,
+        }
+    }
+}
+
+struct Client {
+    rpc: RpcClient,
+    payer: Keypair,
+}
+
+impl Client {
+    fn pool(&self, address: &Pubkey) -> Result<(Pool, PoolAddresses)> {
+        let data = self
+            .rpc
+            .get_account_data(address)
+            .with_context(|| format!("fetching pool {}", address))?;
+        let pool = decode_pool(&data).map_err(|err| anyhow!("decoding pool: {}", err))?;
+        let addresses = PoolAddresses::from_pool(*address, &pool);
+        Ok((pool, addresses))
+    }
+
+    fn fee_distribution(&self, pool: &Pubkey) -> Result<FeeDistribution> {
+        let address = fee_distribution_address(pool).0;
+        let data = self
+            .rpc
+            .get_account_data(&address)
+            .with_context(|| format!("fetching fee distribution {}", address))?;
+        FeeDistribution::try_deserialize(&mut &data[..])
+            .map_err(|err| anyhow!("decoding fee distribution: {}", err))
+    }
+
+    fn token_account(&self, address: &Pubkey) -> Result<TokenAccount> {
+        let data = self.rpc.get_account_data(address)?;
+        TokenAccount::try_deserialize(&mut &data[..])
+            .map_err(|err| anyhow!("decoding token account {}: {}", address, err))
+    }
+
+    fn mint(&self, address: &Pubkey) -> Result<Mint> {
+        let data = self.rpc.get_account_data(address)?;
+        Mint::try_deserialize(&mut &data[..])
+            .map_err(|err| anyhow!("decoding mint {}: {}", address, err))
+    }
+
+    /// Idempotent creation of the payer's associated token account for `mint`.
+    fn create_ata(&self, mint: &Pubkey) -> Instruction {
+        self.create_ata_for(&self.payer.pubkey(), mint)
+    }
+
+    fn create_ata_for(&self, owner: &Pubkey, mint: &Pubkey) -> Instruction {
+        create_associated_token_account_idempotent(&self.payer.pubkey(), owner, mint, &token::ID)
+    }
+
+    fn send(&self, instructions: &[Instruction], extra_signers: &[&Keypair]) -> Result<Signature> {
+        let mut signers = vec![&self.payer];
+        signers.extend_from_slice(extra_signers);
+        let blockhash = self.rpc.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.payer.pubkey()),
+            &signers,
+            blockhash,
+        );
+        Ok(self.rpc.send_and_confirm_transaction(&transaction)?)
+    }
+
+    /// Runs a read-only instruction in simulation and returns its return data.
+    fn simulate(&self, instruction: Instruction) -> Result<Vec<u8>> {
+        let transaction = Transaction::new_with_payer(&[instruction], Some(&self.payer.pubkey()));
+        let result = self
+            .rpc
+            .simulate_transaction_with_config(
+                &transaction,
+                RpcSimulateTransactionConfig {
+                    sig_verify: false,
+                    replace_recent_blockhash: true,
+                    ..Default::default()
+                },
+            )?
+            .value;
+        if let Some(err) = result.err {
+            bail!(
+                "simulation failed: {}\n{}",
+                err,
+                result.logs.unwrap_or_default().join("\n")
+            );
+        }
+        let return_data = result
+            .return_data
+            .ok_or_else(|| anyhow!("simulation returned no data"))?;
+        Ok(STANDARD.decode(return_data.data.0)?)
+    }
+}
+
+fn read_keypair(path: &str) -> Result<Keypair> {
+    read_keypair_file(path).map_err(|err| anyhow!("reading keypair {}: {}", path, err))
+}
+
+fn print_quote(quote: &SwapQuote) {
+    println!("amount_in         {}", quote.amount_in);
+    println!("amount_out        {}", quote.amount_out);
+    println!("protocol_fee      {}", quote.protocol_fee);
+    println!("lp_fee            {}", quote.lp_fee);
+    println!("price_impact_bps  {}", quote.price_impact_bps);
+}
+
+fn show_pool(client: &Client, address: &Pubkey) -> Result<()> {
+    let (pool, addresses) = client.pool(address)?;
+    let vault_a = client.token_account(&addresses.vault_a)?;
+    let vault_b = client.token_account(&addresses.vault_b)?;
+    let fee_vault_a = client.token_account(&addresses.fee_vault_a)?;
+    let fee_vault_b = client.token_account(&addresses.fee_vault_b)?;
+    let lp_mint = client.mint(&addresses.lp_mint)?;
+
+    println!("pool              {}", address);
+    println!("admin             {}", pool.admin);
+    println!("fee_recipient     {}", pool.fee_recipient);
+    println!("mint_a            {}", pool.mint_a);
+    println!("mint_b            {}", pool.mint_b);
+    println!("vault_a           {} ({})", pool.vault_a, vault_a.amount);
+    println!("vault_b           {} ({})", pool.vault_b, vault_b.amount);
+    println!(
+        "lp_mint           {} (supply {})",
+        pool.lp_mint, lp_mint.supply
+    );
+    println!(
+        "fee_vault_a       {} ({})",
+        pool.fee_vault_a, fee_vault_a.amount
+    );
+    println!(
+        "fee_vault_b       {} ({})",
+        pool.fee_vault_b, fee_vault_b.amount
+    );
+    println!("fee_bps           {}", pool.fee_bps);
+    println!("protocol_fee_bps  {}", pool.protocol_fee_bps);
+    println!("flash_fee_bps     {}", pool.flash_fee_bps);
+    println!("paused            {}", pool.paused);
+    println!("volume_a          {}", pool.stats.volume_a);
+    println!("volume_b          {}", pool.stats.volume_b);
+    println!("swap_count        {}", pool.stats.swap_count);
+    println!("lp_fees_a         {}", pool.stats.lp_fees_a);
+    println!("lp_fees_b         {}", pool.stats.lp_fees_b);
+    println!("protocol_fees_a   {}", pool.stats.protocol_fees_a);
+    println!("protocol_fees_b   {}", pool.stats.protocol_fees_b);
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let home = std::env::var("HOME").ok();
+    let solana_config = SolanaConfig::load(cli.config, home.as_deref())?;
+    let settings = Settings::resolve(
+        cli.url.as_deref(),
+        cli.keypair.as_deref(),
+        &solana_config,
+        home.as_deref(),
+    );
+    let client = Client {
+        rpc: RpcClient::new_with_commitment(settings.url, CommitmentConfig::confirmed()),
+        payer: read_keypair(&settings.keypair_path)?,
+    };
+    let payer = client.payer.pubkey();
+
+    let signature = match cli.command {
+        Command::Init {
+            mint_a,
+            mint_b,
+            admin,
+        } => {
+            let addresses = PoolAddresses::derive(mint_a, mint_b);
+            let admin = admin.as_deref().map(read_keypair).transpose()?;
+            let admin_key = admin.as_ref().map_or(payer, |admin| admin.pubkey());
+            let extra_signers: Vec<&Keypair> = admin.iter().collect();
+            let signature = client.send(
+                &[instructions::initialize(&addresses, &payer, &admin_key)],
+                &extra_signers,
+            )?;
+            println!("pool {}", addresses.pool);
+            signature
+        }
+        Command::Deposit {
+            pool,
+            amount_a,
+            amount_b,
+            min_lp,
+        } => {
+            let (_, addresses) = client.pool(&pool)?;
+            let user = UserAccounts::associated(payer, &addresses);
+            client.send(
+                &[
+                    client.create_ata(&addresses.lp_mint),
+                    instructions::deposit_liquidity(&addresses, &user, amount_a, amount_b, min_lp),
+                ],
+                &[],
+            )?
+        }
+        Command::Withdraw {
+            pool,
+            lp_amount,
+            min_a,
+            min_b,
+        } => {
+            let (_, addresses) = client.pool(&pool)?;
+            let user = UserAccounts::associated(payer, &addresses);
+            client.send(
+                &[
+                    client.create_ata(&addresses.mint_a),
+                    client.create_ata(&addresses.mint_b),
+                    instructions::withdraw_liquidity(&addresses, &user, lp_amount, min_a, min_b),
+                ],
+                &[],
+            )?
+        }
+        Command::Swap {
+            pool,
+            amount_in,
+            direction,
+            min_out,
+        } => {
+            let (_, addresses) = client.pool(&pool)?;
+            let user = UserAccounts::associated(payer, &addresses);
+            let direction = SwapDirection::from(direction);
+            let mint_out = match direction {
+                SwapDirection::AtoB => addresses.mint_b,
+                SwapDirection::BtoA => addresses.mint_a,
+            };
+            client.send(
+                &[
+                    client.create_ata(&mint_out),
+                    instructions::swap(&addresses, &user, amount_in, min_out, direction),
+                ],
+                &[],
+            )?
+        }
+        Command::Quote {
+            pool,
+            amount,
+            direction,
+            exact_out,
+        } => {
+            let (_, addresses) = client.pool(&pool)?;
+            let instruction = if exact_out {
+                instructions::quote_swap_exact_out(&addresses, amount, direction.into())
+            } else {
+                instructions::quote_swap(&addresses, amount, direction.into())
+            };
+            let quote = SwapQuote::try_from_slice(&client.simulate(instruction)?)?;
+            print_quote(&quote);
+            return Ok(());
+        }
+        Command::Pause { pool, unpause } => {
+            client.send(&[instructions::set_pause(&pool, &payer, !unpause)], &[])?
+        }
+        Command::SetAdmin {
+            pool,
+            new_admin_keypair,
+        } => {
+            let new_admin = read_keypair(&new_admin_keypair)?;
+            client.send(
+                &[instructions::set_admin(&pool, &payer, &new_admin.pubkey())],
+                &[&new_admin],
+            )?
+        }
+        Command::SetFeeRecipient {
+            pool,
+            fee_recipient,
+        } => client.send(
+            &[instructions::set_fee_recipient(
+                &pool,
+                &payer,
+                &fee_recipient,
+            )],
+            &[],
+        )?,
+        Command::WithdrawFees {
+            pool,
+            amount_a,
+            amount_b,
+        } => {
+            let (state, addresses) = client.pool(&pool)?;
+            let treasury = UserAccounts::associated(state.fee_recipient, &addresses);
+            client.send(
+                &[
+                    client.create_ata_for(&state.fee_recipient, &addresses.mint_a),
+                    client.create_ata_for(&state.fee_recipient, &addresses.mint_b),
+                    instructions::withdraw_protocol_fees(&addresses, &treasury, amount_a, amount_b),
+                ],
+                &[],
+            )?
+        }
+        Command::SetFeeDistribution { pool, recipients } => client.send(
+            &[instructions::set_fee_distribution(
+                &pool, &payer, recipients,
+            )],
+            &[],
+        )?,
+        Command::MigratePool { pool } => {
+            client.send(&[instructions::migrate_pool(&pool, &payer)], &[])?
+        }
+        Command::DistributeFees { pool } => {
+            let (_, addresses) = client.pool(&pool)?;
+            let distribution = client.fee_distribution(&pool)?;
+            let recipients: Vec<UserAccounts> = distribution
+                .recipients
+                .iter()
+                .map(|share| UserAccounts::associated(share.owner, &addresses))
+                .collect();
+            let mut batch: Vec<Instruction> = recipients
+                .iter()
+                .flat_map(|recipient| {
+                    [
+                        client.create_ata_for(&recipient.owner, &addresses.mint_a),
+                        client.create_ata_for(&recipient.owner, &addresses.mint_b),
+                    ]
+                })
+                .collect();
+            batch.push(instructions::distribute_protocol_fees(
+                &addresses,
+                &recipients,
+            ));
+            client.send(&batch, &[])?
+        }
+        Command::Pool {
+            command: PoolCommand::Show { pool },
+        } => {
+            show_pool(&client, &pool)?;
+            return Ok(());
+        }
+    };
+    println!("signature {}", signature);
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    run(Cli::parse())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_subcommands_and_global_flags() {
+        let pool = Pubkey::new_unique();
+        let cli = Cli::try_parse_from([
+            "amm-cli",
+            "swap",
+            &pool.to_string(),
+            "1000",
+            "--direction",
+            "b-to-a",
+            "--min-out",
+            "990",
+            "-u",
+            "localhost",
+        ])
+        .unwrap();
+        assert_eq!(cli.url.as_deref(), Some("localhost"));
+        match cli.command {
+            Command::Swap {
+                pool: parsed,
+                amount_in,
+                direction,
+                min_out,
+            } => {
+                assert_eq!(parsed, pool);
+                assert_eq!((amount_in, min_out), (1_000, 990));
+                assert!(SwapDirection::from(direction) == SwapDirection::BtoA);
+            }
+            _ => panic!("expected swap"),
+        }
+
+        let cli = Cli::try_parse_from(["amm-cli", "pool", "show", &pool.to_string()]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Pool {
+                command: PoolCommand::Show { .. }
+            }
+        ));
+        assert!(Cli::try_parse_from(["amm-cli", "pause", "not-a-pubkey"]).is_err());
+    }
+
+    #[test]
+    fn parses_fee_shares() {
+        let pool = Pubkey::new_unique();
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let cli = Cli::try_parse_from([
+            "amm-cli",
+            "set-fee-distribution",
+            &pool.to_string(),
+            &format!("{first}:7000"),
+            &format!("{second}:3000"),
+        ])
+        .unwrap();
+        match cli.command {
+            Command::SetFeeDistribution { recipients, .. } => assert_eq!(
+                recipients,
+                vec![
+                    FeeShare {
+                        owner: first,
+                        share_bps: 7_000,
+                    },
+                    FeeShare {
+                        owner: second,
+                        share_bps: 3_000,
+                    },
+                ]
+            ),
+            _ => panic!("expected set-fee-distribution"),
+        }
+        for bad in [first.to_string(), format!("{first}:-1"), "owner:10".into()] {
+            assert!(parse_fee_share(&bad).is_err());
+        }
+    }
+}
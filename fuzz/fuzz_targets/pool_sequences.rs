@@ -0,0 +1,14 @@
+//! Random sequences of pool instructions and direct vault transfers, checked
+//! against the pool's invariants after every step. See
+//! `amm_harness::sequence`.
+//!
+//! Run with `cargo +nightly fuzz run pool_sequences` from the repository root.
+
+#![no_main]
+
+use amm_harness::sequence::{Sequence, Step};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|steps: Vec<Step>| {
+    Sequence::new().run(&steps);
+});
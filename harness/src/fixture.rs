@@ -0,0 +1,123 @@
+//! An initialized pool with its admin, ready for tests to trade against.
+
+use amm_sdk::{instructions, PoolAddresses, UserAccounts};
+use anchor_lang::solana_program::{
+    instruction::Instruction, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+};
+
+use crate::runtime::{Outcome, Processor, Runtime, TransactionError};
+
+pub const DECIMALS: u8 = 6;
+
+pub struct PoolFixture {
+    pub runtime: Runtime,
+    pub pool: PoolAddresses,
+    /// The pool admin, with empty token accounts to collect protocol fees.
+    pub admin: UserAccounts,
+}
+
+impl Default for PoolFixture {
+    fn default() -> Self {
+        PoolFixture::new()
+    }
+}
+
+impl PoolFixture {
+    /// Creates two mints and an empty pool for them, paid for by the admin.
+    pub fn new() -> Self {
+        PoolFixture::with_programs(&[])
+    }
+
+    /// Like [`PoolFixture::new`], on a runtime that also has `programs`
+    /// deployed; see [`Runtime::with_programs`].
+    pub fn with_programs(programs: &[(Pubkey, Processor)]) -> Self {
+        let mut fixture = PoolFixture::on(Runtime::with_programs(programs));
+        let admin = fixture.admin;
+        fixture
+            .process(
+                instructions::initialize(&fixture.pool, &admin.owner, &admin.owner),
+                &admin,
+            )
+            .expect("initialize");
+        fixture
+    }
+
+    /// Creates two mints and a funded admin, but not the pool itself.
+    pub fn uninitialized() -> Self {
+        PoolFixture::on(Runtime::new())
+    }
+
+    fn on(mut runtime: Runtime) -> Self {
+        let mint_a = runtime.create_mint(DECIMALS);
+        let mint_b = runtime.create_mint(DECIMALS);
+        let pool = PoolAddresses::derive(mint_a, mint_b);
+        let admin = wallet(&mut runtime, &pool);
+        PoolFixture {
+            runtime,
+            pool,
+            admin,
+        }
+    }
+
+    /// A new wallet holding `amount_a` of A, `amount_b` of B and no LP.
+    pub fn user(&mut self, amount_a: u64, amount_b: u64) -> UserAccounts {
+        let user = wallet(&mut self.runtime, &self.pool);
+        self.runtime.mint_to(&user.token_a, amount_a);
+        self.runtime.mint_to(&user.token_b, amount_b);
+        user
+    }
+
+    /// Runs `instruction` as a transaction signed by `signer`.
+    pub fn process(
+        &mut self,
+        instruction: Instruction,
+        signer: &UserAccounts,
+    ) -> Result<Outcome, TransactionError> {
+        self.runtime
+            .process_transaction(&[instruction], &[signer.owner])
+    }
+
+    pub fn pool_state(&self) -> amm::Pool {
+        let account = self.runtime.account(&self.pool.pool).expect("pool account");
+        amm_sdk::decode_pool(&account.data).expect("pool state")
+    }
+
+    pub fn reserves(&self) -> (u64, u64) {
+        (
+            self.runtime.token_balance(&self.pool.vault_a),
+            self.runtime.token_balance(&self.pool.vault_b),
+        )
+    }
+
+    pub fn protocol_fees(&self) -> (u64, u64) {
+        (
+            self.runtime.token_balance(&self.pool.fee_vault_a),
+            self.runtime.token_balance(&self.pool.fee_vault_b),
+        )
+    }
+
+    pub fn lp_supply(&self) -> u64 {
+        self.runtime.mint_supply(&self.pool.lp_mint)
+    }
+
+    /// A, B and LP balances of `user`.
+    pub fn balances(&self, user: &UserAccounts) -> (u64, u64, u64) {
+        (
+            self.runtime.token_balance(&user.token_a),
+            self.runtime.token_balance(&user.token_b),
+            self.runtime.token_balance(&user.lp),
+        )
+    }
+}
+
+/// A funded keypair-less wallet with empty token accounts for the pool's
+/// mints. Signing is by address, so no keypair is needed.
+fn wallet(runtime: &mut Runtime, pool: &PoolAddresses) -> UserAccounts {
+    let owner = Pubkey::new_unique();
+    runtime.airdrop(&owner, 10 * LAMPORTS_PER_SOL);
+    let user = UserAccounts::associated(owner, pool);
+    runtime.create_token_account(user.token_a, &pool.mint_a, &owner);
+    runtime.create_token_account(user.token_b, &pool.mint_b, &owner);
+    runtime.create_token_account(user.lp, &pool.lp_mint, &owner);
+    user
+}
@@ -0,0 +1,21 @@
+//! Runs the amm program and SPL Token on an in-process bank, so
+//! instruction-level tests need no validator.
+//!
+//! [`Runtime`] executes transactions against that bank; [`PoolFixture`] sets
+//! up an initialized pool and funded users on top of it.
+
+mod fixture;
+mod runtime;
+pub mod sequence;
+mod tokens;
+
+pub use fixture::{PoolFixture, DECIMALS};
+pub use runtime::{Account, InstructionError, Outcome, Processor, Runtime, TransactionError};
+
+use anchor_lang::solana_program::program_error::ProgramError;
+
+/// The error an instruction fails with when a program returns custom error
+/// `code`, e.g. an `AmmError` or an Anchor `ErrorCode`.
+pub fn custom_error(code: impl Into<u32>) -> InstructionError {
+    InstructionError::Program(ProgramError::Custom(code.into()))
+}
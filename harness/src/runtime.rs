@@ -0,0 +1,405 @@
+//! Transactions run on a `solana_runtime::Bank`, the bank a validator
+//! replays blocks with, so account ownership, writability, CPI privileges,
+//! rent and compute limits are enforced by the runtime itself.
+//!
+//! SPL Token is the SBF build that ships with `solana-program-test`. The
+//! amm program runs natively through program-test's builtin shim, unless
+//! `SBF_OUT_DIR` or `BPF_OUT_DIR` names a directory holding `amm.so`, as
+//! `cargo test-sbf` arranges; then the SBF build is deployed instead. Native
+//! programs are charged only for what the runtime meters around them (CPIs,
+//! syscalls and the programs they invoke), so [`Outcome::compute_units`]
+//! reflects what a validator reports only when amm runs as SBF; see
+//! [`Runtime::amm_is_sbf`].
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Once, OnceLock, RwLock},
+};
+
+use amm_sdk::{parse_cpi_events, AmmEvent};
+use anchor_lang::solana_program::{
+    account_info::AccountInfo,
+    bpf_loader,
+    clock::{Clock, MAX_PROCESSING_AGE},
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+};
+use solana_accounts_db::transaction_results::TransactionExecutionResult;
+use solana_program_runtime::{
+    invoke_context::BuiltinFunctionWithContext, loaded_programs::LoadedProgram,
+    timings::ExecuteTimings,
+};
+use solana_program_test::{processor, programs::spl_programs, ProgramTest};
+use solana_runtime::{
+    bank::Bank, bank_forks::BankForks, genesis_utils::create_genesis_config_with_leader_ex,
+};
+use solana_sdk::{
+    account::AccountSharedData, fee_calculator::FeeRateGovernor, genesis_config::ClusterType,
+    instruction::InstructionError as RuntimeError, native_token::sol_to_lamports,
+    signature::Signature, transaction::Transaction,
+};
+
+pub use solana_sdk::account::Account;
+
+/// A program entrypoint, such as `amm::entry`.
+pub type Processor = for<'info> fn(&Pubkey, &'info [AccountInfo<'info>], &[u8]) -> ProgramResult;
+
+/// Why an instruction failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InstructionError {
+    /// The program returned an error.
+    Program(ProgramError),
+    /// A signer meta the transaction was not signed for. Signing is by
+    /// address, so this is checked before the transaction is built; the
+    /// runtime never sees it.
+    MissingSignature(Pubkey),
+    /// The runtime rejected what the program did, e.g. writing to an account
+    /// it does not own.
+    Runtime(RuntimeError),
+    /// The transaction was rejected before any instruction ran.
+    Transaction(solana_sdk::transaction::TransactionError),
+}
+
+impl fmt::Display for InstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstructionError::Program(error) => write!(f, "{error}"),
+            InstructionError::MissingSignature(key) => write!(f, "{key} did not sign"),
+            InstructionError::Runtime(error) => write!(f, "{error}"),
+            InstructionError::Transaction(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl From<ProgramError> for InstructionError {
+    fn from(error: ProgramError) -> Self {
+        InstructionError::Program(error)
+    }
+}
+
+impl From<RuntimeError> for InstructionError {
+    fn from(error: RuntimeError) -> Self {
+        match ProgramError::try_from(error.clone()) {
+            Ok(error) => InstructionError::Program(error),
+            Err(_) => InstructionError::Runtime(error),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TransactionError {
+    /// Index of the instruction that failed, or 0 if the transaction was
+    /// rejected as a whole.
+    pub index: usize,
+    pub error: InstructionError,
+    pub logs: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub logs: Vec<String>,
+    /// Return data set by the last program invoked.
+    pub return_data: Option<(Pubkey, Vec<u8>)>,
+    /// Compute units consumed, as the bank reports them; see the module
+    /// docs.
+    pub compute_units: u64,
+    /// Every CPI made, in the order it was made, as the inner instructions
+    /// of a confirmed transaction. Like those, account metas carry the
+    /// transaction's signer and writable flags, not the CPI's.
+    pub inner_instructions: Vec<Instruction>,
+}
+
+impl Outcome {
+    /// The amm events emitted, decoded from the event CPIs among the inner
+    /// instructions.
+    pub fn events(&self) -> Vec<AmmEvent> {
+        parse_cpi_events(
+            self.inner_instructions
+                .iter()
+                .map(|instruction| (&instruction.program_id, &instruction.data[..])),
+        )
+    }
+}
+
+pub struct Runtime {
+    bank_forks: Arc<RwLock<BankForks>>,
+    /// Pays the fees, so the signers' balances only move by what the
+    /// instructions do.
+    payer: Pubkey,
+    amm_is_sbf: bool,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Runtime::new()
+    }
+}
+
+impl Runtime {
+    /// A bank with the builtin programs, SPL Token and amm deployed.
+    pub fn new() -> Self {
+        Runtime::with_programs(&[])
+    }
+
+    /// Like [`Runtime::new`], with each of `programs` deployed natively at
+    /// its address. Programs can't be added to a running bank.
+    pub fn with_programs(programs: &[(Pubkey, Processor)]) -> Self {
+        install_syscall_stubs();
+        let payer = Pubkey::new_unique();
+        let rent = Rent::default();
+        let genesis_config = create_genesis_config_with_leader_ex(
+            sol_to_lamports(1_000_000.0),
+            &payer,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            rent.minimum_balance(solana_sdk::vote::state::VoteState::size_of())
+                + sol_to_lamports(1_000_000.0),
+            42,
+            FeeRateGovernor::default(),
+            rent,
+            ClusterType::Development,
+            vec![],
+        );
+        let mut bank = Bank::new_for_tests(&genesis_config);
+        for (program_id, account) in spl_programs(&rent) {
+            bank.store_account(&program_id, &account);
+        }
+
+        let amm_so = std::env::var_os("SBF_OUT_DIR")
+            .or_else(|| std::env::var_os("BPF_OUT_DIR"))
+            .map(|_| {
+                let path = solana_program_test::find_file("amm.so")
+                    .expect("SBF_OUT_DIR is set, but amm.so is not in it");
+                std::fs::read(&path).unwrap_or_else(|err| panic!("{}: {err}", path.display()))
+            });
+        let amm_is_sbf = amm_so.is_some();
+        match amm_so {
+            Some(data) => bank.store_account(
+                &amm::ID,
+                &AccountSharedData::from(Account {
+                    lamports: rent.minimum_balance(data.len()),
+                    data,
+                    owner: bpf_loader::ID,
+                    executable: true,
+                    rent_epoch: 0,
+                }),
+            ),
+            None => add_native_program(&mut bank, amm::ID, amm::entry),
+        }
+        for &(program_id, processor) in programs {
+            add_native_program(&mut bank, program_id, processor);
+        }
+
+        // Start past slot 0, so slot arithmetic in tests has room.
+        let bank_forks = BankForks::new_rw_arc(bank);
+        let parent = bank_forks.read().unwrap().working_bank();
+        parent.fill_bank_with_ticks_for_tests();
+        let bank = Bank::new_from_parent(parent, &Pubkey::default(), 1);
+        bank_forks.write().unwrap().insert(bank);
+        Runtime {
+            bank_forks,
+            payer,
+            amm_is_sbf,
+        }
+    }
+
+    /// Whether amm runs as its SBF build, so compute is fully metered.
+    pub fn amm_is_sbf(&self) -> bool {
+        self.amm_is_sbf
+    }
+
+    fn bank(&self) -> Arc<Bank> {
+        self.bank_forks.read().unwrap().working_bank()
+    }
+
+    pub fn account(&self, address: &Pubkey) -> Option<Account> {
+        self.bank().get_account(address).map(Account::from)
+    }
+
+    /// Overwrites `address` in the bank, bypassing the runtime's checks.
+    pub fn set_account(&mut self, address: Pubkey, account: Account) {
+        self.bank()
+            .store_account(&address, &AccountSharedData::from(account));
+    }
+
+    /// Credits `lamports` to `address`, creating it as a system account if
+    /// needed.
+    pub fn airdrop(&mut self, address: &Pubkey, lamports: u64) {
+        let mut account = self
+            .account(address)
+            .unwrap_or_else(|| Account::new(0, 0, &system_program::ID));
+        account.lamports += lamports;
+        self.set_account(*address, account);
+    }
+
+    pub fn rent(&self) -> Rent {
+        self.bank().rent_collector().rent
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.bank().clock()
+    }
+
+    /// Runs `instructions` in order as one transaction signed by `signers`.
+    /// On failure no account changes are kept.
+    pub fn process_transaction(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[Pubkey],
+    ) -> Result<Outcome, TransactionError> {
+        for (index, instruction) in instructions.iter().enumerate() {
+            if let Some(meta) = instruction
+                .accounts
+                .iter()
+                .find(|meta| meta.is_signer && !signers.contains(&meta.pubkey))
+            {
+                return Err(TransactionError {
+                    index,
+                    error: InstructionError::MissingSignature(meta.pubkey),
+                    logs: Vec::new(),
+                });
+            }
+        }
+
+        let bank = self.bank();
+        // A fresh blockhash per transaction, so repeating one isn't rejected
+        // as already processed.
+        bank.register_unique_recent_blockhash_for_test();
+        let message =
+            Message::new_with_blockhash(instructions, Some(&self.payer), &bank.last_blockhash());
+        // Signatures are not verified, only counted.
+        let transaction = Transaction {
+            signatures: (0..message.header.num_required_signatures)
+                .map(|_| Signature::new_unique())
+                .collect(),
+            message,
+        };
+        let rejected = |error| TransactionError {
+            index: 0,
+            error: InstructionError::Transaction(error),
+            logs: Vec::new(),
+        };
+
+        let batch = bank
+            .prepare_entry_batch(vec![transaction.clone().into()])
+            .map_err(rejected)?;
+        let (mut results, _) = bank.load_execute_and_commit_transactions(
+            &batch,
+            MAX_PROCESSING_AGE,
+            false, // collect_balances
+            true,  // enable_cpi_recording
+            true,  // enable_log_recording
+            true,  // enable_return_data_recording
+            &mut ExecuteTimings::default(),
+            None,
+        );
+        let details = match results.execution_results.remove(0) {
+            TransactionExecutionResult::Executed { details, .. } => details,
+            TransactionExecutionResult::NotExecuted(error) => return Err(rejected(error)),
+        };
+        let logs = details.log_messages.unwrap_or_default();
+        match details.status {
+            Ok(()) => Ok(Outcome {
+                logs,
+                return_data: details
+                    .return_data
+                    .map(|return_data| (return_data.program_id, return_data.data)),
+                compute_units: details.executed_units,
+                inner_instructions: details
+                    .inner_instructions
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flatten()
+                    .map(|inner| decompile(&transaction.message, &inner.instruction))
+                    .collect(),
+            }),
+            Err(solana_sdk::transaction::TransactionError::InstructionError(index, error)) => {
+                Err(TransactionError {
+                    index: index.into(),
+                    error: error.into(),
+                    logs,
+                })
+            }
+            Err(error) => Err(TransactionError {
+                logs,
+                ..rejected(error)
+            }),
+        }
+    }
+}
+
+fn decompile(
+    message: &Message,
+    instruction: &solana_sdk::instruction::CompiledInstruction,
+) -> Instruction {
+    let key = |index: u8| message.account_keys[usize::from(index)];
+    Instruction {
+        program_id: key(instruction.program_id_index),
+        accounts: instruction
+            .accounts
+            .iter()
+            .map(|&index| AccountMeta {
+                pubkey: key(index),
+                is_signer: message.is_signer(index.into()),
+                is_writable: message.is_writable(index.into()),
+            })
+            .collect(),
+        data: instruction.data.clone(),
+    }
+}
+
+/// Processors of the natively deployed programs, by address. Builtins are
+/// plain function pointers, so they all share [`dispatch`], which looks the
+/// program up here.
+fn processors() -> &'static RwLock<HashMap<Pubkey, Processor>> {
+    static PROCESSORS: OnceLock<RwLock<HashMap<Pubkey, Processor>>> = OnceLock::new();
+    PROCESSORS.get_or_init(Default::default)
+}
+
+fn add_native_program(bank: &mut Bank, program_id: Pubkey, processor: Processor) {
+    processors().write().unwrap().insert(program_id, processor);
+    let builtin: Option<BuiltinFunctionWithContext> = processor!(dispatch);
+    let name = program_id.to_string();
+    bank.add_builtin(
+        program_id,
+        name.clone(),
+        LoadedProgram::new_builtin(0, name.len(), builtin.unwrap()),
+    );
+}
+
+fn dispatch<'a, 'info>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'info>],
+    data: &[u8],
+) -> ProgramResult {
+    let processor = processors().read().unwrap()[program_id];
+    // SAFETY: program-test builds these `AccountInfo`s over a parameter
+    // buffer that outlives the call and drops them right after it returns,
+    // so nothing observes the shortened lifetime once `'a` ends.
+    let accounts =
+        unsafe { std::mem::transmute::<&'a [AccountInfo<'info>], &'a [AccountInfo<'a>]>(accounts) };
+    processor(program_id, accounts, data)
+}
+
+/// Native programs make their syscalls, CPIs included, through stubs that
+/// program-test installs the first time it sets up a bank of its own.
+fn install_syscall_stubs() {
+    static STUBS: Once = Once::new();
+    STUBS.call_once(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(ProgramTest::default().start());
+        // `ProgramTest::default` turns on debug logging of every program
+        // invocation; keep `RUST_LOG` in charge.
+        solana_logger::setup_with_default("warn");
+    });
+}
@@ -0,0 +1,473 @@
+//! Random instruction sequences against one pool, checked step by step.
+//!
+//! Every step runs through the program and through a [`PoolSimulator`]
+//! model, which must agree on success, on the error, and on the resulting
+//! balances and trading totals. After each successful step the pool's invariants are checked:
+//!
+//! - no LP can withdraw more than their share of the reserves,
+//! - a swap pays at most `protocol_fee_bps` of its input to the protocol,
+//! - `k / lp_supply^2` never decreases.
+//!
+//! Violations panic, which is what the `pool_sequences` fuzz target looks
+//! for.
+
+use amm::{math::widening_mul, PoolStats, SwapDirection, WithdrawQuote};
+use amm_sdk::{instructions, PoolSimulator, UserAccounts};
+use anchor_lang::{
+    prelude::*,
+    solana_program::{instruction::Instruction, program_error::ProgramError},
+};
+use anchor_spl::token::spl_token;
+use arbitrary::Arbitrary;
+
+use crate::{InstructionError, PoolFixture};
+
+/// Wallets trading against the pool, besides the admin.
+pub const USERS: usize = 3;
+/// Largest amount a single deposit, swap or donation moves. Users are
+/// minted whatever they lack before each step, so they never run dry.
+pub const MAX_AMOUNT: u64 = 1 << 40;
+
+/// How much of an upper bound a step uses: `MAX_AMOUNT` for tokens users
+/// pay in, the current balance for LP and protocol fees.
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub enum Amount {
+    All,
+    /// Capped at the bound.
+    Exact(u64),
+    /// Log-uniform up to the bound, so that dust and whale-sized amounts
+    /// both come up.
+    Scaled(u64),
+}
+
+impl Amount {
+    fn of(self, max: u64) -> u64 {
+        match self {
+            Amount::All => max,
+            Amount::Exact(amount) => amount.min(max),
+            Amount::Scaled(raw) => (raw >> (raw % 64)) % (max + 1),
+        }
+    }
+}
+
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub enum Step {
+    Initialize,
+    Deposit {
+        user: u8,
+        amount_a: Amount,
+        amount_b: Amount,
+    },
+    Withdraw {
+        user: u8,
+        lp_amount: Amount,
+    },
+    Swap {
+        user: u8,
+        amount_in: Amount,
+        a_to_b: bool,
+        /// Through `swap_v2` rather than `swap`.
+        v2: bool,
+    },
+    WithdrawProtocolFees {
+        amount_a: Amount,
+        amount_b: Amount,
+    },
+    /// A plain token transfer into a vault, bypassing the program.
+    Donate {
+        user: u8,
+        amount: Amount,
+        to_vault_a: bool,
+    },
+}
+
+/// Balances the pool controls, and its trading totals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PoolState {
+    reserve_a: u64,
+    reserve_b: u64,
+    lp_supply: u64,
+    protocol_fees_a: u64,
+    protocol_fees_b: u64,
+    stats: PoolStats,
+}
+
+impl From<&PoolSimulator> for PoolState {
+    fn from(model: &PoolSimulator) -> Self {
+        PoolState {
+            reserve_a: model.reserve_a,
+            reserve_b: model.reserve_b,
+            lp_supply: model.lp_supply,
+            protocol_fees_a: model.protocol_fees_a,
+            protocol_fees_b: model.protocol_fees_b,
+            stats: model.stats,
+        }
+    }
+}
+
+/// What the model says a step should do.
+enum Expected {
+    Success,
+    Error(ProgramError),
+    /// The pool does not exist yet, or already does for `Initialize`.
+    AnyError,
+}
+
+pub struct Sequence {
+    fixture: PoolFixture,
+    users: Vec<UserAccounts>,
+    /// Set once the pool is initialized.
+    model: Option<PoolSimulator>,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence::new()
+    }
+}
+
+impl Sequence {
+    /// Funded users and an admin, before the pool is initialized.
+    pub fn new() -> Self {
+        let mut fixture = PoolFixture::uninitialized();
+        let users = (0..USERS).map(|_| fixture.user(0, 0)).collect();
+        Sequence {
+            fixture,
+            users,
+            model: None,
+        }
+    }
+
+    pub fn run(&mut self, steps: &[Step]) {
+        for (index, step) in steps.iter().enumerate() {
+            self.apply(index, *step);
+        }
+    }
+
+    /// Applies `step`, the `index`th of its sequence, and panics if the
+    /// program disagrees with the model or breaks an invariant.
+    pub fn apply(&mut self, index: usize, step: Step) {
+        let before = self.pool_state();
+        let mut model = self.model;
+        let (instruction, signer, expected) = self.plan(step, &mut model);
+        let user_before = self.user_balances(step);
+
+        let result = self
+            .fixture
+            .runtime
+            .process_transaction(&[instruction], &[signer]);
+        match (expected, result) {
+            (Expected::Success, Ok(_)) => {}
+            (Expected::Error(expected), Err(err))
+                if err.error == InstructionError::Program(expected.clone()) => {}
+            (Expected::AnyError, Err(_)) => {}
+            (expected, result) => panic!(
+                "step {index} {step:?}: expected {}, got {result:#?}",
+                match expected {
+                    Expected::Success => "success".to_string(),
+                    Expected::Error(error) => format!("{error:?}"),
+                    Expected::AnyError => "an error".to_string(),
+                }
+            ),
+        }
+        if let (Step::Initialize, None) = (step, model) {
+            let pool = self.fixture.pool_state();
+            model = Some(PoolSimulator::from_pool(&pool, 0, 0, 0, 0, 0));
+        }
+        self.model = model;
+
+        let after = self.pool_state();
+        let Some(model) = &self.model else {
+            return;
+        };
+        assert_eq!(
+            after,
+            Some(PoolState::from(model)),
+            "step {index} {step:?}: pool diverged from the model"
+        );
+        self.check_invariants(index, step, before, after.unwrap(), user_before);
+    }
+
+    fn user(&self, index: u8) -> UserAccounts {
+        self.users[index as usize % USERS]
+    }
+
+    /// Builds the instruction for `step` and applies it to `model`.
+    fn plan(
+        &mut self,
+        step: Step,
+        model: &mut Option<PoolSimulator>,
+    ) -> (Instruction, Pubkey, Expected) {
+        let pool = self.fixture.pool;
+        let admin = self.fixture.admin;
+
+        if let Step::Initialize = step {
+            let instruction = instructions::initialize(&pool, &admin.owner, &admin.owner);
+            // The model is created from the new pool once it exists.
+            let expected = match model {
+                Some(_) => Expected::AnyError,
+                None => Expected::Success,
+            };
+            return (instruction, admin.owner, expected);
+        }
+
+        let (instruction, signer, outcome) = match step {
+            Step::Initialize => unreachable!(),
+            Step::Deposit {
+                user,
+                amount_a,
+                amount_b,
+            } => {
+                let user = self.user(user);
+                let amount_a = self.fund(&user.token_a, amount_a);
+                let amount_b = self.fund(&user.token_b, amount_b);
+                (
+                    instructions::deposit_liquidity(&pool, &user, amount_a, amount_b, 0),
+                    user.owner,
+                    model
+                        .as_mut()
+                        .map(|model| model.deposit_liquidity(amount_a, amount_b, 0).map(drop)),
+                )
+            }
+            Step::Withdraw { user, lp_amount } => {
+                let user = self.user(user);
+                let lp_amount = lp_amount.of(self.fixture.runtime.token_balance(&user.lp));
+                (
+                    instructions::withdraw_liquidity(&pool, &user, lp_amount, 0, 0),
+                    user.owner,
+                    model
+                        .as_mut()
+                        .map(|model| model.withdraw_liquidity(lp_amount, 0, 0).map(drop)),
+                )
+            }
+            Step::Swap {
+                user,
+                amount_in,
+                a_to_b,
+                v2,
+            } => {
+                let user = self.user(user);
+                let (direction, source) = if a_to_b {
+                    (SwapDirection::AtoB, user.token_a)
+                } else {
+                    (SwapDirection::BtoA, user.token_b)
+                };
+                let amount_in = self.fund(&source, amount_in);
+                let swap = if v2 {
+                    instructions::swap_v2
+                } else {
+                    instructions::swap
+                };
+                (
+                    swap(&pool, &user, amount_in, 0, direction),
+                    user.owner,
+                    model
+                        .as_mut()
+                        .map(|model| model.swap(amount_in, 0, direction).map(drop)),
+                )
+            }
+            Step::WithdrawProtocolFees { amount_a, amount_b } => {
+                let model_fees = model
+                    .as_ref()
+                    .map_or((0, 0), |m| (m.protocol_fees_a, m.protocol_fees_b));
+                let amount_a = amount_a.of(model_fees.0);
+                let amount_b = amount_b.of(model_fees.1);
+                (
+                    instructions::withdraw_protocol_fees(&pool, &admin, amount_a, amount_b),
+                    admin.owner,
+                    model.as_mut().map(|model| {
+                        require!(amount_a > 0 || amount_b > 0, amm::AmmError::InvalidAmount);
+                        model.protocol_fees_a -= amount_a;
+                        model.protocol_fees_b -= amount_b;
+                        Ok(())
+                    }),
+                )
+            }
+            Step::Donate {
+                user,
+                amount,
+                to_vault_a,
+            } => {
+                let user = self.user(user);
+                let (source, vault) = if to_vault_a {
+                    (user.token_a, pool.vault_a)
+                } else {
+                    (user.token_b, pool.vault_b)
+                };
+                let amount = self.fund(&source, amount);
+                let transfer = spl_token::instruction::transfer(
+                    &spl_token::ID,
+                    &source,
+                    &vault,
+                    &user.owner,
+                    &[],
+                    amount,
+                )
+                .unwrap();
+                (
+                    transfer,
+                    user.owner,
+                    model.as_mut().map(|model| {
+                        if to_vault_a {
+                            model.reserve_a += amount;
+                        } else {
+                            model.reserve_b += amount;
+                        }
+                        Ok(())
+                    }),
+                )
+            }
+        };
+        let expected = match outcome {
+            None => Expected::AnyError,
+            Some(Ok(())) => Expected::Success,
+            Some(Err(error)) => Expected::Error(error.into()),
+        };
+        (instruction, signer, expected)
+    }
+
+    /// Draws an amount up to `MAX_AMOUNT` and mints whatever `account`
+    /// lacks to pay it.
+    fn fund(&mut self, account: &Pubkey, amount: Amount) -> u64 {
+        let amount = amount.of(MAX_AMOUNT);
+        let balance = self.fixture.runtime.token_balance(account);
+        if balance < amount {
+            self.fixture.runtime.mint_to(account, amount - balance);
+        }
+        amount
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        self.model.as_ref()?;
+        let (reserve_a, reserve_b) = self.fixture.reserves();
+        let (protocol_fees_a, protocol_fees_b) = self.fixture.protocol_fees();
+        Some(PoolState {
+            reserve_a,
+            reserve_b,
+            lp_supply: self.fixture.lp_supply(),
+            protocol_fees_a,
+            protocol_fees_b,
+            stats: self.fixture.pool_state().stats,
+        })
+    }
+
+    /// A, B and LP balances of the user `step` acts for.
+    fn user_balances(&self, step: Step) -> Option<(u64, u64, u64)> {
+        let user = match step {
+            Step::Deposit { user, .. }
+            | Step::Withdraw { user, .. }
+            | Step::Swap { user, .. }
+            | Step::Donate { user, .. } => self.user(user),
+            Step::Initialize | Step::WithdrawProtocolFees { .. } => return None,
+        };
+        Some(self.fixture.balances(&user))
+    }
+
+    fn check_invariants(
+        &mut self,
+        index: usize,
+        step: Step,
+        before: Option<PoolState>,
+        after: PoolState,
+        user_before: Option<(u64, u64, u64)>,
+    ) {
+        let context = format!("step {index} {step:?}");
+
+        let lp_held: u64 = self
+            .users
+            .iter()
+            .map(|user| self.fixture.runtime.token_balance(&user.lp))
+            .sum();
+        assert_eq!(lp_held, after.lp_supply, "{context}: LP supply mismatch");
+
+        let Some(before) = before else {
+            return;
+        };
+
+        // k per LP share: after.k / after.lp^2 >= before.k / before.lp^2.
+        if before.lp_supply > 0 && after.lp_supply > 0 {
+            let k_before = before.reserve_a as u128 * before.reserve_b as u128;
+            let k_after = after.reserve_a as u128 * after.reserve_b as u128;
+            let lp_before = before.lp_supply as u128 * before.lp_supply as u128;
+            let lp_after = after.lp_supply as u128 * after.lp_supply as u128;
+            assert!(
+                widening_mul(k_after, lp_before) >= widening_mul(k_before, lp_after),
+                "{context}: k per LP share decreased from {before:?} to {after:?}"
+            );
+        }
+
+        let user_after = self.user_balances(step);
+        match (step, user_before, user_after) {
+            (Step::Swap { a_to_b, .. }, Some(user_before), Some(user_after)) => {
+                let (amount_in, fee_in, fee_other) = if a_to_b {
+                    (
+                        user_before.0 - user_after.0,
+                        after.protocol_fees_a - before.protocol_fees_a,
+                        after.protocol_fees_b - before.protocol_fees_b,
+                    )
+                } else {
+                    (
+                        user_before.1 - user_after.1,
+                        after.protocol_fees_b - before.protocol_fees_b,
+                        after.protocol_fees_a - before.protocol_fees_a,
+                    )
+                };
+                let protocol_fee_bps = self.model.unwrap().protocol_fee_bps as u128;
+                assert!(
+                    fee_in as u128 * 10_000 <= amount_in as u128 * protocol_fee_bps,
+                    "{context}: protocol fee {fee_in} on input {amount_in}"
+                );
+                assert_eq!(fee_other, 0, "{context}: fee charged on the output side");
+            }
+            (Step::Withdraw { .. }, Some(user_before), Some(user_after)) => {
+                let burned = (user_before.2 - user_after.2) as u128;
+                let received_a = (user_after.0 - user_before.0) as u128;
+                let received_b = (user_after.1 - user_before.1) as u128;
+                let supply = before.lp_supply as u128;
+                assert!(
+                    received_a * supply <= burned * before.reserve_a as u128
+                        && received_b * supply <= burned * before.reserve_b as u128,
+                    "{context}: withdrew ({received_a}, {received_b}) for {burned} LP from {before:?}"
+                );
+            }
+            _ => {}
+        }
+
+        // What every LP could take out right now, per the program's own
+        // quote, stays within their share and, in sum, within the reserves.
+        let mut claimed = (0u128, 0u128);
+        for user in self.users.clone() {
+            let lp_amount = self.fixture.runtime.token_balance(&user.lp);
+            if lp_amount == 0 {
+                continue;
+            }
+            let quote = self.quote_withdraw(lp_amount);
+            let supply = after.lp_supply as u128;
+            assert!(
+                quote.amount_a as u128 * supply <= lp_amount as u128 * after.reserve_a as u128
+                    && quote.amount_b as u128 * supply
+                        <= lp_amount as u128 * after.reserve_b as u128,
+                "{context}: {lp_amount} LP quoted {quote:?} from {after:?}"
+            );
+            claimed.0 += quote.amount_a as u128;
+            claimed.1 += quote.amount_b as u128;
+        }
+        assert!(
+            claimed.0 <= after.reserve_a as u128 && claimed.1 <= after.reserve_b as u128,
+            "{context}: LPs can claim {claimed:?} from {after:?}"
+        );
+    }
+
+    fn quote_withdraw(&mut self, lp_amount: u64) -> WithdrawQuote {
+        let admin = self.fixture.admin;
+        let outcome = self
+            .fixture
+            .process(
+                instructions::quote_withdraw(&self.fixture.pool, lp_amount),
+                &admin,
+            )
+            .expect("quote_withdraw");
+        let (_, data) = outcome.return_data.expect("quote return data");
+        WithdrawQuote::deserialize(&mut data.as_slice()).expect("withdraw quote")
+    }
+}
@@ -0,0 +1,98 @@
+//! SPL Token accounts written straight into the runtime, for setting up
+//! balances without going through instructions.
+
+use anchor_lang::solana_program::{
+    program_option::COption,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+};
+use anchor_spl::{
+    associated_token::get_associated_token_address,
+    token::spl_token::{
+        self,
+        state::{Account as TokenAccount, AccountState, Mint},
+    },
+};
+
+use crate::runtime::{Account, Runtime};
+
+impl Runtime {
+    /// Creates a mint with no mint authority; supply only grows through
+    /// [`Runtime::mint_to`].
+    pub fn create_mint(&mut self, decimals: u8) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let mint = Mint {
+            mint_authority: COption::None,
+            supply: 0,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        self.set_packed(address, mint);
+        address
+    }
+
+    pub fn create_token_account(&mut self, address: Pubkey, mint: &Pubkey, owner: &Pubkey) {
+        let account = TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        };
+        self.set_packed(address, account);
+    }
+
+    /// Creates `owner`'s associated token account for `mint` and returns its
+    /// address.
+    pub fn create_associated_token_account(&mut self, owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+        let address = get_associated_token_address(owner, mint);
+        self.create_token_account(address, mint, owner);
+        address
+    }
+
+    /// Credits `amount` to a token account and adds it to its mint's supply.
+    pub fn mint_to(&mut self, token_account: &Pubkey, amount: u64) {
+        let mut account: TokenAccount = self.unpack(token_account);
+        let mut mint: Mint = self.unpack(&account.mint);
+        account.amount += amount;
+        mint.supply += amount;
+        self.set_packed(account.mint, mint);
+        self.set_packed(*token_account, account);
+    }
+
+    pub fn token_balance(&self, token_account: &Pubkey) -> u64 {
+        self.unpack::<TokenAccount>(token_account).amount
+    }
+
+    pub fn mint_supply(&self, mint: &Pubkey) -> u64 {
+        self.unpack::<Mint>(mint).supply
+    }
+
+    /// Unpacks an SPL Token account. Panics if `address` does not hold a `T`.
+    pub fn unpack<T: Pack + IsInitialized>(&self, address: &Pubkey) -> T {
+        let account = self
+            .account(address)
+            .unwrap_or_else(|| panic!("{address} does not exist"));
+        assert_eq!(
+            account.owner,
+            spl_token::ID,
+            "{address} is not a token account"
+        );
+        T::unpack(&account.data).unwrap_or_else(|err| panic!("unpacking {address}: {err}"))
+    }
+
+    fn set_packed<T: Pack>(&mut self, address: Pubkey, state: T) {
+        let mut data = vec![0; T::LEN];
+        T::pack(state, &mut data).unwrap();
+        self.set_account(
+            address,
+            Account {
+                lamports: self.rent().minimum_balance(T::LEN),
+                data,
+                owner: spl_token::ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Compute benchmarks for the instructions that end up in routes, checked
+//! against `compute-budget.toml`.
+//!
+//! The units are what the harness's bank reports with amm's SBF build
+//! deployed, i.e. what a validator would charge. Natively built, amm's own
+//! execution isn't metered, so these tests are ignored unless asked for and
+//! need `amm.so`, found through an absolute `SBF_OUT_DIR`:
+//!
+//! ```text
+//! anchor build
+//! SBF_OUT_DIR=$PWD/target/deploy cargo test -p amm-harness --test compute_budget -- --include-ignored
+//! ```
+//!
+//! Add `--nocapture` to see the table, and set `UPDATE_COMPUTE_BUDGET=1` to
+//! rewrite the budget file from the current measurements.
+
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use amm::{FeeShare, ReferralFeeMode, SwapDirection};
+use amm_harness::{Outcome, PoolFixture};
+use amm_sdk::{instructions, UserAccounts};
+use anchor_lang::solana_program::instruction::Instruction;
+
+const RESERVE_A: u64 = 1_000_000;
+const RESERVE_B: u64 = 4_000_000;
+
+const HEADER: &str = "\
+# Compute units each benchmarked instruction may use, measured with amm's
+# SBF build (amm.so) deployed in the amm-harness bank.
+#
+# Checked by the ignored tests in harness/tests/compute_budget.rs, which need
+# SBF_OUT_DIR; see that file for how to run them. To accept new
+# measurements, rerun them with UPDATE_COMPUTE_BUDGET=1.
+#
+# [swap_v2_savings] is how many units swap_v2 saves over swap on the same
+# trade; it may only grow.
+";
+
+/// Budget file table holding how many units `swap_v2` saves over `swap` on
+/// the same trade, which may only grow.
+const SAVINGS: &str = "swap_v2_savings";
+
+fn budget_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("compute-budget.toml")
+}
+
+/// Runs `instruction` signed by `signer` and returns the units it used.
+fn measure(fixture: &mut PoolFixture, instruction: Instruction, signer: &UserAccounts) -> u64 {
+    run(fixture, instruction, signer).compute_units
+}
+
+fn run(fixture: &mut PoolFixture, instruction: Instruction, signer: &UserAccounts) -> Outcome {
+    assert!(
+        fixture.runtime.amm_is_sbf(),
+        "compute is only metered with amm.so deployed; set SBF_OUT_DIR to the directory holding it"
+    );
+    fixture
+        .process(instruction, signer)
+        .unwrap_or_else(|err| panic!("benchmark failed: {err:#?}"))
+}
+
+/// A pool holding `RESERVE_A`/`RESERVE_B` and a trader with funds to spare.
+fn funded_pool() -> (PoolFixture, UserAccounts) {
+    let mut fixture = PoolFixture::new();
+    let provider = fixture.user(RESERVE_A, RESERVE_B);
+    fixture
+        .process(
+            instructions::deposit_liquidity(&fixture.pool, &provider, RESERVE_A, RESERVE_B, 0),
+            &provider,
+        )
+        .expect("seed deposit");
+    let trader = fixture.user(RESERVE_A, RESERVE_B);
+    (fixture, trader)
+}
+
+/// A funded pool whose fee vaults hold the protocol fees of a swap each way.
+fn pool_with_fees() -> PoolFixture {
+    let (mut fixture, trader) = funded_pool();
+    for (amount_in, direction) in [
+        (200_000, SwapDirection::AtoB),
+        (800_000, SwapDirection::BtoA),
+    ] {
+        fixture
+            .process(
+                instructions::swap(&fixture.pool, &trader, amount_in, 0, direction),
+                &trader,
+            )
+            .expect("fee-generating swap");
+    }
+    fixture
+}
+
+fn measurements() -> BTreeMap<&'static str, u64> {
+    let mut units = BTreeMap::new();
+
+    let mut fixture = PoolFixture::new();
+    let provider = fixture.user(RESERVE_A, RESERVE_B);
+    let deposit =
+        instructions::deposit_liquidity(&fixture.pool, &provider, RESERVE_A, RESERVE_B, 0);
+    units.insert("first_deposit", measure(&mut fixture, deposit, &provider));
+
+    // Twice the B the ratio asks for: only the proportional part is taken.
+    let (mut fixture, trader) = funded_pool();
+    let deposit = instructions::deposit_liquidity(&fixture.pool, &trader, 10_000, 80_000, 0);
+    units.insert(
+        "imbalanced_deposit",
+        measure(&mut fixture, deposit, &trader),
+    );
+
+    for (name, amount_in, direction) in [
+        ("swap_a_to_b", 10_000, SwapDirection::AtoB),
+        ("swap_b_to_a", 40_000, SwapDirection::BtoA),
+    ] {
+        let (mut fixture, trader) = funded_pool();
+        let swap = instructions::swap(&fixture.pool, &trader, amount_in, 0, direction);
+        units.insert(name, measure(&mut fixture, swap, &trader));
+    }
+    // Paying the referrer adds a transfer to the fee vault's.
+    let (mut fixture, trader) = funded_pool();
+    let admin = fixture.admin;
+    fixture
+        .process(
+            instructions::set_referral_fee(
+                &fixture.pool.pool,
+                &admin.owner,
+                2,
+                ReferralFeeMode::FromProtocolFee,
+            ),
+            &admin,
+        )
+        .expect("allow referrals");
+    let referrer = fixture.user(0, 0);
+    let swap = instructions::swap_with_referrer(
+        &fixture.pool,
+        &trader,
+        10_000,
+        0,
+        SwapDirection::AtoB,
+        &referrer.token_a,
+        2,
+    );
+    units.insert("swap_with_referrer", measure(&mut fixture, swap, &trader));
+    for (name, amount_in, direction) in [
+        ("swap_v2_a_to_b", 10_000, SwapDirection::AtoB),
+        ("swap_v2_b_to_a", 40_000, SwapDirection::BtoA),
+    ] {
+        let (mut fixture, trader) = funded_pool();
+        let swap = instructions::swap_v2(&fixture.pool, &trader, amount_in, 0, direction);
+        units.insert(name, measure(&mut fixture, swap, &trader));
+    }
+
+    let mut fixture = pool_with_fees();
+    let (fees_a, fees_b) = fixture.protocol_fees();
+    let admin = fixture.admin;
+    let withdraw = instructions::withdraw_protocol_fees(&fixture.pool, &admin, fees_a, fees_b);
+    units.insert(
+        "withdraw_protocol_fees",
+        measure(&mut fixture, withdraw, &admin),
+    );
+
+    // Each recipient adds two transfers and an event.
+    let mut fixture = pool_with_fees();
+    let admin = fixture.admin;
+    let recipients = [fixture.user(0, 0), fixture.user(0, 0)];
+    fixture
+        .process(
+            instructions::set_fee_distribution(
+                &fixture.pool.pool,
+                &admin.owner,
+                recipients
+                    .iter()
+                    .map(|recipient| FeeShare {
+                        owner: recipient.owner,
+                        share_bps: 5_000,
+                    })
+                    .collect(),
+            ),
+            &admin,
+        )
+        .expect("set fee distribution");
+    let distribute = instructions::distribute_protocol_fees(&fixture.pool, &recipients);
+    units.insert(
+        "distribute_protocol_fees_to_two",
+        measure(&mut fixture, distribute, &admin),
+    );
+
+    units
+}
+
+/// Units `swap_v2` saves over `swap` in each direction, from the benchmarks
+/// of the same trade through both.
+fn swap_v2_savings(measured: &BTreeMap<&str, u64>) -> BTreeMap<&'static str, i64> {
+    [
+        ("a_to_b", "swap_a_to_b", "swap_v2_a_to_b"),
+        ("b_to_a", "swap_b_to_a", "swap_v2_b_to_a"),
+    ]
+    .into_iter()
+    .map(|(direction, v1, v2)| (direction, measured[v1] as i64 - measured[v2] as i64))
+    .collect()
+}
+
+#[test]
+#[ignore = "needs amm.so in SBF_OUT_DIR"]
+fn instructions_stay_within_compute_budget() {
+    let measured = measurements();
+    let savings = swap_v2_savings(&measured);
+
+    if env::var_os("UPDATE_COMPUTE_BUDGET").is_some() {
+        let mut file = HEADER.to_string();
+        file.push('\n');
+        for (name, units) in &measured {
+            file.push_str(&format!("{name} = {units}\n"));
+        }
+        file.push_str(&format!("\n[{SAVINGS}]\n"));
+        for (direction, saved) in &savings {
+            file.push_str(&format!("{direction} = {saved}\n"));
+        }
+        fs::write(budget_path(), file).expect("write compute budget");
+        return;
+    }
+
+    let budget: toml::Table = fs::read_to_string(budget_path())
+        .expect("read compute budget")
+        .parse()
+        .expect("parse compute budget");
+    let mut failures = Vec::new();
+    println!("{:<24} {:>8} {:>8}", "instruction", "units", "budget");
+    for (name, &units) in &measured {
+        let limit = budget.get(*name).and_then(toml::Value::as_integer);
+        match limit {
+            Some(limit) => {
+                println!("{name:<24} {units:>8} {limit:>8}");
+                if units as i64 > limit {
+                    failures.push(format!(
+                        "{name} used {units} units, over its budget of {limit}"
+                    ));
+                }
+            }
+            None => failures.push(format!("{name} has no budget")),
+        }
+    }
+    for name in budget.keys() {
+        if name != SAVINGS && !measured.contains_key(name.as_str()) {
+            failures.push(format!("{name} is budgeted but not benchmarked"));
+        }
+    }
+    let recorded = budget.get(SAVINGS).and_then(toml::Value::as_table);
+    for (direction, &saved) in &savings {
+        match recorded
+            .and_then(|recorded| recorded.get(*direction))
+            .and_then(toml::Value::as_integer)
+        {
+            Some(floor) => {
+                println!("swap_v2 saves {saved} units {direction}, recorded {floor}");
+                if saved < floor {
+                    failures.push(format!(
+                        "swap_v2 saves {saved} units {direction}, down from {floor}"
+                    ));
+                }
+            }
+            None => failures.push(format!("{SAVINGS}.{direction} is not recorded")),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\nrerun with UPDATE_COMPUTE_BUDGET=1 if the change is intended",
+        failures.join("\n")
+    );
+}
+
+#[test]
+#[ignore = "needs amm.so in SBF_OUT_DIR"]
+fn swap_v2_uses_less_compute_than_swap() {
+    for direction in [SwapDirection::AtoB, SwapDirection::BtoA] {
+        let (mut fixture, trader) = funded_pool();
+        let v1 = instructions::swap(&fixture.pool, &trader, 10_000, 0, direction);
+        let v1_accounts = v1.accounts.len();
+        let v1 = run(&mut fixture, v1, &trader);
+        let v2 = instructions::swap_v2(&fixture.pool, &trader, 10_000, 0, direction);
+        let v2_accounts = v2.accounts.len();
+        let v2 = run(&mut fixture, v2, &trader);
+
+        assert_eq!((v1_accounts, v2_accounts), (14, 11));
+        assert!(
+            v2.compute_units < v1.compute_units,
+            "swap_v2 used {} units, swap {}",
+            v2.compute_units,
+            v1.compute_units
+        );
+    }
+}
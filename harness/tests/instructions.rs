@@ -0,0 +1,1424 @@
+//! Instruction-level tests: every handler runs through the real Anchor
+//! entrypoint with SPL Token CPIs, against the in-process bank.
+
+use amm::{
+    AmmError, Deadline, FeeShare, PoolStats, ReferralFeeMode, SwapDirection, SwapQuote, TokenSide,
+};
+use amm_harness::{
+    custom_error, InstructionError, Outcome, PoolFixture, TransactionError, DECIMALS,
+};
+use amm_sdk::{instructions, pda::event_authority_address, AmmEvent, PoolSimulator, UserAccounts};
+use anchor_lang::{
+    error::ErrorCode,
+    prelude::*,
+    solana_program::{
+        entrypoint::ProgramResult, instruction::Instruction, program::invoke,
+        program_option::COption, program_pack::Pack,
+    },
+};
+use anchor_spl::token::spl_token::{
+    self,
+    state::{Account as TokenAccount, Mint},
+};
+
+const RESERVE_A: u64 = 1_000_000;
+const RESERVE_B: u64 = 4_000_000;
+/// `sqrt(RESERVE_A * RESERVE_B)`.
+const INITIAL_LP: u64 = 2_000_000;
+
+fn expect_error(result: std::result::Result<Outcome, TransactionError>, error: AmmError) {
+    let err = result.expect_err("instruction should fail");
+    assert_eq!(err.error, custom_error(error), "logs: {:#?}", err.logs);
+}
+
+/// A fixture whose pool holds `RESERVE_A`/`RESERVE_B`, all owned by the
+/// returned provider.
+fn funded_pool() -> (PoolFixture, UserAccounts) {
+    fund(PoolFixture::new())
+}
+
+fn fund(mut fixture: PoolFixture) -> (PoolFixture, UserAccounts) {
+    let provider = fixture.user(RESERVE_A, RESERVE_B);
+    fixture
+        .process(
+            instructions::deposit_liquidity(&fixture.pool, &provider, RESERVE_A, RESERVE_B, 0),
+            &provider,
+        )
+        .expect("seed deposit");
+    (fixture, provider)
+}
+
+fn simulator(fixture: &PoolFixture) -> PoolSimulator {
+    let (reserve_a, reserve_b) = fixture.reserves();
+    let (fees_a, fees_b) = fixture.protocol_fees();
+    PoolSimulator::from_pool(
+        &fixture.pool_state(),
+        reserve_a,
+        reserve_b,
+        fixture.lp_supply(),
+        fees_a,
+        fees_b,
+    )
+}
+
+/// Flash loan receiver that pays back `amount` from its first account to its
+/// second, signed by its third.
+fn repay_receiver(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let amount = u64::from_le_bytes(data.try_into().unwrap());
+    let transfer = spl_token::instruction::transfer(
+        &spl_token::ID,
+        accounts[0].key,
+        accounts[1].key,
+        accounts[2].key,
+        &[],
+        amount,
+    )?;
+    invoke(&transfer, &accounts[..3])
+}
+
+fn noop_receiver(_program_id: &Pubkey, _accounts: &[AccountInfo], _data: &[u8]) -> ProgramResult {
+    Ok(())
+}
+
+#[test]
+fn initialize_creates_pool_and_vaults() {
+    let fixture = PoolFixture::new();
+    let pool = fixture.pool_state();
+    let addresses = fixture.pool;
+
+    assert_eq!(
+        (pool.mint_a, pool.mint_b),
+        (addresses.mint_a, addresses.mint_b)
+    );
+    assert_eq!(
+        (pool.vault_a, pool.vault_b),
+        (addresses.vault_a, addresses.vault_b)
+    );
+    assert_eq!(
+        (pool.fee_vault_a, pool.fee_vault_b),
+        (addresses.fee_vault_a, addresses.fee_vault_b)
+    );
+    assert_eq!(pool.lp_mint, addresses.lp_mint);
+    assert_eq!(pool.admin, fixture.admin.owner);
+    assert_eq!(
+        pool.bump,
+        amm_sdk::pda::pool_address(&pool.mint_a, &pool.mint_b).1
+    );
+    assert_eq!(
+        (pool.fee_bps, pool.protocol_fee_bps, pool.flash_fee_bps),
+        (30, 5, 9)
+    );
+    assert!(!pool.paused);
+
+    for (vault, mint) in [
+        (addresses.vault_a, addresses.mint_a),
+        (addresses.vault_b, addresses.mint_b),
+        (addresses.fee_vault_a, addresses.mint_a),
+        (addresses.fee_vault_b, addresses.mint_b),
+    ] {
+        let vault: TokenAccount = fixture.runtime.unpack(&vault);
+        assert_eq!(
+            (vault.mint, vault.owner, vault.amount),
+            (mint, addresses.pool, 0)
+        );
+    }
+    let lp_mint: Mint = fixture.runtime.unpack(&addresses.lp_mint);
+    assert_eq!(lp_mint.mint_authority, COption::Some(addresses.pool));
+    assert_eq!((lp_mint.decimals, lp_mint.supply), (DECIMALS, 0));
+    let rent = fixture.runtime.rent();
+    let pool_account = fixture.runtime.account(&addresses.pool).unwrap();
+    assert_eq!(pool_account.owner, amm::ID);
+    assert!(rent.is_exempt(pool_account.lamports, amm::Pool::LEN));
+    assert!(rent.is_exempt(
+        fixture
+            .runtime
+            .account(&addresses.vault_a)
+            .unwrap()
+            .lamports,
+        TokenAccount::LEN
+    ));
+}
+
+#[test]
+fn initialize_rejects_identical_mints() {
+    let mut runtime = amm_harness::Runtime::new();
+    let mint = runtime.create_mint(DECIMALS);
+    let pool = amm_sdk::PoolAddresses::derive(mint, mint);
+    let admin = Pubkey::new_unique();
+    runtime.airdrop(&admin, 1_000_000_000);
+
+    expect_error(
+        runtime.process_transaction(&[instructions::initialize(&pool, &admin, &admin)], &[admin]),
+        AmmError::SameMint,
+    );
+    assert!(runtime.account(&pool.pool).is_none());
+}
+
+#[test]
+fn first_deposit_mints_geometric_mean() {
+    let mut fixture = PoolFixture::new();
+    let user = fixture.user(RESERVE_A, RESERVE_B);
+    let outcome = fixture
+        .process(
+            instructions::deposit_liquidity(&fixture.pool, &user, RESERVE_A, RESERVE_B, INITIAL_LP),
+            &user,
+        )
+        .unwrap();
+
+    assert_eq!(fixture.reserves(), (RESERVE_A, RESERVE_B));
+    assert_eq!(fixture.balances(&user), (0, 0, INITIAL_LP));
+    assert_eq!(fixture.lp_supply(), INITIAL_LP);
+    match outcome.events().as_slice() {
+        [AmmEvent::Deposit(event)] => {
+            assert_eq!(event.user, user.owner);
+            assert_eq!(
+                (event.amount_a_in, event.amount_b_in, event.lp_minted),
+                (RESERVE_A, RESERVE_B, INITIAL_LP)
+            );
+            assert_eq!(event.version, amm::EVENT_VERSION);
+            assert_eq!(
+                (event.reserve_a, event.reserve_b, event.lp_supply),
+                (RESERVE_A, RESERVE_B, INITIAL_LP)
+            );
+            // B per A, Q64.64.
+            assert_eq!(event.spot_price, 4 << 64);
+        }
+        _ => panic!("expected one deposit event"),
+    }
+}
+
+#[test]
+fn deposit_takes_only_the_proportional_amounts() {
+    let (mut fixture, _) = funded_pool();
+
+    // Excess B is left with the user: A is the limiting side.
+    let user = fixture.user(100_000, 1_000_000);
+    fixture
+        .process(
+            instructions::deposit_liquidity(&fixture.pool, &user, 100_000, 1_000_000, 200_000),
+            &user,
+        )
+        .unwrap();
+    assert_eq!(fixture.balances(&user), (0, 600_000, 200_000));
+
+    // Excess A is left with the user: B is the limiting side.
+    let user = fixture.user(1_000_000, 40_000);
+    fixture
+        .process(
+            instructions::deposit_liquidity(&fixture.pool, &user, 1_000_000, 40_000, 20_000),
+            &user,
+        )
+        .unwrap();
+    assert_eq!(fixture.balances(&user), (990_000, 0, 20_000));
+
+    assert_eq!(fixture.reserves(), (1_110_000, 4_440_000));
+    assert_eq!(fixture.lp_supply(), 2_220_000);
+}
+
+#[test]
+fn deposit_checks_minimum_lp_and_rolls_back() {
+    let (mut fixture, _) = funded_pool();
+    let user = fixture.user(100_000, 400_000);
+    expect_error(
+        fixture.process(
+            instructions::deposit_liquidity(&fixture.pool, &user, 100_000, 400_000, 200_001),
+            &user,
+        ),
+        AmmError::SlippageExceeded,
+    );
+    assert_eq!(fixture.balances(&user), (100_000, 400_000, 0));
+    assert_eq!(fixture.reserves(), (RESERVE_A, RESERVE_B));
+
+    expect_error(
+        fixture.process(
+            instructions::deposit_liquidity(&fixture.pool, &user, 0, 400_000, 0),
+            &user,
+        ),
+        AmmError::InvalidAmount,
+    );
+}
+
+#[test]
+fn strict_deposit_rejects_a_skewed_ratio() {
+    let (mut fixture, _) = funded_pool();
+    let user = fixture.user(100_000, 500_000);
+    expect_error(
+        fixture.process(
+            instructions::deposit_liquidity_strict(&fixture.pool, &user, 100_000, 500_000, 0, 100),
+            &user,
+        ),
+        AmmError::NonProportionalDeposit,
+    );
+    fixture
+        .process(
+            instructions::deposit_liquidity_strict(&fixture.pool, &user, 100_000, 404_000, 0, 100),
+            &user,
+        )
+        .unwrap();
+    assert_eq!(fixture.balances(&user), (0, 100_000, 200_000));
+}
+
+#[test]
+fn withdraw_returns_pro_rata_share() {
+    let (mut fixture, provider) = funded_pool();
+    fixture
+        .process(
+            instructions::withdraw_liquidity(&fixture.pool, &provider, 500_000, 250_000, 1_000_000),
+            &provider,
+        )
+        .unwrap();
+    assert_eq!(fixture.balances(&provider), (250_000, 1_000_000, 1_500_000));
+    assert_eq!(fixture.reserves(), (750_000, 3_000_000));
+    assert_eq!(fixture.lp_supply(), 1_500_000);
+
+    expect_error(
+        fixture.process(
+            instructions::withdraw_liquidity(&fixture.pool, &provider, 100_000, 50_001, 0),
+            &provider,
+        ),
+        AmmError::SlippageExceeded,
+    );
+    expect_error(
+        fixture.process(
+            instructions::withdraw_liquidity(&fixture.pool, &provider, 0, 0, 0),
+            &provider,
+        ),
+        AmmError::InvalidAmount,
+    );
+}
+
+#[test]
+fn swaps_in_both_directions_match_the_simulator() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(50_000, 200_000);
+
+    for (direction, amount_in) in [
+        (SwapDirection::AtoB, 50_000),
+        (SwapDirection::BtoA, 200_000),
+    ] {
+        let mut expected = simulator(&fixture);
+        let quote = expected.swap(amount_in, 0, direction).unwrap();
+        let before = fixture.balances(&trader);
+
+        let outcome = fixture
+            .process(
+                instructions::swap(
+                    &fixture.pool,
+                    &trader,
+                    amount_in,
+                    quote.amount_out,
+                    direction,
+                ),
+                &trader,
+            )
+            .unwrap();
+
+        assert_eq!(simulator(&fixture), expected);
+        let after = fixture.balances(&trader);
+        let (spent, received) = match direction {
+            SwapDirection::AtoB => (before.0 - after.0, after.1 - before.1),
+            SwapDirection::BtoA => (before.1 - after.1, after.0 - before.0),
+        };
+        assert_eq!((spent, received), (amount_in, quote.amount_out));
+        match outcome.events().as_slice() {
+            [AmmEvent::Swap(event)] => {
+                assert_eq!(
+                    (event.amount_in, event.amount_out),
+                    (amount_in, quote.amount_out)
+                );
+                assert_eq!(event.protocol_fee, quote.protocol_fee);
+                assert!(event.direction == direction);
+                assert_eq!(event.lp_fee, quote.lp_fee);
+                assert_eq!((event.reserve_a, event.reserve_b), fixture.reserves());
+                // `swap` doesn't take the LP mint, so can't report its supply.
+                assert_eq!(event.lp_supply, None);
+                assert_eq!(event.stats, expected.stats);
+            }
+            _ => panic!("expected one swap event"),
+        }
+    }
+    // 0.05% of each input went to the protocol.
+    assert_eq!(fixture.protocol_fees(), (25, 100));
+}
+
+#[test]
+fn events_report_the_pool_after_each_leg() {
+    let (mut fixture, provider) = funded_pool();
+    let user = fixture.user(100_000, 0);
+
+    let outcome = fixture
+        .process(
+            instructions::deposit_single_sided(&fixture.pool, &user, 100_000, TokenSide::A, 0),
+            &user,
+        )
+        .unwrap();
+    let lp = match outcome.events().as_slice() {
+        [AmmEvent::Swap(swap), AmmEvent::Deposit(deposit)] => {
+            // The swap leg leaves LP supply alone; the deposit leg ends in
+            // the pool's final state.
+            assert_eq!(swap.lp_supply, Some(INITIAL_LP));
+            assert_eq!(
+                (swap.reserve_a, swap.reserve_b),
+                (
+                    deposit.reserve_a - deposit.amount_a_in,
+                    deposit.reserve_b - deposit.amount_b_in
+                )
+            );
+            assert_eq!((deposit.reserve_a, deposit.reserve_b), fixture.reserves());
+            assert_eq!(deposit.lp_supply, fixture.lp_supply());
+            assert_eq!(
+                swap.spot_price,
+                (((swap.reserve_b as u128) << 64) / swap.reserve_a as u128)
+            );
+            deposit.lp_minted
+        }
+        _ => panic!("expected a swap and a deposit event"),
+    };
+
+    let before = fixture.balances(&user);
+    let outcome = fixture
+        .process(
+            instructions::withdraw_single_sided(&fixture.pool, &user, lp, TokenSide::B, 0),
+            &user,
+        )
+        .unwrap();
+    let after = fixture.balances(&user);
+    match outcome.events().as_slice() {
+        [AmmEvent::Swap(swap), AmmEvent::Withdraw(withdraw)] => {
+            // The swap leg is the last, so both events end in the final
+            // state, and the withdrawal reports only what the user received.
+            assert_eq!(swap.lp_supply, Some(INITIAL_LP));
+            assert_eq!((swap.reserve_a, swap.reserve_b), fixture.reserves());
+            assert_eq!(withdraw.lp_supply, INITIAL_LP);
+            assert_eq!((withdraw.reserve_a, withdraw.reserve_b), fixture.reserves());
+            assert_eq!(
+                (withdraw.amount_a_out, withdraw.amount_b_out),
+                (0, after.1 - before.1)
+            );
+        }
+        _ => panic!("expected a swap and a withdraw event"),
+    }
+
+    // An empty pool has no price.
+    let outcome = fixture
+        .process(
+            instructions::withdraw_liquidity(&fixture.pool, &provider, INITIAL_LP, 0, 0),
+            &provider,
+        )
+        .unwrap();
+    match outcome.events().as_slice() {
+        [AmmEvent::Withdraw(event)] => {
+            assert_eq!(
+                (event.reserve_a, event.reserve_b, event.lp_supply),
+                (0, 0, 0)
+            );
+            assert_eq!(event.spot_price, 0);
+        }
+        _ => panic!("expected one withdraw event"),
+    }
+}
+
+#[test]
+fn events_are_cpis_only_the_program_can_sign() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(10_000, 0);
+    let outcome = fixture
+        .process(
+            instructions::swap(&fixture.pool, &trader, 10_000, 0, SwapDirection::AtoB),
+            &trader,
+        )
+        .unwrap();
+    // Nothing is logged without the `log-events` feature.
+    assert!(!outcome
+        .logs
+        .iter()
+        .any(|log| log.starts_with("Program data: ")));
+    let (event_authority, _) = event_authority_address();
+    let mut event = outcome.inner_instructions.last().unwrap().clone();
+    assert_eq!(event.program_id, amm::ID);
+    assert_eq!(
+        event.accounts,
+        vec![AccountMeta::new_readonly(event_authority, false)]
+    );
+
+    // Replayed from outside the program, the event authority can't sign.
+    event.accounts[0].is_signer = true;
+    let err = fixture
+        .runtime
+        .process_transaction(std::slice::from_ref(&event), &[trader.owner])
+        .unwrap_err();
+    assert_eq!(
+        err.error,
+        InstructionError::MissingSignature(event_authority)
+    );
+    let mut unsigned = event;
+    unsigned.accounts[0].is_signer = false;
+    let err = fixture
+        .runtime
+        .process_transaction(&[unsigned], &[trader.owner])
+        .unwrap_err();
+    assert_eq!(err.error, custom_error(ErrorCode::ConstraintSigner));
+}
+
+#[test]
+fn swap_enforces_amount_slippage_and_mints() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(10_000, 0);
+    let quote = simulator(&fixture)
+        .swap(10_000, 0, SwapDirection::AtoB)
+        .unwrap();
+
+    expect_error(
+        fixture.process(
+            instructions::swap(&fixture.pool, &trader, 0, 0, SwapDirection::AtoB),
+            &trader,
+        ),
+        AmmError::InvalidAmount,
+    );
+    expect_error(
+        fixture.process(
+            instructions::swap(
+                &fixture.pool,
+                &trader,
+                10_000,
+                quote.amount_out + 1,
+                SwapDirection::AtoB,
+            ),
+            &trader,
+        ),
+        AmmError::SlippageExceeded,
+    );
+    // Source and destination swapped: the source holds B for an A-to-B swap.
+    let crossed = UserAccounts {
+        token_a: trader.token_b,
+        token_b: trader.token_a,
+        ..trader
+    };
+    expect_error(
+        fixture.process(
+            instructions::swap(&fixture.pool, &crossed, 10_000, 0, SwapDirection::AtoB),
+            &trader,
+        ),
+        AmmError::InvalidSwapMint,
+    );
+    assert_eq!(fixture.balances(&trader), (10_000, 0, 0));
+}
+
+#[test]
+fn referrers_are_paid_from_the_protocol_fee_or_on_top() {
+    let (mut fixture, _) = funded_pool();
+    let admin = fixture.admin;
+    let trader = fixture.user(300_000, 0);
+    let referrer = fixture.user(0, 0);
+    let referred_swap = |fixture: &PoolFixture, referrer: &Pubkey, referral_fee_bps| {
+        instructions::swap_with_referrer(
+            &fixture.pool,
+            &trader,
+            100_000,
+            0,
+            SwapDirection::AtoB,
+            referrer,
+            referral_fee_bps,
+        )
+    };
+
+    // Referrals are off until the admin allows them.
+    expect_error(
+        fixture.process(referred_swap(&fixture, &referrer.token_a, 1), &trader),
+        AmmError::InvalidFee,
+    );
+    // A cut of the protocol fee can't exceed it.
+    expect_error(
+        fixture.process(
+            instructions::set_referral_fee(
+                &fixture.pool.pool,
+                &admin.owner,
+                6,
+                ReferralFeeMode::FromProtocolFee,
+            ),
+            &admin,
+        ),
+        AmmError::InvalidFee,
+    );
+    let outcome = fixture
+        .process(
+            instructions::set_referral_fee(
+                &fixture.pool.pool,
+                &admin.owner,
+                5,
+                ReferralFeeMode::FromProtocolFee,
+            ),
+            &admin,
+        )
+        .unwrap();
+    assert!(matches!(
+        outcome.events().as_slice(),
+        [AmmEvent::ReferralFeeUpdated(event)] if event.max_referral_fee_bps == 5
+    ));
+    expect_error(
+        fixture.process(referred_swap(&fixture, &referrer.token_a, 6), &trader),
+        AmmError::InvalidFee,
+    );
+    expect_error(
+        fixture.process(referred_swap(&fixture, &referrer.token_b, 2), &trader),
+        AmmError::InvalidSwapMint,
+    );
+
+    // From the protocol fee: the trade itself is unchanged.
+    let mut expected = simulator(&fixture);
+    let (quote, referral_fee) = expected
+        .swap_with_referrer(100_000, 0, SwapDirection::AtoB, 2)
+        .unwrap();
+    assert_eq!(referral_fee, 20);
+    let outcome = fixture
+        .process(referred_swap(&fixture, &referrer.token_a, 2), &trader)
+        .unwrap();
+    assert_eq!(simulator(&fixture), expected);
+    assert_eq!(fixture.balances(&trader), (200_000, quote.amount_out, 0));
+    assert_eq!(fixture.balances(&referrer), (20, 0, 0));
+    match outcome.events().as_slice() {
+        [AmmEvent::Swap(event)] => {
+            assert_eq!(event.referrer, Some(referrer.token_a));
+            assert_eq!(event.referral_fee, 20);
+            assert_eq!(event.protocol_fee, quote.protocol_fee - 20);
+            assert_eq!(event.stats, expected.stats);
+        }
+        _ => panic!("expected one swap event"),
+    }
+
+    // On top: the trader pays the referral fee besides `amount_in`.
+    fixture
+        .process(
+            instructions::set_referral_fee(
+                &fixture.pool.pool,
+                &admin.owner,
+                50,
+                ReferralFeeMode::OnTop,
+            ),
+            &admin,
+        )
+        .unwrap();
+    let mut expected = simulator(&fixture);
+    let (quote, referral_fee) = expected
+        .swap_with_referrer(100_000, 0, SwapDirection::AtoB, 50)
+        .unwrap();
+    assert_eq!(referral_fee, 500);
+    let b_before = fixture.balances(&trader).1;
+    let outcome = fixture
+        .process(referred_swap(&fixture, &referrer.token_a, 50), &trader)
+        .unwrap();
+    assert_eq!(simulator(&fixture), expected);
+    assert_eq!(
+        fixture.balances(&trader),
+        (100_000 - 500, b_before + quote.amount_out, 0)
+    );
+    assert_eq!(fixture.balances(&referrer), (20 + 500, 0, 0));
+    match outcome.events().as_slice() {
+        [AmmEvent::Swap(event)] => {
+            assert_eq!(event.referral_fee, 500);
+            assert_eq!(event.protocol_fee, quote.protocol_fee);
+            assert_eq!(event.stats, expected.stats);
+        }
+        _ => panic!("expected one swap event"),
+    }
+}
+
+#[test]
+fn swap_against_an_empty_pool_fails() {
+    let mut fixture = PoolFixture::new();
+    let trader = fixture.user(10_000, 0);
+    expect_error(
+        fixture.process(
+            instructions::swap(&fixture.pool, &trader, 10_000, 0, SwapDirection::AtoB),
+            &trader,
+        ),
+        AmmError::InsufficientLiquidity,
+    );
+}
+
+#[test]
+fn swap_v2_trades_like_swap() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(50_000, 200_000);
+
+    for (direction, amount_in) in [
+        (SwapDirection::AtoB, 50_000),
+        (SwapDirection::BtoA, 200_000),
+    ] {
+        let mut expected = simulator(&fixture);
+        let quote = expected.swap(amount_in, 0, direction).unwrap();
+
+        let outcome = fixture
+            .process(
+                instructions::swap_v2(
+                    &fixture.pool,
+                    &trader,
+                    amount_in,
+                    quote.amount_out,
+                    direction,
+                ),
+                &trader,
+            )
+            .unwrap();
+
+        assert_eq!(simulator(&fixture), expected);
+        match outcome.events().as_slice() {
+            [AmmEvent::Swap(event)] => {
+                assert_eq!(
+                    (event.amount_in, event.amount_out),
+                    (amount_in, quote.amount_out)
+                );
+                assert_eq!(event.protocol_fee, quote.protocol_fee);
+                assert_eq!(event.lp_supply, Some(fixture.lp_supply()));
+            }
+            _ => panic!("expected one swap event"),
+        }
+    }
+    assert_eq!(fixture.protocol_fees(), (25, 100));
+}
+
+#[test]
+fn swap_v2_checks_accounts_against_the_pool() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(10_000, 0);
+    let swap = |fixture: &PoolFixture, user: &UserAccounts| {
+        instructions::swap_v2(&fixture.pool, user, 10_000, 0, SwapDirection::AtoB)
+    };
+
+    // The output side's fee vault in place of the input side's.
+    let mut wrong_fee_vault = swap(&fixture, &trader);
+    wrong_fee_vault.accounts[6].pubkey = fixture.pool.fee_vault_b;
+    expect_error(
+        fixture.process(wrong_fee_vault, &trader),
+        AmmError::InvalidVault,
+    );
+    // Vaults in the order of the opposite direction.
+    let mut reversed = swap(&fixture, &trader);
+    reversed.accounts.swap(4, 5);
+    expect_error(fixture.process(reversed, &trader), AmmError::InvalidVault);
+    let crossed = UserAccounts {
+        token_a: trader.token_b,
+        token_b: trader.token_a,
+        ..trader
+    };
+    expect_error(
+        fixture.process(swap(&fixture, &crossed), &trader),
+        AmmError::InvalidSwapMint,
+    );
+    // Anything but a pool account is refused by the loader.
+    let mut not_a_pool = swap(&fixture, &trader);
+    not_a_pool.accounts[1].pubkey = fixture.pool.vault_a;
+    let err = fixture.process(not_a_pool, &trader).unwrap_err();
+    assert_eq!(
+        err.error,
+        custom_error(ErrorCode::AccountOwnedByWrongProgram)
+    );
+
+    let admin = fixture.admin;
+    fixture
+        .process(
+            instructions::set_pause(&fixture.pool.pool, &admin.owner, true),
+            &admin,
+        )
+        .unwrap();
+    expect_error(
+        fixture.process(swap(&fixture, &trader), &trader),
+        AmmError::PoolPaused,
+    );
+    assert_eq!(fixture.balances(&trader), (10_000, 0, 0));
+}
+
+#[test]
+fn quote_returns_the_swap_it_would_make() {
+    let (mut fixture, _) = funded_pool();
+    let admin = fixture.admin;
+    let outcome = fixture
+        .process(
+            instructions::quote_swap(&fixture.pool, 10_000, SwapDirection::BtoA),
+            &admin,
+        )
+        .unwrap();
+    let (program, data) = outcome.return_data.expect("return data");
+    assert_eq!(program, amm::ID);
+    let quote = SwapQuote::deserialize(&mut data.as_slice()).unwrap();
+    let expected = simulator(&fixture)
+        .swap(10_000, 0, SwapDirection::BtoA)
+        .unwrap();
+    assert_eq!(quote, expected);
+}
+
+#[test]
+fn every_swap_path_adds_to_pool_stats() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(200_000, 200_000);
+    let swaps = [
+        instructions::swap(&fixture.pool, &trader, 10_000, 0, SwapDirection::AtoB),
+        instructions::swap_v2(&fixture.pool, &trader, 40_000, 0, SwapDirection::BtoA),
+        instructions::swap_with_price_limit(&fixture.pool, &trader, 10_000, 0, SwapDirection::BtoA),
+        instructions::deposit_single_sided(&fixture.pool, &trader, 50_000, TokenSide::A, 0),
+    ];
+    let mut expected = PoolStats::default();
+    let mut record = |outcome: Outcome| {
+        for event in outcome.events() {
+            if let AmmEvent::Swap(event) = event {
+                let quote = SwapQuote {
+                    amount_in: event.amount_in,
+                    amount_out: event.amount_out,
+                    protocol_fee: event.protocol_fee,
+                    lp_fee: event.lp_fee,
+                    price_impact_bps: 0,
+                };
+                expected.record_swap(event.direction, &quote).unwrap();
+                // Each event carries the totals up to and including itself.
+                assert_eq!(event.stats, expected);
+            }
+        }
+    };
+    for swap in swaps {
+        record(fixture.process(swap, &trader).unwrap());
+    }
+    let lp = fixture.balances(&trader).2;
+    record(
+        fixture
+            .process(
+                instructions::withdraw_single_sided(&fixture.pool, &trader, lp, TokenSide::B, 0),
+                &trader,
+            )
+            .unwrap(),
+    );
+
+    let stats = fixture.pool_state().stats;
+    assert_eq!(stats, expected);
+    assert_eq!(stats.swap_count, 5);
+    // Protocol fees are all still in the fee vaults.
+    let (fees_a, fees_b) = fixture.protocol_fees();
+    assert_eq!(
+        (stats.protocol_fees_a, stats.protocol_fees_b),
+        (fees_a as u128, fees_b as u128)
+    );
+
+    let admin = fixture.admin;
+    let outcome = fixture
+        .process(instructions::pool_stats(&fixture.pool), &admin)
+        .unwrap();
+    let (_, data) = outcome.return_data.expect("return data");
+    assert_eq!(PoolStats::deserialize(&mut data.as_slice()).unwrap(), stats);
+}
+
+#[test]
+fn quote_overflowing_the_input_reserve_fails() {
+    let mut fixture = PoolFixture::new();
+    let whale = fixture.user(1 << 60, 1 << 60);
+    fixture
+        .process(
+            instructions::deposit_liquidity(&fixture.pool, &whale, 1 << 60, 1 << 60, 0),
+            &whale,
+        )
+        .unwrap();
+    expect_error(
+        fixture.process(
+            instructions::quote_swap(&fixture.pool, u64::MAX, SwapDirection::AtoB),
+            &whale,
+        ),
+        AmmError::MathOverflow,
+    );
+}
+
+#[test]
+fn anyone_pays_protocol_fees_to_the_fee_recipient() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(200_000, 800_000);
+    for (amount_in, direction) in [
+        (200_000, SwapDirection::AtoB),
+        (800_000, SwapDirection::BtoA),
+    ] {
+        fixture
+            .process(
+                instructions::swap(&fixture.pool, &trader, amount_in, 0, direction),
+                &trader,
+            )
+            .unwrap();
+    }
+    assert_eq!(fixture.protocol_fees(), (100, 400));
+
+    // The fee recipient starts as the admin, and only its accounts are paid.
+    let admin = fixture.admin;
+    assert_eq!(fixture.pool_state().fee_recipient, admin.owner);
+    let err = fixture
+        .process(
+            instructions::withdraw_protocol_fees(&fixture.pool, &trader, 100, 400),
+            &trader,
+        )
+        .unwrap_err();
+    assert_eq!(err.error, custom_error(ErrorCode::ConstraintRaw));
+    expect_error(
+        fixture.process(
+            instructions::withdraw_protocol_fees(&fixture.pool, &admin, 0, 0),
+            &trader,
+        ),
+        AmmError::InvalidAmount,
+    );
+    expect_error(
+        fixture.process(
+            instructions::withdraw_protocol_fees(&fixture.pool, &admin, 101, 0),
+            &trader,
+        ),
+        AmmError::InsufficientLiquidity,
+    );
+
+    // The trader cranks it; the admin is paid.
+    let outcome = fixture
+        .process(
+            instructions::withdraw_protocol_fees(&fixture.pool, &admin, 60, 0),
+            &trader,
+        )
+        .unwrap();
+    assert_eq!(fixture.protocol_fees(), (40, 400));
+    assert_eq!(fixture.balances(&admin), (60, 0, 0));
+    assert!(matches!(
+        outcome.events().as_slice(),
+        [AmmEvent::ProtocolFeeWithdraw(event)] if event.fee_recipient == admin.owner
+    ));
+
+    // Only the admin moves the fees to a treasury.
+    let treasury = fixture.user(0, 0);
+    let err = fixture
+        .process(
+            instructions::set_fee_recipient(&fixture.pool.pool, &trader.owner, &trader.owner),
+            &trader,
+        )
+        .unwrap_err();
+    assert_eq!(err.error, custom_error(ErrorCode::ConstraintRaw));
+    let outcome = fixture
+        .process(
+            instructions::set_fee_recipient(&fixture.pool.pool, &admin.owner, &treasury.owner),
+            &admin,
+        )
+        .unwrap();
+    match outcome.events().as_slice() {
+        [AmmEvent::FeeRecipientUpdated(event)] => assert_eq!(
+            (event.old_fee_recipient, event.new_fee_recipient),
+            (admin.owner, treasury.owner)
+        ),
+        _ => panic!("expected a fee recipient update event"),
+    }
+    let err = fixture
+        .process(
+            instructions::withdraw_protocol_fees(&fixture.pool, &admin, 40, 400),
+            &admin,
+        )
+        .unwrap_err();
+    assert_eq!(err.error, custom_error(ErrorCode::ConstraintRaw));
+    fixture
+        .process(
+            instructions::withdraw_protocol_fees(&fixture.pool, &treasury, 40, 400),
+            &trader,
+        )
+        .unwrap();
+    assert_eq!(fixture.protocol_fees(), (0, 0));
+    assert_eq!(fixture.balances(&treasury), (40, 400, 0));
+    assert_eq!(fixture.balances(&admin), (60, 0, 0));
+}
+
+#[test]
+fn admin_migrates_a_pool_created_before_the_stats() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(2_000, 0);
+    let before = fixture.pool_state();
+
+    // Cut the pool back to its size before `stats` and every later field.
+    let old_len = 8 + 32 * 8 + 1 + 2 + 2 + 1 + 2;
+    let mut account = fixture.runtime.account(&fixture.pool.pool).unwrap();
+    account.data.truncate(old_len);
+    account.lamports = Rent::default().minimum_balance(old_len);
+    fixture.runtime.set_account(fixture.pool.pool, account);
+    let err = fixture
+        .process(
+            instructions::swap(&fixture.pool, &trader, 1_000, 0, SwapDirection::AtoB),
+            &trader,
+        )
+        .unwrap_err();
+    assert_eq!(err.error, custom_error(ErrorCode::AccountDidNotDeserialize));
+
+    let admin = fixture.admin;
+    let err = fixture
+        .process(
+            instructions::migrate_pool(&fixture.pool.pool, &trader.owner),
+            &trader,
+        )
+        .unwrap_err();
+    assert_eq!(err.error, custom_error(ErrorCode::ConstraintRaw));
+    fixture
+        .process(
+            instructions::migrate_pool(&fixture.pool.pool, &admin.owner),
+            &admin,
+        )
+        .unwrap();
+
+    // The old fields are kept, the new ones start from zero, and the fee
+    // recipient falls back to the admin.
+    let account = fixture.runtime.account(&fixture.pool.pool).unwrap();
+    assert_eq!(account.data.len(), amm::Pool::LEN);
+    assert_eq!(
+        account.lamports,
+        Rent::default().minimum_balance(amm::Pool::LEN)
+    );
+    let after = fixture.pool_state();
+    assert_eq!(
+        (
+            after.mint_a,
+            after.lp_mint,
+            after.admin,
+            after.fee_bps,
+            after.bump
+        ),
+        (
+            before.mint_a,
+            before.lp_mint,
+            before.admin,
+            before.fee_bps,
+            before.bump
+        )
+    );
+    assert_eq!(after.stats, PoolStats::default());
+    assert_eq!(after.max_referral_fee_bps, 0);
+    assert_eq!(after.fee_recipient, admin.owner);
+    fixture
+        .process(
+            instructions::swap(&fixture.pool, &trader, 1_000, 0, SwapDirection::AtoB),
+            &trader,
+        )
+        .unwrap();
+
+    expect_error(
+        fixture.process(
+            instructions::migrate_pool(&fixture.pool.pool, &admin.owner),
+            &admin,
+        ),
+        AmmError::PoolUpToDate,
+    );
+}
+
+#[test]
+fn anyone_distributes_protocol_fees_by_share() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(200_000, 800_000);
+    for (amount_in, direction) in [
+        (200_000, SwapDirection::AtoB),
+        (800_000, SwapDirection::BtoA),
+    ] {
+        fixture
+            .process(
+                instructions::swap(&fixture.pool, &trader, amount_in, 0, direction),
+                &trader,
+            )
+            .unwrap();
+    }
+    assert_eq!(fixture.protocol_fees(), (100, 400));
+
+    let admin = fixture.admin;
+    let recipients = [fixture.user(0, 0), fixture.user(0, 0), fixture.user(0, 0)];
+    let shares = |shares_bps: &[u16]| -> Vec<FeeShare> {
+        recipients
+            .iter()
+            .zip(shares_bps)
+            .map(|(recipient, &share_bps)| FeeShare {
+                owner: recipient.owner,
+                share_bps,
+            })
+            .collect()
+    };
+
+    // Only the admin sets the distribution, and its shares must add up.
+    let err = fixture
+        .process(
+            instructions::set_fee_distribution(
+                &fixture.pool.pool,
+                &trader.owner,
+                shares(&[5_000, 5_000]),
+            ),
+            &trader,
+        )
+        .unwrap_err();
+    assert_eq!(err.error, custom_error(ErrorCode::ConstraintRaw));
+    for invalid in [&[][..], &[5_000, 4_999], &[10_000, 0]] {
+        expect_error(
+            fixture.process(
+                instructions::set_fee_distribution(
+                    &fixture.pool.pool,
+                    &admin.owner,
+                    shares(invalid),
+                ),
+                &admin,
+            ),
+            AmmError::InvalidFeeDistribution,
+        );
+    }
+    let outcome = fixture
+        .process(
+            instructions::set_fee_distribution(
+                &fixture.pool.pool,
+                &admin.owner,
+                shares(&[3_334, 3_333, 3_333]),
+            ),
+            &admin,
+        )
+        .unwrap();
+    match outcome.events().as_slice() {
+        [AmmEvent::FeeDistributionUpdated(event)] => {
+            assert_eq!(event.recipients, shares(&[3_334, 3_333, 3_333]))
+        }
+        _ => panic!("expected a fee distribution update event"),
+    }
+
+    // From now on fees only leave through the distribution.
+    expect_error(
+        fixture.process(
+            instructions::withdraw_protocol_fees(&fixture.pool, &admin, 100, 400),
+            &admin,
+        ),
+        AmmError::FeesAreDistributed,
+    );
+
+    // Every recipient's accounts must be passed, in order.
+    for wrong in [
+        vec![recipients[0], recipients[1]],
+        vec![recipients[1], recipients[0], recipients[2]],
+    ] {
+        expect_error(
+            fixture.process(
+                instructions::distribute_protocol_fees(&fixture.pool, &wrong),
+                &trader,
+            ),
+            AmmError::InvalidFeeRecipient,
+        );
+    }
+
+    // The trader cranks it; rounding dust stays in the vaults.
+    let outcome = fixture
+        .process(
+            instructions::distribute_protocol_fees(&fixture.pool, &recipients),
+            &trader,
+        )
+        .unwrap();
+    for recipient in &recipients {
+        assert_eq!(fixture.balances(recipient), (33, 133, 0));
+    }
+    assert_eq!(fixture.protocol_fees(), (1, 1));
+    let paid: Vec<_> = outcome
+        .events()
+        .into_iter()
+        .map(|event| match event {
+            AmmEvent::ProtocolFeeWithdraw(event) => {
+                (event.fee_recipient, event.amount_a, event.amount_b)
+            }
+            _ => panic!("expected protocol fee withdraw events"),
+        })
+        .collect();
+    assert_eq!(
+        paid,
+        recipients
+            .iter()
+            .map(|recipient| (recipient.owner, 33, 133))
+            .collect::<Vec<_>>()
+    );
+
+    // The distribution can be replaced; the dust goes out with the next payout.
+    fixture
+        .process(
+            instructions::set_fee_distribution(&fixture.pool.pool, &admin.owner, shares(&[10_000])),
+            &admin,
+        )
+        .unwrap();
+    fixture
+        .process(
+            instructions::distribute_protocol_fees(&fixture.pool, &recipients[..1]),
+            &trader,
+        )
+        .unwrap();
+    assert_eq!(fixture.balances(&recipients[0]), (34, 134, 0));
+    assert_eq!(fixture.protocol_fees(), (0, 0));
+    expect_error(
+        fixture.process(
+            instructions::distribute_protocol_fees(&fixture.pool, &recipients[..1]),
+            &trader,
+        ),
+        AmmError::InvalidAmount,
+    );
+}
+
+#[test]
+fn pause_stops_trading_but_not_withdrawals() {
+    let (mut fixture, provider) = funded_pool();
+    let admin = fixture.admin;
+    let pool = fixture.pool.pool;
+
+    let err = fixture
+        .process(
+            instructions::set_pause(&pool, &provider.owner, true),
+            &provider,
+        )
+        .unwrap_err();
+    assert_eq!(err.error, custom_error(ErrorCode::ConstraintRaw));
+
+    fixture
+        .process(instructions::set_pause(&pool, &admin.owner, true), &admin)
+        .unwrap();
+    assert!(fixture.pool_state().paused);
+
+    let trader = fixture.user(10_000, 40_000);
+    expect_error(
+        fixture.process(
+            instructions::swap(&fixture.pool, &trader, 10_000, 0, SwapDirection::AtoB),
+            &trader,
+        ),
+        AmmError::PoolPaused,
+    );
+    expect_error(
+        fixture.process(
+            instructions::deposit_liquidity(&fixture.pool, &trader, 10_000, 40_000, 0),
+            &trader,
+        ),
+        AmmError::PoolPaused,
+    );
+    fixture
+        .process(
+            instructions::withdraw_liquidity(&fixture.pool, &provider, 1_000, 0, 0),
+            &provider,
+        )
+        .unwrap();
+
+    fixture
+        .process(instructions::set_pause(&pool, &admin.owner, false), &admin)
+        .unwrap();
+    fixture
+        .process(
+            instructions::swap(&fixture.pool, &trader, 10_000, 0, SwapDirection::AtoB),
+            &trader,
+        )
+        .unwrap();
+}
+
+#[test]
+fn deadlines_reject_late_transactions() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(10_000, 0);
+    let now = fixture.runtime.clock().unix_timestamp;
+    let slot = fixture.runtime.clock().slot;
+
+    for deadline in [
+        Deadline::UnixTimestamp(now - 1),
+        Deadline::MaxSlot(slot - 1),
+    ] {
+        expect_error(
+            fixture.process(
+                instructions::swap_with_deadline(
+                    &fixture.pool,
+                    &trader,
+                    10_000,
+                    0,
+                    SwapDirection::AtoB,
+                    Some(deadline),
+                ),
+                &trader,
+            ),
+            AmmError::Expired,
+        );
+    }
+    fixture
+        .process(
+            instructions::swap_with_deadline(
+                &fixture.pool,
+                &trader,
+                10_000,
+                0,
+                SwapDirection::AtoB,
+                Some(Deadline::UnixTimestamp(now)),
+            ),
+            &trader,
+        )
+        .unwrap();
+}
+
+#[test]
+fn price_bounds_are_enforced() {
+    let (mut fixture, _) = funded_pool();
+    let trader = fixture.user(10_000, 40_000);
+    // The pool prices A at 4 B, in Q64.64.
+    let spot = 4u128 << 64;
+
+    expect_error(
+        fixture.process(
+            instructions::swap_with_price_limit(
+                &fixture.pool,
+                &trader,
+                10_000,
+                spot + 1,
+                SwapDirection::AtoB,
+            ),
+            &trader,
+        ),
+        AmmError::PriceLimitReached,
+    );
+    expect_error(
+        fixture.process(
+            instructions::deposit_liquidity_guarded(
+                &fixture.pool,
+                &trader,
+                10_000,
+                40_000,
+                0,
+                None,
+                Some(spot + 1),
+                None,
+            ),
+            &trader,
+        ),
+        AmmError::PriceOutOfRange,
+    );
+    // Both guards apply together: a price in range doesn't excuse a late
+    // transaction.
+    let now = fixture.runtime.clock().unix_timestamp;
+    expect_error(
+        fixture.process(
+            instructions::deposit_liquidity_guarded(
+                &fixture.pool,
+                &trader,
+                10_000,
+                40_000,
+                0,
+                Some(Deadline::UnixTimestamp(now - 1)),
+                Some(spot),
+                Some(spot),
+            ),
+            &trader,
+        ),
+        AmmError::Expired,
+    );
+    fixture
+        .process(
+            instructions::deposit_liquidity_guarded(
+                &fixture.pool,
+                &trader,
+                10_000,
+                40_000,
+                0,
+                Some(Deadline::UnixTimestamp(now)),
+                Some(spot),
+                Some(spot),
+            ),
+            &trader,
+        )
+        .unwrap();
+
+    let lp = fixture.balances(&trader).2;
+    expect_error(
+        fixture.process(
+            instructions::withdraw_liquidity_guarded(
+                &fixture.pool,
+                &trader,
+                lp,
+                0,
+                0,
+                Some(Deadline::UnixTimestamp(now)),
+                None,
+                Some(spot - 1),
+            ),
+            &trader,
+        ),
+        AmmError::PriceOutOfRange,
+    );
+    fixture
+        .process(
+            instructions::withdraw_liquidity_guarded(
+                &fixture.pool,
+                &trader,
+                lp,
+                0,
+                0,
+                Some(Deadline::UnixTimestamp(now)),
+                Some(spot),
+                Some(spot),
+            ),
+            &trader,
+        )
+        .unwrap();
+}
+
+#[test]
+fn flash_fee_is_bounded() {
+    let mut fixture = PoolFixture::new();
+    let admin = fixture.admin;
+    expect_error(
+        fixture.process(
+            instructions::set_flash_fee(&fixture.pool.pool, &admin.owner, 10_000),
+            &admin,
+        ),
+        AmmError::InvalidFee,
+    );
+    fixture
+        .process(
+            instructions::set_flash_fee(&fixture.pool.pool, &admin.owner, 100),
+            &admin,
+        )
+        .unwrap();
+    assert_eq!(fixture.pool_state().flash_fee_bps, 100);
+}
+
+#[test]
+fn flash_loan_must_be_repaid_with_fee() {
+    let repay = Pubkey::new_unique();
+    let noop = Pubkey::new_unique();
+    let (mut fixture, _) = fund(PoolFixture::with_programs(&[
+        (repay, repay_receiver),
+        (noop, noop_receiver),
+    ]));
+    // The fee on 100_000 A at 9 bps is 90.
+    let borrower = fixture.user(90, 0);
+    let receiver_accounts = vec![
+        AccountMeta::new(borrower.token_a, false),
+        AccountMeta::new(fixture.pool.vault_a, false),
+        AccountMeta::new_readonly(borrower.owner, true),
+        AccountMeta::new_readonly(spl_token::ID, false),
+    ];
+    let loan = |receiver: &Pubkey, repaid: u64| -> Instruction {
+        instructions::flash_loan(
+            &fixture.pool,
+            &borrower,
+            receiver,
+            100_000,
+            0,
+            repaid.to_le_bytes().to_vec(),
+            receiver_accounts.clone(),
+        )
+    };
+    let (not_repaid, short, full, reentrant) = (
+        loan(&noop, 0),
+        loan(&repay, 100_089),
+        loan(&repay, 100_090),
+        loan(&amm::ID, 0),
+    );
+
+    expect_error(
+        fixture.process(not_repaid, &borrower),
+        AmmError::FlashLoanNotRepaid,
+    );
+    expect_error(
+        fixture.process(short, &borrower),
+        AmmError::FlashLoanNotRepaid,
+    );
+    expect_error(
+        fixture.process(reentrant, &borrower),
+        AmmError::InvalidFlashReceiver,
+    );
+    assert_eq!(fixture.balances(&borrower), (90, 0, 0));
+
+    fixture.process(full, &borrower).unwrap();
+    assert_eq!(fixture.balances(&borrower), (0, 0, 0));
+    // 5/30 of the fee, rounded down, goes to the protocol; LPs keep the rest.
+    assert_eq!(fixture.protocol_fees(), (15, 0));
+    assert_eq!(fixture.reserves(), (RESERVE_A + 75, RESERVE_B));
+}
+
+// `AmmError::MissingBump` is not raised by any instruction: bumps come from
+// `ctx.bumps`, which Anchor always fills for `seeds` constraints.
@@ -0,0 +1,97 @@
+//! Seeded runs of the instruction-sequence checker that backs the
+//! `pool_sequences` fuzz target, so `cargo test` covers it too.
+
+use amm_harness::sequence::{Amount, Sequence, Step};
+use arbitrary::{Arbitrary, Unstructured};
+
+/// Deterministic input bytes for `arbitrary` (SplitMix64).
+fn seeded_bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    let mut bytes = Vec::with_capacity(len + 8);
+    while bytes.len() < len {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        bytes.extend_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+    }
+    bytes
+}
+
+#[test]
+fn random_sequences_keep_invariants() {
+    for seed in 0..16 {
+        let bytes = seeded_bytes(seed, 4096);
+        let mut input = Unstructured::new(&bytes);
+        let mut steps = vec![Step::Initialize];
+        while steps.len() < 100 {
+            steps.push(Step::arbitrary(&mut input).unwrap());
+        }
+        Sequence::new().run(&steps);
+    }
+}
+
+#[test]
+fn donations_between_deposits_and_withdrawals() {
+    Sequence::new().run(&[
+        // Nothing works before the pool exists.
+        Step::Deposit {
+            user: 0,
+            amount_a: Amount::Exact(1_000),
+            amount_b: Amount::Exact(1_000),
+        },
+        Step::Initialize,
+        Step::Initialize,
+        // Donate first, so the first depositor mints against a skewed vault.
+        Step::Donate {
+            user: 1,
+            amount: Amount::Exact(7),
+            to_vault_a: true,
+        },
+        Step::Deposit {
+            user: 0,
+            amount_a: Amount::Exact(1_000),
+            amount_b: Amount::Exact(3_000),
+        },
+        Step::Deposit {
+            user: 1,
+            amount_a: Amount::Exact(333),
+            amount_b: Amount::Exact(1_001),
+        },
+        Step::Swap {
+            user: 2,
+            amount_in: Amount::Exact(500),
+            a_to_b: true,
+            v2: false,
+        },
+        Step::Donate {
+            user: 2,
+            amount: Amount::Exact(1),
+            to_vault_a: false,
+        },
+        Step::Swap {
+            user: 2,
+            amount_in: Amount::Exact(1_999),
+            a_to_b: false,
+            v2: true,
+        },
+        Step::Withdraw {
+            user: 1,
+            lp_amount: Amount::All,
+        },
+        Step::WithdrawProtocolFees {
+            amount_a: Amount::All,
+            amount_b: Amount::All,
+        },
+        Step::Withdraw {
+            user: 0,
+            lp_amount: Amount::All,
+        },
+        // Empty again: the next deposit starts over at sqrt(a * b).
+        Step::Deposit {
+            user: 2,
+            amount_a: Amount::Exact(10),
+            amount_b: Amount::Exact(10),
+        },
+    ]);
+}
@@ -0,0 +1,14 @@
+//! Builds a local SQLite history of amm pools from confirmed transactions.
+//!
+//! Feed [`Store::index`] `getTransaction` responses, from RPC or recorded in
+//! files: it decodes every amm event, from the event CPIs in the inner
+//! instructions or, for transactions that only have them, from the logs, and
+//! upserts pools, swaps, deposits, withdrawals, protocol fee withdrawals and
+//! admin actions. Indexing is idempotent, so overlapping fetches and replays
+//! are safe.
+
+pub mod store;
+pub mod transaction;
+
+pub use store::Store;
+pub use transaction::{ConfirmedTransaction, IndexedEvent};
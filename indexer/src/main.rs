@@ -0,0 +1,25 @@
+//! `amm-indexer <database> <transaction.json>...`: indexes saved
+//! `getTransaction` responses into the SQLite database at `<database>`.
+
+use std::{env, fs};
+
+use amm_indexer::{ConfirmedTransaction, Store};
+use anyhow::{bail, Context, Result};
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let Some(database) = args.next() else {
+        bail!("usage: amm-indexer <database> <transaction.json>...");
+    };
+    let mut store = Store::open(&database)?;
+    for path in args {
+        let json = fs::read_to_string(&path).with_context(|| format!("read {path}"))?;
+        let transaction =
+            ConfirmedTransaction::from_json(&json).with_context(|| format!("parse {path}"))?;
+        let events = store
+            .index(&transaction)
+            .with_context(|| format!("index {path}"))?;
+        println!("{path}: {events} events");
+    }
+    Ok(())
+}
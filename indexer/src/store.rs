@@ -0,0 +1,577 @@
+//! The SQLite database events are indexed into.
+//!
+//! Swaps, deposits, withdrawals, protocol fee withdrawals and admin actions
+//! get one row per event, keyed by `(signature, instruction_index,
+//! event_index)`, so indexing a transaction again, or from its logs after its
+//! inner instructions, rewrites the same rows. `pools` holds each pool's
+//! latest known state: its reserves as of the last swap, deposit or
+//! withdrawal, its LP supply as of the last of those that reported it (plain
+//! `swap`s don't), its trading totals as of the last swap, and its
+//! admin settings as of the last change. Updates from a slot older than the
+//! one a row already reflects are ignored, so transactions may be indexed out
+//! of order across slots.
+//!
+//! Token amounts are stored as `INTEGER`, so an amount above `i64::MAX` fails
+//! to index rather than wrapping. Q64.64 spot prices and the pool's `u128`
+//! trading totals don't fit and are stored as decimal `TEXT`. Flash loans are
+//! decoded but not stored.
+
+use std::path::Path;
+
+use amm_sdk::{AmmEvent, ReferralFeeMode, SwapDirection};
+use anchor_lang::prelude::Pubkey;
+use anyhow::{Context, Result};
+use rusqlite::{Connection, ToSql};
+use serde_json::json;
+
+use crate::transaction::{ConfirmedTransaction, IndexedEvent};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pools (
+    address TEXT PRIMARY KEY,
+    mint_a TEXT,
+    mint_b TEXT,
+    lp_mint TEXT,
+    vault_a TEXT,
+    vault_b TEXT,
+    fee_vault_a TEXT,
+    fee_vault_b TEXT,
+    fee_bps INTEGER,
+    protocol_fee_bps INTEGER,
+    admin TEXT,
+    fee_recipient TEXT,
+    paused INTEGER,
+    flash_fee_bps INTEGER,
+    max_referral_fee_bps INTEGER,
+    referral_fee_mode TEXT,
+    config_slot INTEGER NOT NULL DEFAULT 0,
+    reserve_a INTEGER,
+    reserve_b INTEGER,
+    lp_supply INTEGER,
+    spot_price TEXT,
+    reserves_slot INTEGER NOT NULL DEFAULT 0,
+    volume_a TEXT,
+    volume_b TEXT,
+    swap_count TEXT,
+    lp_fees_a TEXT,
+    lp_fees_b TEXT,
+    protocol_fees_a TEXT,
+    protocol_fees_b TEXT,
+    stats_slot INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS swaps (
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    event_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    pool TEXT NOT NULL,
+    user TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    amount_in INTEGER NOT NULL,
+    amount_out INTEGER NOT NULL,
+    amount_in_unfilled INTEGER NOT NULL,
+    lp_fee INTEGER NOT NULL,
+    protocol_fee INTEGER NOT NULL,
+    reserve_a INTEGER NOT NULL,
+    reserve_b INTEGER NOT NULL,
+    lp_supply INTEGER,
+    spot_price TEXT NOT NULL,
+    referrer TEXT,
+    referral_fee INTEGER NOT NULL,
+    PRIMARY KEY (signature, instruction_index, event_index)
+);
+
+CREATE TABLE IF NOT EXISTS deposits (
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    event_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    pool TEXT NOT NULL,
+    user TEXT NOT NULL,
+    amount_a INTEGER NOT NULL,
+    amount_b INTEGER NOT NULL,
+    lp_minted INTEGER NOT NULL,
+    reserve_a INTEGER NOT NULL,
+    reserve_b INTEGER NOT NULL,
+    lp_supply INTEGER NOT NULL,
+    spot_price TEXT NOT NULL,
+    PRIMARY KEY (signature, instruction_index, event_index)
+);
+
+CREATE TABLE IF NOT EXISTS withdrawals (
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    event_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    pool TEXT NOT NULL,
+    user TEXT NOT NULL,
+    lp_burned INTEGER NOT NULL,
+    amount_a INTEGER NOT NULL,
+    amount_b INTEGER NOT NULL,
+    reserve_a INTEGER NOT NULL,
+    reserve_b INTEGER NOT NULL,
+    lp_supply INTEGER NOT NULL,
+    spot_price TEXT NOT NULL,
+    PRIMARY KEY (signature, instruction_index, event_index)
+);
+
+CREATE TABLE IF NOT EXISTS protocol_fee_withdrawals (
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    event_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    pool TEXT NOT NULL,
+    fee_recipient TEXT NOT NULL,
+    amount_a INTEGER NOT NULL,
+    amount_b INTEGER NOT NULL,
+    PRIMARY KEY (signature, instruction_index, event_index)
+);
+
+-- `details` is a JSON object with the action's arguments.
+CREATE TABLE IF NOT EXISTS admin_actions (
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    event_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    pool TEXT NOT NULL,
+    action TEXT NOT NULL,
+    admin TEXT NOT NULL,
+    details TEXT NOT NULL,
+    PRIMARY KEY (signature, instruction_index, event_index)
+);
+";
+
+pub struct Store {
+    connection: Connection,
+}
+
+impl Store {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let connection =
+            Connection::open(path).with_context(|| format!("open {}", path.display()))?;
+        Store::new(connection)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Store::new(Connection::open_in_memory()?)
+    }
+
+    fn new(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA).context("create tables")?;
+        Ok(Store { connection })
+    }
+
+    /// For queries against the indexed tables.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Stores every event `transaction` emitted, in one database transaction,
+    /// and returns how many there were.
+    pub fn index(&mut self, transaction: &ConfirmedTransaction) -> Result<usize> {
+        let events = transaction.events()?;
+        let db = self.connection.transaction()?;
+        for event in &events {
+            store_event(&db, event).with_context(|| {
+                format!(
+                    "index event {} of instruction {} in {}",
+                    event.event_index, event.instruction_index, event.signature
+                )
+            })?;
+        }
+        db.commit()?;
+        Ok(events.len())
+    }
+}
+
+fn store_event(db: &Connection, indexed: &IndexedEvent) -> Result<()> {
+    let slot = indexed.slot;
+    match &indexed.event {
+        AmmEvent::Initialize(event) => {
+            db.prepare_cached(
+                "INSERT INTO pools (address, mint_a, mint_b, lp_mint, vault_a, vault_b,
+                     fee_vault_a, fee_vault_b, fee_bps, protocol_fee_bps)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT (address) DO UPDATE SET
+                     mint_a = excluded.mint_a, mint_b = excluded.mint_b,
+                     lp_mint = excluded.lp_mint, vault_a = excluded.vault_a,
+                     vault_b = excluded.vault_b, fee_vault_a = excluded.fee_vault_a,
+                     fee_vault_b = excluded.fee_vault_b, fee_bps = excluded.fee_bps,
+                     protocol_fee_bps = excluded.protocol_fee_bps",
+            )?
+            .execute((
+                key(&event.pool),
+                key(&event.mint_a),
+                key(&event.mint_b),
+                key(&event.lp_mint),
+                key(&event.vault_a),
+                key(&event.vault_b),
+                key(&event.fee_vault_a),
+                key(&event.fee_vault_b),
+                event.fee_bps,
+                event.protocol_fee_bps,
+            ))?;
+            update_pool(
+                db,
+                &event.pool,
+                "config_slot",
+                slot,
+                &[
+                    ("admin", &key(&event.admin)),
+                    ("fee_recipient", &key(&event.fee_recipient)),
+                    ("paused", &event.paused),
+                    ("flash_fee_bps", &event.flash_fee_bps),
+                ],
+            )
+        }
+        AmmEvent::Swap(event) => {
+            let direction = match event.direction {
+                SwapDirection::AtoB => "a_to_b",
+                SwapDirection::BtoA => "b_to_a",
+            };
+            upsert(
+                db,
+                "swaps",
+                indexed,
+                &[
+                    ("pool", &key(&event.pool)),
+                    ("user", &key(&event.user)),
+                    ("direction", &direction),
+                    ("amount_in", &event.amount_in),
+                    ("amount_out", &event.amount_out),
+                    ("amount_in_unfilled", &event.amount_in_unfilled),
+                    ("lp_fee", &event.lp_fee),
+                    ("protocol_fee", &event.protocol_fee),
+                    ("reserve_a", &event.reserve_a),
+                    ("reserve_b", &event.reserve_b),
+                    ("lp_supply", &event.lp_supply),
+                    ("spot_price", &event.spot_price.to_string()),
+                    ("referrer", &event.referrer.as_ref().map(key)),
+                    ("referral_fee", &event.referral_fee),
+                ],
+            )?;
+            update_reserves(
+                db,
+                &event.pool,
+                slot,
+                (event.reserve_a, event.reserve_b, event.lp_supply),
+                event.spot_price,
+            )?;
+            let stats = &event.stats;
+            update_pool(
+                db,
+                &event.pool,
+                "stats_slot",
+                slot,
+                &[
+                    ("volume_a", &stats.volume_a.to_string()),
+                    ("volume_b", &stats.volume_b.to_string()),
+                    ("swap_count", &stats.swap_count.to_string()),
+                    ("lp_fees_a", &stats.lp_fees_a.to_string()),
+                    ("lp_fees_b", &stats.lp_fees_b.to_string()),
+                    ("protocol_fees_a", &stats.protocol_fees_a.to_string()),
+                    ("protocol_fees_b", &stats.protocol_fees_b.to_string()),
+                ],
+            )
+        }
+        AmmEvent::Deposit(event) => {
+            upsert(
+                db,
+                "deposits",
+                indexed,
+                &[
+                    ("pool", &key(&event.pool)),
+                    ("user", &key(&event.user)),
+                    ("amount_a", &event.amount_a_in),
+                    ("amount_b", &event.amount_b_in),
+                    ("lp_minted", &event.lp_minted),
+                    ("reserve_a", &event.reserve_a),
+                    ("reserve_b", &event.reserve_b),
+                    ("lp_supply", &event.lp_supply),
+                    ("spot_price", &event.spot_price.to_string()),
+                ],
+            )?;
+            update_reserves(
+                db,
+                &event.pool,
+                slot,
+                (event.reserve_a, event.reserve_b, Some(event.lp_supply)),
+                event.spot_price,
+            )
+        }
+        AmmEvent::Withdraw(event) => {
+            upsert(
+                db,
+                "withdrawals",
+                indexed,
+                &[
+                    ("pool", &key(&event.pool)),
+                    ("user", &key(&event.user)),
+                    ("lp_burned", &event.lp_burned),
+                    ("amount_a", &event.amount_a_out),
+                    ("amount_b", &event.amount_b_out),
+                    ("reserve_a", &event.reserve_a),
+                    ("reserve_b", &event.reserve_b),
+                    ("lp_supply", &event.lp_supply),
+                    ("spot_price", &event.spot_price.to_string()),
+                ],
+            )?;
+            update_reserves(
+                db,
+                &event.pool,
+                slot,
+                (event.reserve_a, event.reserve_b, Some(event.lp_supply)),
+                event.spot_price,
+            )
+        }
+        AmmEvent::ProtocolFeeWithdraw(event) => upsert(
+            db,
+            "protocol_fee_withdrawals",
+            indexed,
+            &[
+                ("pool", &key(&event.pool)),
+                ("fee_recipient", &key(&event.fee_recipient)),
+                ("amount_a", &event.amount_a),
+                ("amount_b", &event.amount_b),
+            ],
+        ),
+        AmmEvent::FlashFeeUpdated(event) => {
+            admin_action(
+                db,
+                indexed,
+                &event.pool,
+                "set_flash_fee",
+                &event.admin,
+                json!({
+                    "old_flash_fee_bps": event.old_flash_fee_bps,
+                    "new_flash_fee_bps": event.new_flash_fee_bps,
+                }),
+            )?;
+            update_pool(
+                db,
+                &event.pool,
+                "config_slot",
+                slot,
+                &[("flash_fee_bps", &event.new_flash_fee_bps)],
+            )
+        }
+        AmmEvent::ReferralFeeUpdated(event) => {
+            let mode = match event.referral_fee_mode {
+                ReferralFeeMode::FromProtocolFee => "from_protocol_fee",
+                ReferralFeeMode::OnTop => "on_top",
+            };
+            admin_action(
+                db,
+                indexed,
+                &event.pool,
+                "set_referral_fee",
+                &event.admin,
+                json!({
+                    "max_referral_fee_bps": event.max_referral_fee_bps,
+                    "referral_fee_mode": mode,
+                }),
+            )?;
+            update_pool(
+                db,
+                &event.pool,
+                "config_slot",
+                slot,
+                &[
+                    ("max_referral_fee_bps", &event.max_referral_fee_bps),
+                    ("referral_fee_mode", &mode),
+                ],
+            )
+        }
+        AmmEvent::FeeRecipientUpdated(event) => {
+            admin_action(
+                db,
+                indexed,
+                &event.pool,
+                "set_fee_recipient",
+                &event.admin,
+                json!({
+                    "old_fee_recipient": key(&event.old_fee_recipient),
+                    "new_fee_recipient": key(&event.new_fee_recipient),
+                }),
+            )?;
+            update_pool(
+                db,
+                &event.pool,
+                "config_slot",
+                slot,
+                &[("fee_recipient", &key(&event.new_fee_recipient))],
+            )
+        }
+        AmmEvent::FeeDistributionUpdated(event) => admin_action(
+            db,
+            indexed,
+            &event.pool,
+            "set_fee_distribution",
+            &event.admin,
+            json!({
+                "recipients": event
+                    .recipients
+                    .iter()
+                    .map(|share| json!({
+                        "owner": key(&share.owner),
+                        "share_bps": share.share_bps,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        ),
+        AmmEvent::Pause(event) => {
+            admin_action(
+                db,
+                indexed,
+                &event.pool,
+                "set_pause",
+                &event.admin,
+                json!({ "paused": event.paused }),
+            )?;
+            update_pool(
+                db,
+                &event.pool,
+                "config_slot",
+                slot,
+                &[("paused", &event.paused)],
+            )
+        }
+        AmmEvent::AdminUpdated(event) => {
+            admin_action(
+                db,
+                indexed,
+                &event.pool,
+                "set_admin",
+                &event.old_admin,
+                json!({ "new_admin": key(&event.new_admin) }),
+            )?;
+            update_pool(
+                db,
+                &event.pool,
+                "config_slot",
+                slot,
+                &[("admin", &key(&event.new_admin))],
+            )
+        }
+        AmmEvent::FlashLoan(_) => Ok(()),
+    }
+}
+
+fn key(pubkey: &Pubkey) -> String {
+    pubkey.to_string()
+}
+
+/// Inserts or rewrites the row for `indexed` in `table`.
+fn upsert(
+    db: &Connection,
+    table: &str,
+    indexed: &IndexedEvent,
+    columns: &[(&str, &dyn ToSql)],
+) -> Result<()> {
+    let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+    let placeholders: Vec<String> = (6..6 + columns.len()).map(|n| format!("?{n}")).collect();
+    let updates: Vec<String> = ["slot", "block_time"]
+        .iter()
+        .chain(&names)
+        .map(|name| format!("{name} = excluded.{name}"))
+        .collect();
+    let sql = format!(
+        "INSERT INTO {table} (signature, instruction_index, event_index, slot, block_time, {})
+         VALUES (?1, ?2, ?3, ?4, ?5, {})
+         ON CONFLICT (signature, instruction_index, event_index) DO UPDATE SET {}",
+        names.join(", "),
+        placeholders.join(", "),
+        updates.join(", ")
+    );
+    let mut params: Vec<&dyn ToSql> = vec![
+        &indexed.signature,
+        &indexed.instruction_index,
+        &indexed.event_index,
+        &indexed.slot,
+        &indexed.block_time,
+    ];
+    params.extend(columns.iter().map(|(_, value)| *value));
+    db.prepare_cached(&sql)?.execute(&params[..])?;
+    Ok(())
+}
+
+/// Sets `columns` of `pool`'s row, creating it if needed, unless the row's
+/// `slot_column` is already past `slot`.
+fn update_pool(
+    db: &Connection,
+    pool: &Pubkey,
+    slot_column: &str,
+    slot: u64,
+    columns: &[(&str, &dyn ToSql)],
+) -> Result<()> {
+    let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+    let placeholders: Vec<String> = (3..3 + columns.len()).map(|n| format!("?{n}")).collect();
+    let updates: Vec<String> = std::iter::once(&slot_column)
+        .chain(&names)
+        .map(|name| format!("{name} = excluded.{name}"))
+        .collect();
+    let sql = format!(
+        "INSERT INTO pools (address, {slot_column}, {})
+         VALUES (?1, ?2, {})
+         ON CONFLICT (address) DO UPDATE SET {}
+         WHERE excluded.{slot_column} >= pools.{slot_column}",
+        names.join(", "),
+        placeholders.join(", "),
+        updates.join(", ")
+    );
+    let pool = key(pool);
+    let mut params: Vec<&dyn ToSql> = vec![&pool, &slot];
+    params.extend(columns.iter().map(|(_, value)| *value));
+    db.prepare_cached(&sql)?.execute(&params[..])?;
+    Ok(())
+}
+
+/// `lp_supply` is `None` for swaps that don't report it, which keep the
+/// pool's last known supply.
+fn update_reserves(
+    db: &Connection,
+    pool: &Pubkey,
+    slot: u64,
+    (reserve_a, reserve_b, lp_supply): (u64, u64, Option<u64>),
+    spot_price: u128,
+) -> Result<()> {
+    let spot_price = spot_price.to_string();
+    let mut columns: Vec<(&str, &dyn ToSql)> = vec![
+        ("reserve_a", &reserve_a),
+        ("reserve_b", &reserve_b),
+        ("spot_price", &spot_price),
+    ];
+    if let Some(lp_supply) = &lp_supply {
+        columns.push(("lp_supply", lp_supply));
+    }
+    update_pool(db, pool, "reserves_slot", slot, &columns)
+}
+
+fn admin_action(
+    db: &Connection,
+    indexed: &IndexedEvent,
+    pool: &Pubkey,
+    action: &str,
+    admin: &Pubkey,
+    details: serde_json::Value,
+) -> Result<()> {
+    upsert(
+        db,
+        "admin_actions",
+        indexed,
+        &[
+            ("pool", &key(pool)),
+            ("action", &action),
+            ("admin", &key(admin)),
+            ("details", &details.to_string()),
+        ],
+    )
+}
@@ -0,0 +1,199 @@
+//! The parts of a `getTransaction` response the indexer reads, and the
+//! events they carry.
+//!
+//! Responses are taken in the `json` encoding, as returned by RPC or as
+//! recorded in a fixture. Unknown fields are ignored.
+
+use amm_sdk::{parse_cpi_events, parse_events, AmmEvent};
+use anchor_lang::prelude::Pubkey;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmedTransaction {
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub transaction: Transaction,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Transaction {
+    pub signatures: Vec<String>,
+    pub message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Message {
+    pub account_keys: Vec<String>,
+    pub instructions: Vec<CompiledInstruction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    /// Set when the transaction failed. Nothing a failed transaction logged
+    /// or invoked took effect.
+    pub err: Option<serde_json::Value>,
+    pub log_messages: Option<Vec<String>>,
+    /// Missing from old or trimmed responses; events then come from the logs.
+    pub inner_instructions: Option<Vec<InnerInstructions>>,
+    /// Addresses loaded from lookup tables by a v0 transaction. They follow
+    /// the message's own keys when resolving indices.
+    pub loaded_addresses: Option<LoadedAddresses>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoadedAddresses {
+    pub writable: Vec<String>,
+    pub readonly: Vec<String>,
+}
+
+/// The CPIs made by one top-level instruction.
+#[derive(Debug, Deserialize)]
+pub struct InnerInstructions {
+    pub index: u32,
+    pub instructions: Vec<CompiledInstruction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompiledInstruction {
+    pub program_id_index: u8,
+    pub accounts: Vec<u8>,
+    /// Base58.
+    pub data: String,
+}
+
+/// An event and where it was emitted. `(signature, instruction_index,
+/// event_index)` identifies it whether it was read from the logs or from the
+/// inner instructions.
+pub struct IndexedEvent {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    /// The top-level instruction that emitted the event.
+    pub instruction_index: u32,
+    /// Position among the events that instruction emitted.
+    pub event_index: u32,
+    pub event: AmmEvent,
+}
+
+impl ConfirmedTransaction {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("decode transaction")
+    }
+
+    pub fn signature(&self) -> Result<&str> {
+        self.transaction
+            .signatures
+            .first()
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("transaction has no signature"))
+    }
+
+    /// Every amm event the transaction emitted, in order; none if it failed.
+    ///
+    /// Each top-level instruction's events are read from its event CPIs, or
+    /// from its logs if it made none. Transactions from before the program
+    /// emitted events through CPI only have the logs, which the RPC node may
+    /// have truncated.
+    pub fn events(&self) -> Result<Vec<IndexedEvent>> {
+        if self.meta.err.is_some() {
+            return Ok(Vec::new());
+        }
+        let signature = self.signature()?;
+        let keys = self.account_keys()?;
+        let inner = self.meta.inner_instructions.as_deref().unwrap_or_default();
+        let logs = self.logs_by_instruction();
+
+        let mut events = Vec::new();
+        for index in 0..self.transaction.message.instructions.len() as u32 {
+            let mut emitted = match inner.iter().find(|group| group.index == index) {
+                Some(group) => cpi_events(&keys, &group.instructions)?,
+                None => Vec::new(),
+            };
+            if emitted.is_empty() {
+                if let Some(logs) = logs.get(index as usize).copied() {
+                    emitted = parse_events(logs);
+                }
+            }
+            events.extend(emitted.into_iter().enumerate().map(|(event_index, event)| {
+                IndexedEvent {
+                    signature: signature.to_string(),
+                    slot: self.slot,
+                    block_time: self.block_time,
+                    instruction_index: index,
+                    event_index: event_index as u32,
+                    event,
+                }
+            }));
+        }
+        Ok(events)
+    }
+
+    fn account_keys(&self) -> Result<Vec<Pubkey>> {
+        let loaded = self
+            .meta
+            .loaded_addresses
+            .iter()
+            .flat_map(|loaded| loaded.writable.iter().chain(&loaded.readonly));
+        self.transaction
+            .message
+            .account_keys
+            .iter()
+            .chain(loaded)
+            .map(|key| {
+                key.parse()
+                    .with_context(|| format!("bad account key {key}"))
+            })
+            .collect()
+    }
+
+    /// The log lines of each top-level instruction, which begin at its
+    /// depth-one `invoke`.
+    fn logs_by_instruction(&self) -> Vec<&[String]> {
+        let logs = self.meta.log_messages.as_deref().unwrap_or_default();
+        let starts: Vec<usize> = logs
+            .iter()
+            .enumerate()
+            .filter(|(_, log)| log.starts_with("Program ") && log.ends_with(" invoke [1]"))
+            .map(|(position, _)| position)
+            .collect();
+        starts
+            .iter()
+            .enumerate()
+            .map(|(n, &start)| {
+                let end = starts.get(n + 1).copied().unwrap_or(logs.len());
+                &logs[start..end]
+            })
+            .collect()
+    }
+}
+
+fn cpi_events(keys: &[Pubkey], instructions: &[CompiledInstruction]) -> Result<Vec<AmmEvent>> {
+    let decoded = instructions
+        .iter()
+        .map(|instruction| {
+            let program_id = keys
+                .get(instruction.program_id_index as usize)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "program index {} out of range",
+                        instruction.program_id_index
+                    )
+                })?;
+            let data = bs58::decode(&instruction.data)
+                .into_vec()
+                .context("decode instruction data")?;
+            Ok((program_id, data))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(parse_cpi_events(
+        decoded
+            .iter()
+            .map(|(program_id, data)| (*program_id, &data[..])),
+    ))
+}
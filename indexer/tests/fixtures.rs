@@ -0,0 +1,224 @@
+//! Indexing recorded `getTransaction` responses from one pool's history:
+//!
+//! - `initialize`, `deposit`: the pool is created and seeded with 1_000_000 A
+//!   and 4_000_000 B.
+//! - `swap_logs`: a 10_000 A swap, recorded with logs only.
+//! - `single_sided_cpi`, `single_sided_logs`: the same 50_000 A single-sided
+//!   deposit, recorded with its event CPIs and with logs only.
+//! - `admin`: withdraw protocol fees to the admin, pause and hand over the
+//!   admin key, as three instructions of one transaction.
+//! - `failed_swap_logs`: a swap whose transaction failed in a later
+//!   instruction.
+
+use std::{fs, path::PathBuf};
+
+use amm_indexer::{ConfirmedTransaction, Store};
+use rusqlite::Connection;
+
+fn fixture(name: &str) -> ConfirmedTransaction {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{name}.json"));
+    ConfirmedTransaction::from_json(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+fn index(store: &mut Store, names: &[&str]) {
+    for name in names {
+        store.index(&fixture(name)).unwrap();
+    }
+}
+
+fn count(db: &Connection, table: &str) -> i64 {
+    db.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+        row.get(0)
+    })
+    .unwrap()
+}
+
+fn dump(db: &Connection, table: &str) -> Vec<String> {
+    let mut statement = db
+        .prepare(&format!("SELECT * FROM {table} ORDER BY 1, 2, 3"))
+        .unwrap();
+    let columns = statement.column_count();
+    statement
+        .query_map([], |row| {
+            (0..columns)
+                .map(|i| row.get_ref(i).map(|value| format!("{value:?}")))
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .map(|values| values.join("|"))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap()
+}
+
+/// `(reserve_a, reserve_b, lp_supply, reserves_slot)` of the only pool.
+fn pool_reserves(db: &Connection) -> (i64, i64, i64, i64) {
+    db.query_row(
+        "SELECT reserve_a, reserve_b, lp_supply, reserves_slot FROM pools",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )
+    .unwrap()
+}
+
+#[test]
+fn indexes_a_pool_history() {
+    let mut store = Store::open_in_memory().unwrap();
+    index(
+        &mut store,
+        &[
+            "initialize",
+            "deposit",
+            "swap_logs",
+            "single_sided_cpi",
+            "admin",
+        ],
+    );
+    let db = store.connection();
+
+    assert_eq!(count(db, "pools"), 1);
+    assert_eq!(count(db, "deposits"), 2);
+    assert_eq!(count(db, "withdrawals"), 0);
+    let swaps: Vec<(i64, String, i64, i64)> = db
+        .prepare("SELECT slot, direction, amount_in, event_index FROM swaps ORDER BY slot")
+        .unwrap()
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert_eq!(swaps.len(), 2);
+    assert_eq!(swaps[0], (105, "a_to_b".to_string(), 10_000, 0));
+    // The swap leg of the single-sided deposit comes before its deposit leg.
+    assert_eq!(
+        (swaps[1].0, &swaps[1].1[..], swaps[1].3),
+        (107, "a_to_b", 0)
+    );
+    let deposit_index: i64 = db
+        .query_row(
+            "SELECT event_index FROM deposits WHERE slot = 107",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(deposit_index, 1);
+    // Plain swaps don't report LP supply; the single-sided swap leg does.
+    let swap_lp_supply: Vec<Option<i64>> = db
+        .prepare("SELECT lp_supply FROM swaps ORDER BY slot")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert!(matches!(swap_lp_supply[..], [None, Some(_)]));
+
+    // The pool's reserves are those after the last deposit leg.
+    let (reserve_a, reserve_b, lp_supply): (i64, i64, i64) = db
+        .query_row(
+            "SELECT reserve_a, reserve_b, lp_supply FROM deposits WHERE slot = 107",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(pool_reserves(db), (reserve_a, reserve_b, lp_supply, 107));
+    // Every A paid in stays in the pool but the protocol's cut.
+    let protocol_fees: i64 = db
+        .query_row("SELECT SUM(protocol_fee) FROM swaps", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(reserve_a, 1_000_000 + 10_000 + 50_000 - protocol_fees);
+    // The pool's totals are those carried by the last swap.
+    let (swap_count, volume_a, protocol_fees_a): (String, String, String) = db
+        .query_row(
+            "SELECT swap_count, volume_a, protocol_fees_a FROM pools",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    let volume: i64 = db
+        .query_row("SELECT SUM(amount_in) FROM swaps", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(
+        (swap_count, volume_a, protocol_fees_a),
+        (
+            "2".to_string(),
+            volume.to_string(),
+            protocol_fees.to_string()
+        )
+    );
+
+    let actions: Vec<(i64, String)> = db
+        .prepare("SELECT instruction_index, action FROM admin_actions ORDER BY instruction_index")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert_eq!(
+        actions,
+        vec![(1, "set_pause".to_string()), (2, "set_admin".to_string())]
+    );
+    let (recipient, withdrawn_a): (String, i64) = db
+        .query_row(
+            "SELECT fee_recipient, amount_a FROM protocol_fee_withdrawals
+             WHERE instruction_index = 0",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(withdrawn_a, protocol_fees);
+    let (paused, admin, new_admin, fee_recipient): (bool, String, String, String) = db
+        .query_row(
+            "SELECT paused, pools.admin, json_extract(details, '$.new_admin'), fee_recipient
+             FROM pools, admin_actions WHERE action = 'set_admin'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap();
+    assert!(paused);
+    assert_eq!(admin, new_admin);
+    // Handing over the admin key leaves the fees going to the old admin.
+    assert_eq!(fee_recipient, recipient);
+    assert_ne!(fee_recipient, admin);
+}
+
+#[test]
+fn reindexing_is_idempotent_across_sources() {
+    let mut from_cpi = Store::open_in_memory().unwrap();
+    index(&mut from_cpi, &["single_sided_cpi"]);
+    let mut from_logs = Store::open_in_memory().unwrap();
+    index(&mut from_logs, &["single_sided_logs"]);
+
+    let tables = ["pools", "swaps", "deposits"];
+    let snapshot = |store: &Store| -> Vec<Vec<String>> {
+        tables.map(|t| dump(store.connection(), t)).to_vec()
+    };
+    let expected = snapshot(&from_cpi);
+    assert_eq!(expected[1].len(), 1);
+    assert_eq!(expected[2].len(), 1);
+    assert_eq!(snapshot(&from_logs), expected);
+
+    index(&mut from_cpi, &["single_sided_logs", "single_sided_cpi"]);
+    assert_eq!(snapshot(&from_cpi), expected);
+}
+
+#[test]
+fn failed_transactions_are_skipped() {
+    let mut store = Store::open_in_memory().unwrap();
+    let failed = fixture("failed_swap_logs");
+    assert_eq!(store.index(&failed).unwrap(), 0);
+    assert_eq!(count(store.connection(), "swaps"), 0);
+    assert_eq!(count(store.connection(), "pools"), 0);
+}
+
+#[test]
+fn older_slots_do_not_roll_pool_state_back() {
+    let mut store = Store::open_in_memory().unwrap();
+    index(&mut store, &["single_sided_cpi"]);
+    let latest = pool_reserves(store.connection());
+    // Backfilling an earlier swap records it without touching the pool.
+    index(&mut store, &["swap_logs"]);
+    assert_eq!(count(store.connection(), "swaps"), 2);
+    assert_eq!(pool_reserves(store.connection()), latest);
+}
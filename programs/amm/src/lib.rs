@@ -1,12 +1,36 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke;
+use anchor_lang::{Discriminator, ZeroCopy};
 use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, Transfer};
 
+pub mod math;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+
+use math::{integer_sqrt, mul_div_ceil, mul_div_floor, Q64x64};
+
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
 const FEE_BPS: u16 = 30; // 0.3%
 const PROTOCOL_FEE_BPS: u16 = 5; // 0.05% to protocol, rest to LPs
+const FLASH_FEE_BPS: u16 = 9; // 0.09%, split like the swap fee
 const BPS_DENOMINATOR: u64 = 10_000;
 
+/// Emits `$event` through a self-CPI signed by the event authority, so it
+/// lands in the transaction's inner instructions where log truncation can't
+/// drop it. With the `log-events` feature it is also written to the logs, for
+/// indexers that still read them.
+macro_rules! emit_event {
+    ($ctx:expr, $event:expr) => {{
+        let event = $event;
+        let ctx = &$ctx;
+        #[cfg(feature = "log-events")]
+        emit!(event);
+        emit_cpi!(event);
+    }};
+}
+
 #[program]
 pub mod amm {
     use super::*;
@@ -26,25 +50,34 @@ pub mod amm {
         pool.fee_vault_a = ctx.accounts.fee_vault_a.key();
         pool.fee_vault_b = ctx.accounts.fee_vault_b.key();
         pool.admin = ctx.accounts.admin.key();
-        pool.bump = *ctx.bumps.get("pool").ok_or(AmmError::MissingBump)?;
+        pool.fee_recipient = ctx.accounts.admin.key();
+        pool.bump = ctx.bumps.pool;
         pool.fee_bps = FEE_BPS;
         pool.protocol_fee_bps = PROTOCOL_FEE_BPS;
         pool.paused = false;
-
-        emit!(InitializeEvent {
-            pool: pool.key(),
-            mint_a: pool.mint_a,
-            mint_b: pool.mint_b,
-            lp_mint: pool.lp_mint,
-            vault_a: pool.vault_a,
-            vault_b: pool.vault_b,
-            fee_vault_a: pool.fee_vault_a,
-            fee_vault_b: pool.fee_vault_b,
-            fee_bps: pool.fee_bps,
-            protocol_fee_bps: pool.protocol_fee_bps,
-            admin: pool.admin,
-            paused: pool.paused,
-        });
+        pool.flash_fee_bps = FLASH_FEE_BPS;
+        pool.max_referral_fee_bps = 0;
+        pool.referral_fee_mode = ReferralFeeMode::FromProtocolFee;
+
+        emit_event!(
+            ctx,
+            InitializeEvent {
+                pool: pool.key(),
+                mint_a: pool.mint_a,
+                mint_b: pool.mint_b,
+                lp_mint: pool.lp_mint,
+                vault_a: pool.vault_a,
+                vault_b: pool.vault_b,
+                fee_vault_a: pool.fee_vault_a,
+                fee_vault_b: pool.fee_vault_b,
+                fee_bps: pool.fee_bps,
+                protocol_fee_bps: pool.protocol_fee_bps,
+                admin: pool.admin,
+                fee_recipient: pool.fee_recipient,
+                paused: pool.paused,
+                flash_fee_bps: pool.flash_fee_bps,
+            }
+        );
         Ok(())
     }
 
@@ -57,46 +90,17 @@ pub mod amm {
         require!(!ctx.accounts.pool.paused, AmmError::PoolPaused);
         require!(amount_a > 0 && amount_b > 0, AmmError::InvalidAmount);
 
-        let reserve_a = ctx.accounts.vault_a.amount;
-        let reserve_b = ctx.accounts.vault_b.amount;
-        let total_lp = ctx.accounts.lp_mint.supply;
-
-        let (used_a, used_b, lp_to_mint) = if total_lp == 0 {
-            let product = (amount_a as u128)
-                .checked_mul(amount_b as u128)
-                .ok_or(AmmError::MathOverflow)?;
-            let lp = integer_sqrt(product);
-            (amount_a, amount_b, lp)
-        } else {
-            require!(reserve_a > 0 && reserve_b > 0, AmmError::InsufficientLiquidity);
-
-            // Accept imbalanced deposits and mint LP from the limiting side.
-            let ideal_b = (amount_a as u128)
-                .checked_mul(reserve_b as u128)
-                .ok_or(AmmError::MathOverflow)?
-                .checked_div(reserve_a as u128)
-                .ok_or(AmmError::MathOverflow)? as u64;
-            if amount_b >= ideal_b {
-                let lp = (amount_a as u128)
-                    .checked_mul(total_lp as u128)
-                    .ok_or(AmmError::MathOverflow)?
-                    .checked_div(reserve_a as u128)
-                    .ok_or(AmmError::MathOverflow)? as u64;
-                (amount_a, ideal_b, lp)
-            } else {
-                let ideal_a = (amount_b as u128)
-                    .checked_mul(reserve_a as u128)
-                    .ok_or(AmmError::MathOverflow)?
-                    .checked_div(reserve_b as u128)
-                    .ok_or(AmmError::MathOverflow)? as u64;
-                let lp = (amount_b as u128)
-                    .checked_mul(total_lp as u128)
-                    .ok_or(AmmError::MathOverflow)?
-                    .checked_div(reserve_b as u128)
-                    .ok_or(AmmError::MathOverflow)? as u64;
-                (ideal_a, amount_b, lp)
-            }
-        };
+        let DepositQuote {
+            amount_a: used_a,
+            amount_b: used_b,
+            lp_amount: lp_to_mint,
+        } = compute_deposit(
+            amount_a,
+            amount_b,
+            ctx.accounts.vault_a.amount,
+            ctx.accounts.vault_b.amount,
+            ctx.accounts.lp_mint.supply,
+        )?;
 
         require!(lp_to_mint >= min_lp_out, AmmError::SlippageExceeded);
 
@@ -109,13 +113,171 @@ pub mod amm {
             lp_to_mint,
         )?;
 
-        emit!(DepositEvent {
-            user: ctx.accounts.user.key(),
-            pool: ctx.accounts.pool.key(),
-            amount_a_in: used_a,
-            amount_b_in: used_b,
-            lp_minted: lp_to_mint,
-        });
+        let reserve_a = ctx.accounts.vault_a.amount + used_a;
+        let reserve_b = ctx.accounts.vault_b.amount + used_b;
+        emit_event!(
+            ctx,
+            DepositEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                amount_a_in: used_a,
+                amount_b_in: used_b,
+                lp_minted: lp_to_mint,
+                reserve_a,
+                reserve_b,
+                lp_supply: ctx.accounts.lp_mint.supply + lp_to_mint,
+                spot_price: event_spot_price(reserve_a, reserve_b),
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Mints exactly `lp_amount` LP, taking the token amounts it requires
+    /// rounded up, and fails if either exceeds its maximum.
+    pub fn deposit_exact_lp(
+        ctx: Context<DepositLiquidity>,
+        lp_amount: u64,
+        max_amount_a: u64,
+        max_amount_b: u64,
+    ) -> Result<DepositQuote> {
+        require!(!ctx.accounts.pool.paused, AmmError::PoolPaused);
+        require!(lp_amount > 0, AmmError::InvalidAmount);
+
+        let quote = compute_deposit_exact_lp(
+            lp_amount,
+            ctx.accounts.vault_a.amount,
+            ctx.accounts.vault_b.amount,
+            ctx.accounts.lp_mint.supply,
+        )?;
+        require!(quote.amount_a <= max_amount_a, AmmError::SlippageExceeded);
+        require!(quote.amount_b <= max_amount_b, AmmError::SlippageExceeded);
+
+        token::transfer(ctx.accounts.transfer_to_vault_a_ctx(), quote.amount_a)?;
+        token::transfer(ctx.accounts.transfer_to_vault_b_ctx(), quote.amount_b)?;
+
+        let pool_seeds = ctx.accounts.pool.signer_seeds();
+        token::mint_to(
+            ctx.accounts.mint_lp_ctx().with_signer(&[&pool_seeds]),
+            lp_amount,
+        )?;
+
+        let reserve_a = ctx.accounts.vault_a.amount + quote.amount_a;
+        let reserve_b = ctx.accounts.vault_b.amount + quote.amount_b;
+        emit_event!(
+            ctx,
+            DepositEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                amount_a_in: quote.amount_a,
+                amount_b_in: quote.amount_b,
+                lp_minted: lp_amount,
+                reserve_a,
+                reserve_b,
+                lp_supply: ctx.accounts.lp_mint.supply + lp_amount,
+                spot_price: event_spot_price(reserve_a, reserve_b),
+            }
+        );
+
+        Ok(quote)
+    }
+
+    /// Deposits a single token by swapping the optimal portion through the pool
+    /// and adding the result as balanced liquidity in the same instruction.
+    pub fn deposit_single_sided(
+        ctx: Context<DepositSingleSided>,
+        amount_in: u64,
+        side: TokenSide,
+        min_lp_out: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.pool.paused, AmmError::PoolPaused);
+        require!(amount_in > 0, AmmError::InvalidAmount);
+
+        let direction = side.swap_direction();
+        let SingleSidedDeposit { swap, deposit } = compute_deposit_single_sided(
+            amount_in,
+            side,
+            ctx.accounts.vault_a.amount,
+            ctx.accounts.vault_b.amount,
+            ctx.accounts.lp_mint.supply,
+            ctx.accounts.pool.fee_bps,
+            ctx.accounts.pool.protocol_fee_bps,
+        )?;
+        require!(deposit.lp_amount > 0, AmmError::InvalidAmount);
+        require!(deposit.lp_amount >= min_lp_out, AmmError::SlippageExceeded);
+
+        // The swapped-out tokens never leave the vault: they are deposited
+        // straight back, so only the input side moves.
+        let deposit_in = match side {
+            TokenSide::A => deposit.amount_a,
+            TokenSide::B => deposit.amount_b,
+        };
+        let amount_to_vault = swap
+            .amount_in
+            .checked_sub(swap.protocol_fee)
+            .ok_or(AmmError::MathOverflow)?
+            .checked_add(deposit_in)
+            .ok_or(AmmError::MathOverflow)?;
+        token::transfer(ctx.accounts.transfer_to_vault_ctx(side), amount_to_vault)?;
+        if swap.protocol_fee > 0 {
+            token::transfer(
+                ctx.accounts.transfer_to_fee_vault_ctx(side),
+                swap.protocol_fee,
+            )?;
+        }
+
+        let pool_seeds = ctx.accounts.pool.signer_seeds();
+        token::mint_to(
+            ctx.accounts.mint_lp_ctx().with_signer(&[&pool_seeds]),
+            deposit.lp_amount,
+        )?;
+        ctx.accounts.pool.stats.record_swap(direction, &swap)?;
+
+        let (reserve_in, reserve_out) = match side {
+            TokenSide::A => (ctx.accounts.vault_a.amount, ctx.accounts.vault_b.amount),
+            TokenSide::B => (ctx.accounts.vault_b.amount, ctx.accounts.vault_a.amount),
+        };
+        let (reserve_a, reserve_b) = post_swap_reserves(direction, reserve_in, reserve_out, &swap)?;
+        let lp_supply = ctx.accounts.lp_mint.supply;
+        emit_event!(
+            ctx,
+            SwapEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                amount_in: swap.amount_in,
+                amount_out: swap.amount_out,
+                direction,
+                protocol_fee: swap.protocol_fee,
+                amount_in_unfilled: 0,
+                lp_fee: swap.lp_fee,
+                reserve_a,
+                reserve_b,
+                lp_supply: Some(lp_supply),
+                spot_price: event_spot_price(reserve_a, reserve_b),
+                stats: ctx.accounts.pool.stats,
+                referrer: None,
+                referral_fee: 0,
+            }
+        );
+        let (reserve_a, reserve_b) = (reserve_a + deposit.amount_a, reserve_b + deposit.amount_b);
+        emit_event!(
+            ctx,
+            DepositEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                amount_a_in: deposit.amount_a,
+                amount_b_in: deposit.amount_b,
+                lp_minted: deposit.lp_amount,
+                reserve_a,
+                reserve_b,
+                lp_supply: lp_supply + deposit.lp_amount,
+                spot_price: event_spot_price(reserve_a, reserve_b),
+            }
+        );
 
         Ok(())
     }
@@ -128,21 +290,14 @@ pub mod amm {
     ) -> Result<()> {
         require!(lp_amount > 0, AmmError::InvalidAmount);
 
-        let reserve_a = ctx.accounts.vault_a.amount;
-        let reserve_b = ctx.accounts.vault_b.amount;
-        let total_lp = ctx.accounts.lp_mint.supply;
-        require!(total_lp > 0, AmmError::InsufficientLiquidity);
-
-        let amount_a = (lp_amount as u128)
-            .checked_mul(reserve_a as u128)
-            .ok_or(AmmError::MathOverflow)?
-            .checked_div(total_lp as u128)
-            .ok_or(AmmError::MathOverflow)? as u64;
-        let amount_b = (lp_amount as u128)
-            .checked_mul(reserve_b as u128)
-            .ok_or(AmmError::MathOverflow)?
-            .checked_div(total_lp as u128)
-            .ok_or(AmmError::MathOverflow)? as u64;
+        let WithdrawQuote {
+            amount_a, amount_b, ..
+        } = compute_withdraw(
+            lp_amount,
+            ctx.accounts.vault_a.amount,
+            ctx.accounts.vault_b.amount,
+            ctx.accounts.lp_mint.supply,
+        )?;
 
         require!(amount_a >= min_amount_a, AmmError::SlippageExceeded);
         require!(amount_b >= min_amount_b, AmmError::SlippageExceeded);
@@ -159,109 +314,480 @@ pub mod amm {
             amount_b,
         )?;
 
-        emit!(WithdrawEvent {
-            user: ctx.accounts.user.key(),
-            pool: ctx.accounts.pool.key(),
-            lp_burned: lp_amount,
-            amount_a_out: amount_a,
-            amount_b_out: amount_b,
-        });
+        let reserve_a = ctx.accounts.vault_a.amount - amount_a;
+        let reserve_b = ctx.accounts.vault_b.amount - amount_b;
+        emit_event!(
+            ctx,
+            WithdrawEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                lp_burned: lp_amount,
+                amount_a_out: amount_a,
+                amount_b_out: amount_b,
+                reserve_a,
+                reserve_b,
+                lp_supply: ctx.accounts.lp_mint.supply - lp_amount,
+                spot_price: event_spot_price(reserve_a, reserve_b),
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Burns LP and pays out a single token, swapping the other side of the
+    /// pro-rata withdrawal through the pool with the usual fees.
+    pub fn withdraw_single_sided(
+        ctx: Context<WithdrawSingleSided>,
+        lp_amount: u64,
+        side: TokenSide,
+        min_amount_out: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.pool.paused, AmmError::PoolPaused);
+        require!(lp_amount > 0, AmmError::InvalidAmount);
+
+        let SingleSidedWithdraw {
+            withdraw,
+            swap,
+            amount_out,
+        } = compute_withdraw_single_sided(
+            lp_amount,
+            side,
+            ctx.accounts.vault_a.amount,
+            ctx.accounts.vault_b.amount,
+            ctx.accounts.lp_mint.supply,
+            ctx.accounts.pool.fee_bps,
+            ctx.accounts.pool.protocol_fee_bps,
+        )?;
+        require!(amount_out >= min_amount_out, AmmError::SlippageExceeded);
+
+        token::burn(ctx.accounts.burn_lp_ctx(), lp_amount)?;
+
+        // The other side is swapped inside the vaults: only its protocol fee
+        // leaves, and the bought tokens are paid out with the withdrawal.
+        let pool_seeds = ctx.accounts.pool.signer_seeds();
+        if swap.protocol_fee > 0 {
+            token::transfer(
+                ctx.accounts
+                    .transfer_to_fee_vault_ctx(side)
+                    .with_signer(&[&pool_seeds]),
+                swap.protocol_fee,
+            )?;
+        }
+        token::transfer(
+            ctx.accounts
+                .transfer_to_user_ctx(side)
+                .with_signer(&[&pool_seeds]),
+            amount_out,
+        )?;
+        // The withdrawn tokens of the other side are sold back to the pool.
+        let direction = side.swap_direction().reverse();
+        ctx.accounts.pool.stats.record_swap(direction, &swap)?;
+
+        let lp_supply = ctx.accounts.lp_mint.supply - lp_amount;
+        let (reserve_in, reserve_out) = match side {
+            TokenSide::A => (
+                ctx.accounts.vault_b.amount - withdraw.amount_b,
+                ctx.accounts.vault_a.amount - withdraw.amount_a,
+            ),
+            TokenSide::B => (
+                ctx.accounts.vault_a.amount - withdraw.amount_a,
+                ctx.accounts.vault_b.amount - withdraw.amount_b,
+            ),
+        };
+        let (reserve_a, reserve_b) = post_swap_reserves(direction, reserve_in, reserve_out, &swap)?;
+        emit_event!(
+            ctx,
+            SwapEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                amount_in: swap.amount_in,
+                amount_out: swap.amount_out,
+                direction,
+                protocol_fee: swap.protocol_fee,
+                amount_in_unfilled: 0,
+                lp_fee: swap.lp_fee,
+                reserve_a,
+                reserve_b,
+                lp_supply: Some(lp_supply),
+                spot_price: event_spot_price(reserve_a, reserve_b),
+                stats: ctx.accounts.pool.stats,
+                referrer: None,
+                referral_fee: 0,
+            }
+        );
+        let (amount_a_out, amount_b_out) = match side {
+            TokenSide::A => (amount_out, 0),
+            TokenSide::B => (0, amount_out),
+        };
+        emit_event!(
+            ctx,
+            WithdrawEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                lp_burned: lp_amount,
+                amount_a_out,
+                amount_b_out,
+                reserve_a,
+                reserve_b,
+                lp_supply,
+                spot_price: event_spot_price(reserve_a, reserve_b),
+            }
+        );
 
         Ok(())
     }
 
+    /// Pays `referral_fee_bps` of `amount_in` to the `referrer` token
+    /// account, if one is passed, out of the protocol fee or on top of
+    /// `amount_in` as the pool's `referral_fee_mode` says.
     pub fn swap(
         ctx: Context<Swap>,
         amount_in: u64,
         min_amount_out: u64,
         direction: SwapDirection,
+        referral_fee_bps: u16,
     ) -> Result<()> {
         require!(!ctx.accounts.pool.paused, AmmError::PoolPaused);
         require!(amount_in > 0, AmmError::InvalidAmount);
 
-        let (reserve_in, reserve_out) = match direction {
-            SwapDirection::AtoB => (ctx.accounts.vault_a.amount, ctx.accounts.vault_b.amount),
-            SwapDirection::BtoA => (ctx.accounts.vault_b.amount, ctx.accounts.vault_a.amount),
-        };
-        require!(reserve_in > 0 && reserve_out > 0, AmmError::InsufficientLiquidity);
+        let (reserve_in, reserve_out) = ctx.accounts.reserves(direction);
+        let quote = compute_swap(
+            amount_in,
+            reserve_in,
+            reserve_out,
+            ctx.accounts.pool.fee_bps,
+            ctx.accounts.pool.protocol_fee_bps,
+        )?;
+        require!(
+            quote.amount_out >= min_amount_out,
+            AmmError::SlippageExceeded
+        );
+        let referral_fee = ctx
+            .accounts
+            .referral_fee(direction, amount_in, referral_fee_bps)?;
+
+        let protocol_fee = ctx.accounts.settle(direction, &quote, referral_fee)?;
+        let referrer = ctx.accounts.referrer.as_ref().map(Key::key);
+
+        let (reserve_a, reserve_b) =
+            post_swap_reserves(direction, reserve_in, reserve_out, &quote)?;
+        emit_event!(
+            ctx,
+            SwapEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                amount_in,
+                amount_out: quote.amount_out,
+                direction,
+                protocol_fee,
+                amount_in_unfilled: 0,
+                lp_fee: quote.lp_fee,
+                reserve_a,
+                reserve_b,
+                lp_supply: None,
+                spot_price: event_spot_price(reserve_a, reserve_b),
+                stats: ctx.accounts.pool.stats,
+                referrer,
+                referral_fee,
+            }
+        );
 
-        let fee_bps = ctx.accounts.pool.fee_bps;
-        let protocol_fee_bps = ctx.accounts.pool.protocol_fee_bps;
-        require!(protocol_fee_bps <= fee_bps, AmmError::InvalidFee);
+        Ok(())
+    }
 
-        let protocol_fee = (amount_in as u128)
-            .checked_mul(protocol_fee_bps as u128)
-            .ok_or(AmmError::MathOverflow)?
-            .checked_div(BPS_DENOMINATOR as u128)
-            .ok_or(AmmError::MathOverflow)? as u64;
-        let amount_in_to_pool = amount_in
-            .checked_sub(protocol_fee)
-            .ok_or(AmmError::MathOverflow)?;
-        let lp_fee_bps = fee_bps
-            .checked_sub(protocol_fee_bps)
-            .ok_or(AmmError::MathOverflow)?;
+    /// Swaps as much of `amount_in` as possible without pushing the post-trade
+    /// price (output per input, Q64.64) below `min_price`. Only the filled
+    /// input is taken from the user.
+    pub fn swap_with_price_limit(
+        ctx: Context<Swap>,
+        amount_in: u64,
+        min_price: u128,
+        direction: SwapDirection,
+    ) -> Result<()> {
+        require!(!ctx.accounts.pool.paused, AmmError::PoolPaused);
+        require!(amount_in > 0, AmmError::InvalidAmount);
 
-        let amount_out = quote_swap_out(amount_in_to_pool, reserve_in, reserve_out, lp_fee_bps)?;
-        require!(amount_out >= min_amount_out, AmmError::SlippageExceeded);
-        require!(amount_out < reserve_out, AmmError::InsufficientLiquidity);
+        let (reserve_in, reserve_out) = ctx.accounts.reserves(direction);
+        let quote = compute_swap_to_price_limit(
+            amount_in,
+            reserve_in,
+            reserve_out,
+            ctx.accounts.pool.fee_bps,
+            ctx.accounts.pool.protocol_fee_bps,
+            Q64x64::from_raw(min_price),
+        )?;
 
-        match direction {
-            SwapDirection::AtoB => {
-                require!(
-                    ctx.accounts.user_source.mint == ctx.accounts.mint_a.key()
-                        && ctx.accounts.user_destination.mint == ctx.accounts.mint_b.key(),
-                    AmmError::InvalidSwapMint
-                );
-                token::transfer(ctx.accounts.transfer_to_vault_in_ctx(), amount_in_to_pool)?;
-                if protocol_fee > 0 {
-                    token::transfer(
-                        ctx.accounts.transfer_to_fee_vault_ctx(),
-                        protocol_fee,
-                    )?;
-                }
-                let pool_seeds = ctx.accounts.pool.signer_seeds();
-                token::transfer(
-                    ctx.accounts
-                        .transfer_to_user_out_ctx()
-                        .with_signer(&[&pool_seeds]),
-                    amount_out,
-                )?;
-            }
-            SwapDirection::BtoA => {
-                require!(
-                    ctx.accounts.user_source.mint == ctx.accounts.mint_b.key()
-                        && ctx.accounts.user_destination.mint == ctx.accounts.mint_a.key(),
-                    AmmError::InvalidSwapMint
-                );
-                token::transfer(ctx.accounts.transfer_to_vault_in_ctx(), amount_in_to_pool)?;
-                if protocol_fee > 0 {
-                    token::transfer(
-                        ctx.accounts.transfer_to_fee_vault_ctx(),
-                        protocol_fee,
-                    )?;
-                }
-                let pool_seeds = ctx.accounts.pool.signer_seeds();
-                token::transfer(
-                    ctx.accounts
-                        .transfer_to_user_out_ctx()
-                        .with_signer(&[&pool_seeds]),
-                    amount_out,
-                )?;
+        ctx.accounts.settle(direction, &quote, 0)?;
+
+        let (reserve_a, reserve_b) =
+            post_swap_reserves(direction, reserve_in, reserve_out, &quote)?;
+        emit_event!(
+            ctx,
+            SwapEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                amount_in: quote.amount_in,
+                amount_out: quote.amount_out,
+                direction,
+                protocol_fee: quote.protocol_fee,
+                amount_in_unfilled: amount_in - quote.amount_in,
+                lp_fee: quote.lp_fee,
+                reserve_a,
+                reserve_b,
+                lp_supply: None,
+                spot_price: event_spot_price(reserve_a, reserve_b),
+                stats: ctx.accounts.pool.stats,
+                referrer: None,
+                referral_fee: 0,
             }
+        );
+
+        Ok(())
+    }
+
+    pub fn deposit_liquidity_with_deadline(
+        ctx: Context<DepositLiquidity>,
+        amount_a: u64,
+        amount_b: u64,
+        min_lp_out: u64,
+        deadline: Option<Deadline>,
+    ) -> Result<()> {
+        deposit_liquidity_guarded(ctx, amount_a, amount_b, min_lp_out, deadline, None, None)
+    }
+
+    /// Like `deposit_liquidity`, but fails with `NonProportionalDeposit` when
+    /// the supplied ratio is more than `max_ratio_deviation_bps` away from the
+    /// pool's instead of trimming the excess side.
+    pub fn deposit_liquidity_strict(
+        ctx: Context<DepositLiquidity>,
+        amount_a: u64,
+        amount_b: u64,
+        min_lp_out: u64,
+        max_ratio_deviation_bps: u16,
+    ) -> Result<()> {
+        require!(amount_a > 0 && amount_b > 0, AmmError::InvalidAmount);
+        if ctx.accounts.lp_mint.supply > 0 {
+            let deviation_bps = ratio_deviation_bps(
+                amount_a,
+                amount_b,
+                ctx.accounts.vault_a.amount,
+                ctx.accounts.vault_b.amount,
+            )?;
+            require!(
+                deviation_bps <= max_ratio_deviation_bps as u64,
+                AmmError::NonProportionalDeposit
+            );
+        }
+        deposit_liquidity(ctx, amount_a, amount_b, min_lp_out)
+    }
+
+    /// Like `deposit_liquidity`, but fails after `deadline` or unless the
+    /// pool's spot price of A in B (Q64.64) lies within the optional
+    /// `min_price`/`max_price` bounds. Guards left as `None` are skipped.
+    pub fn deposit_liquidity_guarded(
+        ctx: Context<DepositLiquidity>,
+        amount_a: u64,
+        amount_b: u64,
+        min_lp_out: u64,
+        deadline: Option<Deadline>,
+        min_price: Option<u128>,
+        max_price: Option<u128>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        // An empty pool has no price to manipulate: the first deposit sets it.
+        if ctx.accounts.lp_mint.supply > 0 {
+            check_price_range(
+                ctx.accounts.vault_a.amount,
+                ctx.accounts.vault_b.amount,
+                min_price,
+                max_price,
+            )?;
         }
+        deposit_liquidity(ctx, amount_a, amount_b, min_lp_out)
+    }
+
+    /// Like `withdraw_liquidity`, but fails after `deadline` or unless the
+    /// pool's spot price of A in B (Q64.64) lies within the optional
+    /// `min_price`/`max_price` bounds. Unlike the deposit side, the price
+    /// check is not skipped on an empty pool, which has no price to satisfy
+    /// a bound.
+    pub fn withdraw_liquidity_guarded(
+        ctx: Context<WithdrawLiquidity>,
+        lp_amount: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
+        deadline: Option<Deadline>,
+        min_price: Option<u128>,
+        max_price: Option<u128>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        check_price_range(
+            ctx.accounts.vault_a.amount,
+            ctx.accounts.vault_b.amount,
+            min_price,
+            max_price,
+        )?;
+        withdraw_liquidity(ctx, lp_amount, min_amount_a, min_amount_b)
+    }
+
+    pub fn withdraw_liquidity_with_deadline(
+        ctx: Context<WithdrawLiquidity>,
+        lp_amount: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
+        deadline: Option<Deadline>,
+    ) -> Result<()> {
+        withdraw_liquidity_guarded(
+            ctx,
+            lp_amount,
+            min_amount_a,
+            min_amount_b,
+            deadline,
+            None,
+            None,
+        )
+    }
+
+    pub fn swap_with_deadline(
+        ctx: Context<Swap>,
+        amount_in: u64,
+        min_amount_out: u64,
+        direction: SwapDirection,
+        deadline: Option<Deadline>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        swap(ctx, amount_in, min_amount_out, direction, 0)
+    }
+
+    /// `swap` for routers: reads the pool in place instead of deserializing
+    /// it and takes only the accounts the trade touches, checked against the
+    /// pool's stored keys.
+    pub fn swap_v2(
+        ctx: Context<SwapV2>,
+        amount_in: u64,
+        min_amount_out: u64,
+        direction: SwapDirection,
+    ) -> Result<()> {
+        let pool = *ctx.accounts.pool.load()?;
+        require!(pool.paused == 0, AmmError::PoolPaused);
+        require!(amount_in > 0, AmmError::InvalidAmount);
+        ctx.accounts.check_accounts(&pool, direction)?;
 
-        emit!(SwapEvent {
-            user: ctx.accounts.user.key(),
-            pool: ctx.accounts.pool.key(),
+        let (reserve_in, reserve_out) =
+            (ctx.accounts.vault_in.amount, ctx.accounts.vault_out.amount);
+        let quote = compute_swap(
             amount_in,
-            amount_out,
-            direction,
-            protocol_fee,
-        });
+            reserve_in,
+            reserve_out,
+            pool.fee_bps,
+            pool.protocol_fee_bps,
+        )?;
+        require!(
+            quote.amount_out >= min_amount_out,
+            AmmError::SlippageExceeded
+        );
+
+        ctx.accounts.settle(&pool, &quote)?;
+        let mut stats = pool.stats();
+        stats.record_swap(direction, &quote)?;
+        ctx.accounts.pool.load_mut()?.set_stats(&stats);
+
+        let (reserve_a, reserve_b) =
+            post_swap_reserves(direction, reserve_in, reserve_out, &quote)?;
+        emit_event!(
+            ctx,
+            SwapEvent {
+                version: EVENT_VERSION,
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                amount_in,
+                amount_out: quote.amount_out,
+                direction,
+                protocol_fee: quote.protocol_fee,
+                amount_in_unfilled: 0,
+                lp_fee: quote.lp_fee,
+                reserve_a,
+                reserve_b,
+                lp_supply: Some(ctx.accounts.lp_mint.supply),
+                spot_price: event_spot_price(reserve_a, reserve_b),
+                stats,
+                referrer: None,
+                referral_fee: 0,
+            }
+        );
 
         Ok(())
     }
 
+    pub fn quote_swap(
+        ctx: Context<Quote>,
+        amount_in: u64,
+        direction: SwapDirection,
+    ) -> Result<SwapQuote> {
+        require!(amount_in > 0, AmmError::InvalidAmount);
+        let (reserve_in, reserve_out) = ctx.accounts.reserves(direction);
+        compute_swap(
+            amount_in,
+            reserve_in,
+            reserve_out,
+            ctx.accounts.pool.fee_bps,
+            ctx.accounts.pool.protocol_fee_bps,
+        )
+    }
+
+    pub fn quote_swap_exact_out(
+        ctx: Context<Quote>,
+        amount_out: u64,
+        direction: SwapDirection,
+    ) -> Result<SwapQuote> {
+        require!(amount_out > 0, AmmError::InvalidAmount);
+        let (reserve_in, reserve_out) = ctx.accounts.reserves(direction);
+        compute_swap_exact_out(
+            amount_out,
+            reserve_in,
+            reserve_out,
+            ctx.accounts.pool.fee_bps,
+            ctx.accounts.pool.protocol_fee_bps,
+        )
+    }
+
+    pub fn quote_deposit(
+        ctx: Context<Quote>,
+        amount_a: u64,
+        amount_b: u64,
+    ) -> Result<DepositQuote> {
+        require!(amount_a > 0 && amount_b > 0, AmmError::InvalidAmount);
+        compute_deposit(
+            amount_a,
+            amount_b,
+            ctx.accounts.vault_a.amount,
+            ctx.accounts.vault_b.amount,
+            ctx.accounts.lp_mint.supply,
+        )
+    }
+
+    pub fn quote_withdraw(ctx: Context<Quote>, lp_amount: u64) -> Result<WithdrawQuote> {
+        require!(lp_amount > 0, AmmError::InvalidAmount);
+        compute_withdraw(
+            lp_amount,
+            ctx.accounts.vault_a.amount,
+            ctx.accounts.vault_b.amount,
+            ctx.accounts.lp_mint.supply,
+        )
+    }
+
+    pub fn pool_stats(ctx: Context<Quote>) -> Result<PoolStats> {
+        Ok(ctx.accounts.pool.stats)
+    }
+
+    /// Pays protocol fees to the pool's fee recipient. Anyone may call it:
+    /// the destination is fixed by the pool. Not available once the pool has
+    /// a fee distribution.
     pub fn withdraw_protocol_fees(
         ctx: Context<WithdrawProtocolFees>,
         amount_a: u64,
@@ -286,7 +812,7 @@ pub mod amm {
         if amount_a > 0 {
             token::transfer(
                 ctx.accounts
-                    .transfer_fee_to_admin_a_ctx()
+                    .transfer_fee_to_treasury_a_ctx()
                     .with_signer(&[&pool_seeds]),
                 amount_a,
             )?;
@@ -294,30 +820,334 @@ pub mod amm {
         if amount_b > 0 {
             token::transfer(
                 ctx.accounts
-                    .transfer_fee_to_admin_b_ctx()
+                    .transfer_fee_to_treasury_b_ctx()
                     .with_signer(&[&pool_seeds]),
                 amount_b,
             )?;
         }
 
-        emit!(ProtocolFeeWithdrawEvent {
-            admin: ctx.accounts.admin.key(),
-            pool: ctx.accounts.pool.key(),
-            amount_a,
-            amount_b,
-        });
+        emit_event!(
+            ctx,
+            ProtocolFeeWithdrawEvent {
+                pool: ctx.accounts.pool.key(),
+                fee_recipient: ctx.accounts.pool.fee_recipient,
+                amount_a,
+                amount_b,
+            }
+        );
 
         Ok(())
     }
 
-    pub fn set_pause(ctx: Context<SetPause>, paused: bool) -> Result<()> {
-        ctx.accounts.pool.paused = paused;
+    pub fn flash_loan<'info>(
+        ctx: Context<'_, '_, '_, 'info, FlashLoan<'info>>,
+        amount_a: u64,
+        amount_b: u64,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.pool.paused, AmmError::PoolPaused);
+        require!(amount_a > 0 || amount_b > 0, AmmError::InvalidAmount);
+        // The runtime only allows direct self-recursion, so any receiver other
+        // than this program cannot re-enter the pool while the loan is out.
+        require!(
+            ctx.accounts.receiver_program.key() != crate::ID,
+            AmmError::InvalidFlashReceiver
+        );
 
-        emit!(PauseEvent {
-            admin: ctx.accounts.admin.key(),
-            pool: ctx.accounts.pool.key(),
-            paused,
-        });
+        let reserve_a = ctx.accounts.vault_a.amount;
+        let reserve_b = ctx.accounts.vault_b.amount;
+        require!(
+            amount_a <= reserve_a && amount_b <= reserve_b,
+            AmmError::InsufficientLiquidity
+        );
+
+        let pool = &ctx.accounts.pool;
+        let fee_a = flash_fee(amount_a, pool.flash_fee_bps)?;
+        let fee_b = flash_fee(amount_b, pool.flash_fee_bps)?;
+        let protocol_fee_a = flash_protocol_fee(fee_a, pool.fee_bps, pool.protocol_fee_bps)?;
+        let protocol_fee_b = flash_protocol_fee(fee_b, pool.fee_bps, pool.protocol_fee_bps)?;
+
+        let pool_seeds = ctx.accounts.pool.signer_seeds();
+        if amount_a > 0 {
+            token::transfer(
+                ctx.accounts
+                    .transfer_to_borrower_a_ctx()
+                    .with_signer(&[&pool_seeds]),
+                amount_a,
+            )?;
+        }
+        if amount_b > 0 {
+            token::transfer(
+                ctx.accounts
+                    .transfer_to_borrower_b_ctx()
+                    .with_signer(&[&pool_seeds]),
+                amount_b,
+            )?;
+        }
+
+        let receiver_ix = Instruction {
+            program_id: ctx.accounts.receiver_program.key(),
+            accounts: ctx
+                .remaining_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: account.key(),
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data,
+        };
+        let mut receiver_infos = ctx.remaining_accounts.to_vec();
+        receiver_infos.push(ctx.accounts.receiver_program.to_account_info());
+        invoke(&receiver_ix, &receiver_infos)?;
+
+        // Each vault must hold at least its starting reserve plus the fee, so
+        // k can only grow across the loan.
+        ctx.accounts.vault_a.reload()?;
+        ctx.accounts.vault_b.reload()?;
+        let required_a = reserve_a.checked_add(fee_a).ok_or(AmmError::MathOverflow)?;
+        let required_b = reserve_b.checked_add(fee_b).ok_or(AmmError::MathOverflow)?;
+        require!(
+            ctx.accounts.vault_a.amount >= required_a,
+            AmmError::FlashLoanNotRepaid
+        );
+        require!(
+            ctx.accounts.vault_b.amount >= required_b,
+            AmmError::FlashLoanNotRepaid
+        );
+
+        if protocol_fee_a > 0 {
+            token::transfer(
+                ctx.accounts
+                    .transfer_to_fee_vault_a_ctx()
+                    .with_signer(&[&pool_seeds]),
+                protocol_fee_a,
+            )?;
+        }
+        if protocol_fee_b > 0 {
+            token::transfer(
+                ctx.accounts
+                    .transfer_to_fee_vault_b_ctx()
+                    .with_signer(&[&pool_seeds]),
+                protocol_fee_b,
+            )?;
+        }
+
+        emit_event!(
+            ctx,
+            FlashLoanEvent {
+                user: ctx.accounts.user.key(),
+                pool: ctx.accounts.pool.key(),
+                receiver_program: ctx.accounts.receiver_program.key(),
+                amount_a,
+                amount_b,
+                fee_a,
+                fee_b,
+                protocol_fee_a,
+                protocol_fee_b,
+            }
+        );
+
+        Ok(())
+    }
+
+    pub fn set_flash_fee(ctx: Context<SetFlashFee>, flash_fee_bps: u16) -> Result<()> {
+        require!(
+            (flash_fee_bps as u64) < BPS_DENOMINATOR,
+            AmmError::InvalidFee
+        );
+        let old_flash_fee_bps = ctx.accounts.pool.flash_fee_bps;
+        ctx.accounts.pool.flash_fee_bps = flash_fee_bps;
+
+        emit_event!(
+            ctx,
+            FlashFeeUpdatedEvent {
+                admin: ctx.accounts.admin.key(),
+                pool: ctx.accounts.pool.key(),
+                old_flash_fee_bps,
+                new_flash_fee_bps: flash_fee_bps,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Caps the `referral_fee_bps` a swap may pay and sets where it comes
+    /// from. A cap taken from the protocol fee can't exceed it.
+    pub fn set_referral_fee(
+        ctx: Context<SetReferralFee>,
+        max_referral_fee_bps: u16,
+        referral_fee_mode: ReferralFeeMode,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let limit = match referral_fee_mode {
+            ReferralFeeMode::FromProtocolFee => pool.protocol_fee_bps as u64,
+            ReferralFeeMode::OnTop => BPS_DENOMINATOR - 1,
+        };
+        require!(max_referral_fee_bps as u64 <= limit, AmmError::InvalidFee);
+        pool.max_referral_fee_bps = max_referral_fee_bps;
+        pool.referral_fee_mode = referral_fee_mode;
+
+        emit_event!(
+            ctx,
+            ReferralFeeUpdatedEvent {
+                admin: ctx.accounts.admin.key(),
+                pool: ctx.accounts.pool.key(),
+                max_referral_fee_bps,
+                referral_fee_mode,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Sends future protocol fee withdrawals to `fee_recipient`'s token
+    /// accounts.
+    pub fn set_fee_recipient(ctx: Context<SetFeeRecipient>, fee_recipient: Pubkey) -> Result<()> {
+        let old_fee_recipient = ctx.accounts.pool.fee_recipient;
+        ctx.accounts.pool.fee_recipient = fee_recipient;
+
+        emit_event!(
+            ctx,
+            FeeRecipientUpdatedEvent {
+                admin: ctx.accounts.admin.key(),
+                pool: ctx.accounts.pool.key(),
+                old_fee_recipient,
+                new_fee_recipient: fee_recipient,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Grows a pool created by an older version of the program to
+    /// `Pool::LEN`. Fields are only ever appended to `Pool`, so the ones the
+    /// account is missing come out zeroed; the fee recipient then defaults
+    /// to the admin, as in `initialize`. The admin pays the extra rent.
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+        let pool = ctx.accounts.pool.to_account_info();
+        require!(pool.data_len() < Pool::LEN, AmmError::PoolUpToDate);
+
+        let rent = Rent::get()?.minimum_balance(Pool::LEN);
+        let top_up = rent.saturating_sub(pool.lamports());
+        if top_up > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.admin.to_account_info(),
+                        to: pool.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        pool.realloc(Pool::LEN, true)?;
+
+        let mut state = Pool::try_deserialize(&mut &pool.try_borrow_data()?[..])?;
+        require_keys_eq!(
+            state.admin,
+            ctx.accounts.admin.key(),
+            ErrorCode::ConstraintRaw
+        );
+        if state.fee_recipient == Pubkey::default() {
+            state.fee_recipient = state.admin;
+        }
+        state.try_serialize(&mut &mut pool.try_borrow_mut_data()?[..])?;
+
+        Ok(())
+    }
+
+    /// Splits future protocol fees among up to `FeeDistribution::MAX_RECIPIENTS`
+    /// owners, by shares in bps summing to 10,000. From then on the fees are
+    /// paid out by `distribute_protocol_fees` rather than withdrawn.
+    pub fn set_fee_distribution(
+        ctx: Context<SetFeeDistribution>,
+        recipients: Vec<FeeShare>,
+    ) -> Result<()> {
+        check_fee_shares(&recipients)?;
+        let fee_distribution = &mut ctx.accounts.fee_distribution;
+        fee_distribution.pool = ctx.accounts.pool.key();
+        fee_distribution.bump = ctx.bumps.fee_distribution;
+        fee_distribution.recipients = recipients.clone();
+
+        emit_event!(
+            ctx,
+            FeeDistributionUpdatedEvent {
+                admin: ctx.accounts.admin.key(),
+                pool: ctx.accounts.pool.key(),
+                recipients,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Pays out both fee vaults to the pool's fee distribution, pro rata to
+    /// the shares. Anyone may call it, passing each recipient's A and B token
+    /// accounts as remaining accounts in the distribution's order. Rounding
+    /// dust stays in the vaults for the next payout.
+    pub fn distribute_protocol_fees<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DistributeProtocolFees<'info>>,
+    ) -> Result<()> {
+        let recipients = &ctx.accounts.fee_distribution.recipients;
+        require!(
+            ctx.remaining_accounts.len() == 2 * recipients.len(),
+            AmmError::InvalidFeeRecipient
+        );
+        let balance_a = ctx.accounts.fee_vault_a.amount;
+        let balance_b = ctx.accounts.fee_vault_b.amount;
+        require!(balance_a > 0 || balance_b > 0, AmmError::InvalidAmount);
+
+        let pool_seeds = ctx.accounts.pool.signer_seeds();
+        for (share, treasuries) in recipients.iter().zip(ctx.remaining_accounts.chunks(2)) {
+            let amount_a = mul_div_floor(balance_a, share.share_bps as u64, BPS_DENOMINATOR)?;
+            let amount_b = mul_div_floor(balance_b, share.share_bps as u64, BPS_DENOMINATOR)?;
+            for (treasury, fee_vault, amount) in [
+                (&treasuries[0], &ctx.accounts.fee_vault_a, amount_a),
+                (&treasuries[1], &ctx.accounts.fee_vault_b, amount_b),
+            ] {
+                let account = Account::<TokenAccount>::try_from(treasury)?;
+                require!(
+                    account.owner == share.owner && account.mint == fee_vault.mint,
+                    AmmError::InvalidFeeRecipient
+                );
+                if amount > 0 {
+                    token::transfer(
+                        ctx.accounts
+                            .transfer_fee_ctx(fee_vault, treasury)
+                            .with_signer(&[&pool_seeds]),
+                        amount,
+                    )?;
+                }
+            }
+
+            emit_event!(
+                ctx,
+                ProtocolFeeWithdrawEvent {
+                    pool: ctx.accounts.pool.key(),
+                    fee_recipient: share.owner,
+                    amount_a,
+                    amount_b,
+                }
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn set_pause(ctx: Context<SetPause>, paused: bool) -> Result<()> {
+        ctx.accounts.pool.paused = paused;
+
+        emit_event!(
+            ctx,
+            PauseEvent {
+                admin: ctx.accounts.admin.key(),
+                pool: ctx.accounts.pool.key(),
+                paused,
+            }
+        );
 
         Ok(())
     }
@@ -326,16 +1156,20 @@ pub mod amm {
         let old_admin = ctx.accounts.pool.admin;
         ctx.accounts.pool.admin = ctx.accounts.new_admin.key();
 
-        emit!(AdminUpdatedEvent {
-            pool: ctx.accounts.pool.key(),
-            old_admin,
-            new_admin: ctx.accounts.new_admin.key(),
-        });
+        emit_event!(
+            ctx,
+            AdminUpdatedEvent {
+                pool: ctx.accounts.pool.key(),
+                old_admin,
+                new_admin: ctx.accounts.new_admin.key(),
+            }
+        );
 
         Ok(())
     }
 }
 
+#[event_cpi]
 #[derive(Accounts)]
 pub struct Initialize<'info> {
     #[account(mut)]
@@ -409,6 +1243,7 @@ pub struct Initialize<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
 pub struct DepositLiquidity<'info> {
     #[account(mut)]
@@ -470,6 +1305,84 @@ pub struct DepositLiquidity<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(amount_in: u64, side: TokenSide)]
+pub struct DepositSingleSided<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = mint_a,
+        has_one = mint_b,
+        has_one = vault_a,
+        has_one = vault_b,
+        has_one = lp_mint,
+        has_one = fee_vault_a,
+        has_one = fee_vault_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    pub mint_a: Account<'info, Mint>,
+    pub mint_b: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = vault_a.key() == pool.vault_a,
+        constraint = vault_a.mint == mint_a.key(),
+        constraint = vault_a.owner == pool.key()
+    )]
+    pub vault_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_b.key() == pool.vault_b,
+        constraint = vault_b.mint == mint_b.key(),
+        constraint = vault_b.owner == pool.key()
+    )]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = lp_mint.key() == pool.lp_mint)]
+    pub lp_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == pool.fee_vault_a,
+        constraint = fee_vault_a.mint == mint_a.key(),
+        constraint = fee_vault_a.owner == pool.key()
+    )]
+    pub fee_vault_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == pool.fee_vault_b,
+        constraint = fee_vault_b.mint == mint_b.key(),
+        constraint = fee_vault_b.owner == pool.key()
+    )]
+    pub fee_vault_b: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_source.owner == user.key(),
+        constraint = user_source.mint == match side {
+            TokenSide::A => mint_a.key(),
+            TokenSide::B => mint_b.key(),
+        } @ AmmError::InvalidSwapMint
+    )]
+    pub user_source: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_lp.owner == user.key(),
+        constraint = user_lp.mint == lp_mint.key()
+    )]
+    pub user_lp: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
 #[derive(Accounts)]
 pub struct WithdrawLiquidity<'info> {
     #[account(mut)]
@@ -531,8 +1444,10 @@ pub struct WithdrawLiquidity<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-pub struct Swap<'info> {
+#[instruction(lp_amount: u64, side: TokenSide)]
+pub struct WithdrawSingleSided<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
@@ -542,6 +1457,7 @@ pub struct Swap<'info> {
         has_one = mint_b,
         has_one = vault_a,
         has_one = vault_b,
+        has_one = lp_mint,
         has_one = fee_vault_a,
         has_one = fee_vault_b
     )]
@@ -566,6 +1482,9 @@ pub struct Swap<'info> {
     )]
     pub vault_b: Account<'info, TokenAccount>,
 
+    #[account(mut, constraint = lp_mint.key() == pool.lp_mint)]
+    pub lp_mint: Account<'info, Mint>,
+
     #[account(
         mut,
         constraint = fee_vault_a.key() == pool.fee_vault_a,
@@ -584,37 +1503,60 @@ pub struct Swap<'info> {
 
     #[account(
         mut,
-        constraint = user_source.owner == user.key()
+        constraint = user_destination.owner == user.key(),
+        constraint = user_destination.mint == match side {
+            TokenSide::A => mint_a.key(),
+            TokenSide::B => mint_b.key(),
+        } @ AmmError::InvalidSwapMint
     )]
-    pub user_source: Account<'info, TokenAccount>,
+    pub user_destination: Account<'info, TokenAccount>,
 
     #[account(
         mut,
-        constraint = user_destination.owner == user.key()
+        constraint = user_lp.owner == user.key(),
+        constraint = user_lp.mint == lp_mint.key()
     )]
-    pub user_destination: Account<'info, TokenAccount>,
+    pub user_lp: Account<'info, TokenAccount>,
 
     pub token_program: Program<'info, Token>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-pub struct WithdrawProtocolFees<'info> {
+pub struct Swap<'info> {
     #[account(mut)]
-    pub admin: Signer<'info>,
+    pub user: Signer<'info>,
 
     #[account(
         mut,
         has_one = mint_a,
         has_one = mint_b,
+        has_one = vault_a,
+        has_one = vault_b,
         has_one = fee_vault_a,
-        has_one = fee_vault_b,
-        constraint = pool.admin == admin.key()
+        has_one = fee_vault_b
     )]
     pub pool: Account<'info, Pool>,
 
     pub mint_a: Account<'info, Mint>,
     pub mint_b: Account<'info, Mint>,
 
+    #[account(
+        mut,
+        constraint = vault_a.key() == pool.vault_a,
+        constraint = vault_a.mint == mint_a.key(),
+        constraint = vault_a.owner == pool.key()
+    )]
+    pub vault_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_b.key() == pool.vault_b,
+        constraint = vault_b.mint == mint_b.key(),
+        constraint = vault_b.owner == pool.key()
+    )]
+    pub vault_b: Account<'info, TokenAccount>,
+
     #[account(
         mut,
         constraint = fee_vault_a.key() == pool.fee_vault_a,
@@ -633,45 +1575,305 @@ pub struct WithdrawProtocolFees<'info> {
 
     #[account(
         mut,
-        constraint = admin_ata_a.owner == admin.key(),
-        constraint = admin_ata_a.mint == mint_a.key()
+        constraint = user_source.owner == user.key()
     )]
-    pub admin_ata_a: Account<'info, TokenAccount>,
+    pub user_source: Account<'info, TokenAccount>,
 
     #[account(
         mut,
-        constraint = admin_ata_b.owner == admin.key(),
-        constraint = admin_ata_b.mint == mint_b.key()
+        constraint = user_destination.owner == user.key()
     )]
-    pub admin_ata_b: Account<'info, TokenAccount>,
+    pub user_destination: Account<'info, TokenAccount>,
+
+    /// Receives `swap`'s referral fee, in the input token.
+    #[account(mut)]
+    pub referrer: Option<Account<'info, TokenAccount>>,
 
     pub token_program: Program<'info, Token>,
 }
 
+/// The accounts of a `swap_v2` in one direction. Vaults are named by side of
+/// the trade rather than by token.
+#[event_cpi]
 #[derive(Accounts)]
-pub struct SetPause<'info> {
+pub struct SwapV2<'info> {
+    pub user: Signer<'info>,
+
     #[account(mut)]
-    pub admin: Signer<'info>,
+    pub pool: AccountLoader<'info, ZeroCopyPool>,
 
-    #[account(mut, constraint = pool.admin == admin.key())]
-    pub pool: Account<'info, Pool>,
-}
+    #[account(
+        mut,
+        constraint = user_source.owner == user.key()
+    )]
+    pub user_source: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_destination.owner == user.key()
+    )]
+    pub user_destination: Account<'info, TokenAccount>,
 
-#[derive(Accounts)]
-pub struct SetAdmin<'info> {
     #[account(mut)]
-    pub admin: Signer<'info>,
+    pub vault_in: Account<'info, TokenAccount>,
 
-    pub new_admin: Signer<'info>,
+    #[account(mut)]
+    pub vault_out: Account<'info, TokenAccount>,
 
-    #[account(mut, constraint = pool.admin == admin.key())]
-    pub pool: Account<'info, Pool>,
+    /// CHECK: must be the pool's fee vault for the input token, checked by
+    /// `check_accounts`; SPL Token checks it on transfer.
+    #[account(mut)]
+    pub fee_vault_in: UncheckedAccount<'info>,
+
+    #[account(constraint = lp_mint.key() == pool.load()?.lp_mint)]
+    pub lp_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
 }
 
-#[account]
-pub struct Pool {
-    pub mint_a: Pubkey,
-    pub mint_b: Pubkey,
+#[derive(Accounts)]
+pub struct Quote<'info> {
+    #[account(has_one = vault_a, has_one = vault_b, has_one = lp_mint)]
+    pub pool: Account<'info, Pool>,
+
+    pub vault_a: Account<'info, TokenAccount>,
+    pub vault_b: Account<'info, TokenAccount>,
+    pub lp_mint: Account<'info, Mint>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct WithdrawProtocolFees<'info> {
+    #[account(
+        mut,
+        has_one = mint_a,
+        has_one = mint_b,
+        has_one = fee_vault_a,
+        has_one = fee_vault_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// CHECK: must not exist; a pool with a fee distribution pays its fees
+    /// through `distribute_protocol_fees`.
+    #[account(
+        seeds = [b"fee_distribution", pool.key().as_ref()],
+        bump,
+        constraint = fee_distribution.data_is_empty() @ AmmError::FeesAreDistributed
+    )]
+    pub fee_distribution: UncheckedAccount<'info>,
+
+    pub mint_a: Account<'info, Mint>,
+    pub mint_b: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == pool.fee_vault_a,
+        constraint = fee_vault_a.mint == mint_a.key(),
+        constraint = fee_vault_a.owner == pool.key()
+    )]
+    pub fee_vault_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == pool.fee_vault_b,
+        constraint = fee_vault_b.mint == mint_b.key(),
+        constraint = fee_vault_b.owner == pool.key()
+    )]
+    pub fee_vault_b: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = treasury_a.owner == pool.fee_recipient,
+        constraint = treasury_a.mint == mint_a.key()
+    )]
+    pub treasury_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = treasury_b.owner == pool.fee_recipient,
+        constraint = treasury_b.mint == mint_b.key()
+    )]
+    pub treasury_b: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct FlashLoan<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        has_one = mint_a,
+        has_one = mint_b,
+        has_one = vault_a,
+        has_one = vault_b,
+        has_one = fee_vault_a,
+        has_one = fee_vault_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    pub mint_a: Account<'info, Mint>,
+    pub mint_b: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = vault_a.key() == pool.vault_a,
+        constraint = vault_a.mint == mint_a.key(),
+        constraint = vault_a.owner == pool.key()
+    )]
+    pub vault_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_b.key() == pool.vault_b,
+        constraint = vault_b.mint == mint_b.key(),
+        constraint = vault_b.owner == pool.key()
+    )]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == pool.fee_vault_a,
+        constraint = fee_vault_a.mint == mint_a.key(),
+        constraint = fee_vault_a.owner == pool.key()
+    )]
+    pub fee_vault_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == pool.fee_vault_b,
+        constraint = fee_vault_b.mint == mint_b.key(),
+        constraint = fee_vault_b.owner == pool.key()
+    )]
+    pub fee_vault_b: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = borrower_ata_a.mint == mint_a.key())]
+    pub borrower_ata_a: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = borrower_ata_b.mint == mint_b.key())]
+    pub borrower_ata_b: Account<'info, TokenAccount>,
+
+    /// CHECK: arbitrary program invoked with the remaining accounts; repayment
+    /// is verified against the vault balances afterwards.
+    #[account(executable)]
+    pub receiver_program: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetFlashFee<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(mut, constraint = pool.admin == admin.key())]
+    pub pool: Account<'info, Pool>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetReferralFee<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(mut, constraint = pool.admin == admin.key())]
+    pub pool: Account<'info, Pool>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetFeeRecipient<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(mut, constraint = pool.admin == admin.key())]
+    pub pool: Account<'info, Pool>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetFeeDistribution<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(constraint = pool.admin == admin.key())]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = FeeDistribution::LEN,
+        seeds = [b"fee_distribution", pool.key().as_ref()],
+        bump
+    )]
+    pub fee_distribution: Account<'info, FeeDistribution>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct DistributeProtocolFees<'info> {
+    #[account(has_one = fee_vault_a, has_one = fee_vault_b)]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        seeds = [b"fee_distribution", pool.key().as_ref()],
+        bump = fee_distribution.bump,
+        has_one = pool
+    )]
+    pub fee_distribution: Account<'info, FeeDistribution>,
+
+    #[account(mut)]
+    pub fee_vault_a: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub fee_vault_b: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetPause<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(mut, constraint = pool.admin == admin.key())]
+    pub pool: Account<'info, Pool>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetAdmin<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub new_admin: Signer<'info>,
+
+    #[account(mut, constraint = pool.admin == admin.key())]
+    pub pool: Account<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// CHECK: a `Pool` too short to load as one; `migrate_pool` grows it,
+    /// then checks its discriminator and admin.
+    #[account(mut, owner = crate::ID)]
+    pub pool: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[account]
+pub struct Pool {
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
     pub vault_a: Pubkey,
     pub vault_b: Pubkey,
     pub lp_mint: Pubkey,
@@ -682,27 +1884,283 @@ pub struct Pool {
     pub fee_bps: u16,
     pub protocol_fee_bps: u16,
     pub paused: bool,
+    pub flash_fee_bps: u16,
+    pub stats: PoolStats,
+    /// Most a swap may pay its referrer, in bps of `amount_in`.
+    pub max_referral_fee_bps: u16,
+    pub referral_fee_mode: ReferralFeeMode,
+    /// Owner of the token accounts protocol fees are withdrawn to.
+    pub fee_recipient: Pubkey,
 }
 
 impl Pool {
-    pub const LEN: usize = 8 + 32 * 8 + 1 + 2 + 2 + 1;
+    pub const LEN: usize = 8 + 32 * 8 + 1 + 2 + 2 + 1 + 2 + PoolStats::LEN + 2 + 1 + 32;
 
     pub fn signer_seeds(&self) -> [&[u8]; 4] {
         [
             b"pool",
             self.mint_a.as_ref(),
             self.mint_b.as_ref(),
-            &[self.bump],
+            std::slice::from_ref(&self.bump),
         ]
     }
 }
 
+/// Running totals of a pool's trading, returned by `pool_stats` and carried
+/// in every `SwapEvent`. All swaps count, including the swap legs of
+/// single-sided deposits and withdrawals; flash loans don't.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Swap input in A, fees included.
+    pub volume_a: u128,
+    /// Swap input in B, fees included.
+    pub volume_b: u128,
+    pub swap_count: u128,
+    /// Swap fees in A kept in the pool for LPs.
+    pub lp_fees_a: u128,
+    /// Swap fees in B kept in the pool for LPs.
+    pub lp_fees_b: u128,
+    /// Swap fees in A sent to the fee vault.
+    pub protocol_fees_a: u128,
+    /// Swap fees in B sent to the fee vault.
+    pub protocol_fees_b: u128,
+}
+
+impl PoolStats {
+    pub const LEN: usize = 16 * 7;
+
+    /// Adds a swap that took `quote.amount_in` in `direction`.
+    pub fn record_swap(&mut self, direction: SwapDirection, quote: &SwapQuote) -> Result<()> {
+        let (volume, lp_fees, protocol_fees) = match direction {
+            SwapDirection::AtoB => (
+                &mut self.volume_a,
+                &mut self.lp_fees_a,
+                &mut self.protocol_fees_a,
+            ),
+            SwapDirection::BtoA => (
+                &mut self.volume_b,
+                &mut self.lp_fees_b,
+                &mut self.protocol_fees_b,
+            ),
+        };
+        for (total, amount) in [
+            (volume, quote.amount_in),
+            (lp_fees, quote.lp_fee),
+            (protocol_fees, quote.protocol_fee),
+            (&mut self.swap_count, 1),
+        ] {
+            *total = total
+                .checked_add(amount as u128)
+                .ok_or(AmmError::MathOverflow)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `Pool` account read in place. Borsh lays `Pool` out without padding, so
+/// a packed struct with the same fields, `paused` and `referral_fee_mode` as
+/// bytes, maps its data directly and shares its discriminator.
+#[zero_copy]
+#[repr(C, packed)]
+pub struct ZeroCopyPool {
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub vault_a: Pubkey,
+    pub vault_b: Pubkey,
+    pub lp_mint: Pubkey,
+    pub fee_vault_a: Pubkey,
+    pub fee_vault_b: Pubkey,
+    pub admin: Pubkey,
+    pub bump: u8,
+    pub fee_bps: u16,
+    pub protocol_fee_bps: u16,
+    pub paused: u8,
+    pub flash_fee_bps: u16,
+    pub volume_a: u128,
+    pub volume_b: u128,
+    pub swap_count: u128,
+    pub lp_fees_a: u128,
+    pub lp_fees_b: u128,
+    pub protocol_fees_a: u128,
+    pub protocol_fees_b: u128,
+    pub max_referral_fee_bps: u16,
+    pub referral_fee_mode: u8,
+    pub fee_recipient: Pubkey,
+}
+
+const _: () = assert!(8 + std::mem::size_of::<ZeroCopyPool>() == Pool::LEN);
+
+impl Discriminator for ZeroCopyPool {
+    const DISCRIMINATOR: [u8; 8] = Pool::DISCRIMINATOR;
+}
+
+impl Owner for ZeroCopyPool {
+    fn owner() -> Pubkey {
+        crate::ID
+    }
+}
+
+impl ZeroCopy for ZeroCopyPool {}
+
+impl ZeroCopyPool {
+    pub fn signer_seeds(&self) -> [&[u8]; 4] {
+        [
+            b"pool",
+            self.mint_a.as_ref(),
+            self.mint_b.as_ref(),
+            std::slice::from_ref(&self.bump),
+        ]
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            volume_a: self.volume_a,
+            volume_b: self.volume_b,
+            swap_count: self.swap_count,
+            lp_fees_a: self.lp_fees_a,
+            lp_fees_b: self.lp_fees_b,
+            protocol_fees_a: self.protocol_fees_a,
+            protocol_fees_b: self.protocol_fees_b,
+        }
+    }
+
+    pub fn set_stats(&mut self, stats: &PoolStats) {
+        self.volume_a = stats.volume_a;
+        self.volume_b = stats.volume_b;
+        self.swap_count = stats.swap_count;
+        self.lp_fees_a = stats.lp_fees_a;
+        self.lp_fees_b = stats.lp_fees_b;
+        self.protocol_fees_a = stats.protocol_fees_a;
+        self.protocol_fees_b = stats.protocol_fees_b;
+    }
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
 pub enum SwapDirection {
     AtoB,
     BtoA,
 }
 
+impl SwapDirection {
+    pub fn reverse(self) -> SwapDirection {
+        match self {
+            SwapDirection::AtoB => SwapDirection::BtoA,
+            SwapDirection::BtoA => SwapDirection::AtoB,
+        }
+    }
+}
+
+/// One of the two pool tokens.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenSide {
+    A,
+    B,
+}
+
+impl TokenSide {
+    /// Direction of a swap that sells this side.
+    pub fn swap_direction(self) -> SwapDirection {
+        match self {
+            TokenSide::A => SwapDirection::AtoB,
+            TokenSide::B => SwapDirection::BtoA,
+        }
+    }
+}
+
+/// How a pool's protocol fees are split, set by `set_fee_distribution`.
+#[account]
+pub struct FeeDistribution {
+    pub pool: Pubkey,
+    pub bump: u8,
+    pub recipients: Vec<FeeShare>,
+}
+
+impl FeeDistribution {
+    pub const MAX_RECIPIENTS: usize = 8;
+    pub const LEN: usize = 8 + 32 + 1 + 4 + Self::MAX_RECIPIENTS * FeeShare::LEN;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeShare {
+    /// Owner of the token accounts this share is paid to.
+    pub owner: Pubkey,
+    /// Part of each payout, in bps.
+    pub share_bps: u16,
+}
+
+impl FeeShare {
+    pub const LEN: usize = 32 + 2;
+}
+
+/// Who pays a swap's referral fee.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReferralFeeMode {
+    /// The referrer gets part of the protocol fee; the user pays no more.
+    #[default]
+    FromProtocolFee,
+    /// The user pays the referral fee on top of `amount_in`.
+    OnTop,
+}
+
+/// Last moment at which a time-sensitive instruction may still execute.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Deadline {
+    /// Inclusive upper bound on `Clock::unix_timestamp`.
+    UnixTimestamp(i64),
+    /// Inclusive upper bound on `Clock::slot`.
+    MaxSlot(u64),
+}
+
+/// Result of `quote_swap` and `quote_swap_exact_out`, matching what `swap`
+/// would transfer against the same pool state.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapQuote {
+    /// Total input taken from the user, protocol fee included.
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// Portion of `amount_in` sent to the fee vault.
+    pub protocol_fee: u64,
+    /// Portion of `amount_in` kept in the pool for LPs.
+    pub lp_fee: u64,
+    /// Shortfall of `amount_out` against the spot price, fees included.
+    pub price_impact_bps: u64,
+}
+
+/// Result of `quote_deposit` and `deposit_exact_lp`: the token amounts taken
+/// for `lp_amount`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DepositQuote {
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub lp_amount: u64,
+}
+
+/// Split of a single-sided deposit into its internal swap and the balanced
+/// deposit that follows it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SingleSidedDeposit {
+    pub swap: SwapQuote,
+    pub deposit: DepositQuote,
+}
+
+/// Pro-rata withdrawal followed by a swap of the unwanted side, as paid out by
+/// `withdraw_single_sided`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SingleSidedWithdraw {
+    pub withdraw: WithdrawQuote,
+    pub swap: SwapQuote,
+    /// Total of the requested token paid to the user.
+    pub amount_out: u64,
+}
+
+/// Result of `quote_withdraw`: the amounts `withdraw_liquidity` would pay.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WithdrawQuote {
+    pub lp_amount: u64,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
 #[event]
 pub struct InitializeEvent {
     pub pool: Pubkey,
@@ -716,45 +2174,139 @@ pub struct InitializeEvent {
     pub fee_bps: u16,
     pub protocol_fee_bps: u16,
     pub admin: Pubkey,
+    pub fee_recipient: Pubkey,
     pub paused: bool,
+    pub flash_fee_bps: u16,
 }
 
+/// Layout version of `DepositEvent`, `WithdrawEvent` and `SwapEvent`, carried
+/// in their `version` field. Layouts from before the field existed are
+/// version 1; version 3 added `SwapEvent::stats`, version 4 its referral
+/// fields and version 5 made its `lp_supply` optional.
+pub const EVENT_VERSION: u8 = 5;
+
+// The pool state in `DepositEvent`, `WithdrawEvent` and `SwapEvent` is taken
+// after the event's own leg: a single-sided deposit's `SwapEvent` shows the
+// pool before the deposit, and its `DepositEvent` the final state. A
+// single-sided withdrawal's swap leg is its last, so both its events show the
+// final state, and its `WithdrawEvent`, emitted last, only the side paid out.
+
 #[event]
 pub struct DepositEvent {
+    pub version: u8,
     pub user: Pubkey,
     pub pool: Pubkey,
     pub amount_a_in: u64,
     pub amount_b_in: u64,
     pub lp_minted: u64,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    pub lp_supply: u64,
+    /// Price of A in B at `reserve_a`/`reserve_b`, Q64.64.
+    pub spot_price: u128,
 }
 
 #[event]
 pub struct WithdrawEvent {
+    pub version: u8,
     pub user: Pubkey,
     pub pool: Pubkey,
     pub lp_burned: u64,
     pub amount_a_out: u64,
     pub amount_b_out: u64,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    pub lp_supply: u64,
+    /// Price of A in B at `reserve_a`/`reserve_b`, Q64.64; zero once the pool
+    /// is empty.
+    pub spot_price: u128,
 }
 
 #[event]
 pub struct SwapEvent {
+    pub version: u8,
     pub user: Pubkey,
     pub pool: Pubkey,
     pub amount_in: u64,
     pub amount_out: u64,
     pub direction: SwapDirection,
+    /// Part of `amount_in` sent to the fee vault, after any referral fee
+    /// taken from it.
     pub protocol_fee: u64,
+    /// Input left unswapped by a price limit; zero for plain swaps.
+    pub amount_in_unfilled: u64,
+    /// Part of `amount_in` kept in the pool for LPs.
+    pub lp_fee: u64,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    /// `None` from `swap` and the swaps built on it, which don't take the LP
+    /// mint.
+    pub lp_supply: Option<u64>,
+    /// Price of A in B at `reserve_a`/`reserve_b`, Q64.64.
+    pub spot_price: u128,
+    /// The pool's totals including this swap.
+    pub stats: PoolStats,
+    pub referrer: Option<Pubkey>,
+    /// Paid to `referrer` in the input token, out of the protocol fee or on
+    /// top of `amount_in` per the pool's `referral_fee_mode`.
+    pub referral_fee: u64,
 }
 
+/// Emitted by `withdraw_protocol_fees`, and by `distribute_protocol_fees`
+/// once per recipient.
 #[event]
 pub struct ProtocolFeeWithdrawEvent {
-    pub admin: Pubkey,
     pub pool: Pubkey,
+    /// Owner of the token accounts the fees were paid to.
+    pub fee_recipient: Pubkey,
     pub amount_a: u64,
     pub amount_b: u64,
 }
 
+#[event]
+pub struct FlashLoanEvent {
+    pub user: Pubkey,
+    pub pool: Pubkey,
+    pub receiver_program: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub fee_a: u64,
+    pub fee_b: u64,
+    pub protocol_fee_a: u64,
+    pub protocol_fee_b: u64,
+}
+
+#[event]
+pub struct FlashFeeUpdatedEvent {
+    pub admin: Pubkey,
+    pub pool: Pubkey,
+    pub old_flash_fee_bps: u16,
+    pub new_flash_fee_bps: u16,
+}
+
+#[event]
+pub struct ReferralFeeUpdatedEvent {
+    pub admin: Pubkey,
+    pub pool: Pubkey,
+    pub max_referral_fee_bps: u16,
+    pub referral_fee_mode: ReferralFeeMode,
+}
+
+#[event]
+pub struct FeeRecipientUpdatedEvent {
+    pub admin: Pubkey,
+    pub pool: Pubkey,
+    pub old_fee_recipient: Pubkey,
+    pub new_fee_recipient: Pubkey,
+}
+
+#[event]
+pub struct FeeDistributionUpdatedEvent {
+    pub admin: Pubkey,
+    pub pool: Pubkey,
+    pub recipients: Vec<FeeShare>,
+}
+
 #[event]
 pub struct PauseEvent {
     pub admin: Pubkey,
@@ -804,6 +2356,55 @@ impl<'info> DepositLiquidity<'info> {
     }
 }
 
+impl<'info> DepositSingleSided<'info> {
+    fn transfer_to_vault_ctx(
+        &self,
+        side: TokenSide,
+    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let to = match side {
+            TokenSide::A => self.vault_a.to_account_info(),
+            TokenSide::B => self.vault_b.to_account_info(),
+        };
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.user_source.to_account_info(),
+                to,
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+
+    fn transfer_to_fee_vault_ctx(
+        &self,
+        side: TokenSide,
+    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let to = match side {
+            TokenSide::A => self.fee_vault_a.to_account_info(),
+            TokenSide::B => self.fee_vault_b.to_account_info(),
+        };
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.user_source.to_account_info(),
+                to,
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+
+    fn mint_lp_ctx(&self) -> CpiContext<'_, '_, '_, 'info, MintTo<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            MintTo {
+                mint: self.lp_mint.to_account_info(),
+                to: self.user_lp.to_account_info(),
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+}
+
 impl<'info> WithdrawLiquidity<'info> {
     fn burn_lp_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Burn<'info>> {
         CpiContext::new(
@@ -827,19 +2428,155 @@ impl<'info> WithdrawLiquidity<'info> {
         )
     }
 
-    fn transfer_to_user_b_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
-        CpiContext::new(
-            self.token_program.to_account_info(),
-            Transfer {
-                from: self.vault_b.to_account_info(),
-                to: self.user_ata_b.to_account_info(),
-                authority: self.pool.to_account_info(),
+    fn transfer_to_user_b_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.vault_b.to_account_info(),
+                to: self.user_ata_b.to_account_info(),
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+}
+
+impl<'info> WithdrawSingleSided<'info> {
+    fn burn_lp_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Burn<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Burn {
+                mint: self.lp_mint.to_account_info(),
+                from: self.user_lp.to_account_info(),
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+
+    fn transfer_to_fee_vault_ctx(
+        &self,
+        side: TokenSide,
+    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        // The fee is taken on the side being sold, i.e. the one not paid out.
+        let (from, to) = match side {
+            TokenSide::A => (
+                self.vault_b.to_account_info(),
+                self.fee_vault_b.to_account_info(),
+            ),
+            TokenSide::B => (
+                self.vault_a.to_account_info(),
+                self.fee_vault_a.to_account_info(),
+            ),
+        };
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from,
+                to,
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+
+    fn transfer_to_user_ctx(
+        &self,
+        side: TokenSide,
+    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let from = match side {
+            TokenSide::A => self.vault_a.to_account_info(),
+            TokenSide::B => self.vault_b.to_account_info(),
+        };
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from,
+                to: self.user_destination.to_account_info(),
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+}
+
+impl<'info> Swap<'info> {
+    fn reserves(&self, direction: SwapDirection) -> (u64, u64) {
+        match direction {
+            SwapDirection::AtoB => (self.vault_a.amount, self.vault_b.amount),
+            SwapDirection::BtoA => (self.vault_b.amount, self.vault_a.amount),
+        }
+    }
+
+    /// `referral_fee_bps` of `amount_in`, checked against the pool's cap and
+    /// the referrer's mint. Zero without a referrer.
+    fn referral_fee(
+        &self,
+        direction: SwapDirection,
+        amount_in: u64,
+        referral_fee_bps: u16,
+    ) -> Result<u64> {
+        require!(
+            referral_fee_bps <= self.pool.max_referral_fee_bps,
+            AmmError::InvalidFee
+        );
+        let Some(referrer) = &self.referrer else {
+            return Ok(0);
+        };
+        let mint_in = match direction {
+            SwapDirection::AtoB => self.mint_a.key(),
+            SwapDirection::BtoA => self.mint_b.key(),
+        };
+        require!(referrer.mint == mint_in, AmmError::InvalidSwapMint);
+        mul_div_floor(amount_in, referral_fee_bps as u64, BPS_DENOMINATOR)
+    }
+
+    /// Moves the tokens of `quote` and pays `referral_fee`. Returns the
+    /// protocol fee left for the fee vault.
+    fn settle(
+        &mut self,
+        direction: SwapDirection,
+        quote: &SwapQuote,
+        referral_fee: u64,
+    ) -> Result<u64> {
+        let (mint_in, mint_out) = match direction {
+            SwapDirection::AtoB => (self.mint_a.key(), self.mint_b.key()),
+            SwapDirection::BtoA => (self.mint_b.key(), self.mint_a.key()),
+        };
+        require!(
+            self.user_source.mint == mint_in && self.user_destination.mint == mint_out,
+            AmmError::InvalidSwapMint
+        );
+
+        let amount_in_to_pool = quote
+            .amount_in
+            .checked_sub(quote.protocol_fee)
+            .ok_or(AmmError::MathOverflow)?;
+        let protocol_fee = match self.pool.referral_fee_mode {
+            ReferralFeeMode::FromProtocolFee => quote
+                .protocol_fee
+                .checked_sub(referral_fee)
+                .ok_or(AmmError::MathOverflow)?,
+            ReferralFeeMode::OnTop => quote.protocol_fee,
+        };
+        token::transfer(self.transfer_to_vault_in_ctx(), amount_in_to_pool)?;
+        if protocol_fee > 0 {
+            token::transfer(self.transfer_to_fee_vault_ctx(), protocol_fee)?;
+        }
+        if let Some(referrer) = self.referrer.as_ref().filter(|_| referral_fee > 0) {
+            token::transfer(self.transfer_to_referrer_ctx(referrer), referral_fee)?;
+        }
+        let pool_seeds = self.pool.signer_seeds();
+        token::transfer(
+            self.transfer_to_user_out_ctx().with_signer(&[&pool_seeds]),
+            quote.amount_out,
+        )?;
+        self.pool.stats.record_swap(
+            direction,
+            &SwapQuote {
+                protocol_fee,
+                ..*quote
             },
-        )
+        )?;
+        Ok(protocol_fee)
     }
-}
 
-impl<'info> Swap<'info> {
     fn transfer_to_vault_in_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
         let (from, to) = if self.user_source.mint == self.mint_a.key() {
             (
@@ -899,68 +2636,642 @@ impl<'info> Swap<'info> {
             },
         )
     }
+
+    fn transfer_to_referrer_ctx(
+        &self,
+        referrer: &Account<'info, TokenAccount>,
+    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.user_source.to_account_info(),
+                to: referrer.to_account_info(),
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
+}
+
+impl<'info> SwapV2<'info> {
+    fn check_accounts(&self, pool: &ZeroCopyPool, direction: SwapDirection) -> Result<()> {
+        let (mint_in, mint_out, vault_in, vault_out, fee_vault_in) = match direction {
+            SwapDirection::AtoB => (
+                pool.mint_a,
+                pool.mint_b,
+                pool.vault_a,
+                pool.vault_b,
+                pool.fee_vault_a,
+            ),
+            SwapDirection::BtoA => (
+                pool.mint_b,
+                pool.mint_a,
+                pool.vault_b,
+                pool.vault_a,
+                pool.fee_vault_b,
+            ),
+        };
+        require!(
+            self.vault_in.key() == vault_in
+                && self.vault_out.key() == vault_out
+                && self.fee_vault_in.key() == fee_vault_in,
+            AmmError::InvalidVault
+        );
+        require!(
+            self.user_source.mint == mint_in && self.user_destination.mint == mint_out,
+            AmmError::InvalidSwapMint
+        );
+        Ok(())
+    }
+
+    fn settle(&self, pool: &ZeroCopyPool, quote: &SwapQuote) -> Result<()> {
+        let amount_in_to_pool = quote
+            .amount_in
+            .checked_sub(quote.protocol_fee)
+            .ok_or(AmmError::MathOverflow)?;
+        token::transfer(
+            self.transfer_from_user_ctx(self.vault_in.to_account_info()),
+            amount_in_to_pool,
+        )?;
+        if quote.protocol_fee > 0 {
+            token::transfer(
+                self.transfer_from_user_ctx(self.fee_vault_in.to_account_info()),
+                quote.protocol_fee,
+            )?;
+        }
+        let pool_seeds = pool.signer_seeds();
+        token::transfer(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                Transfer {
+                    from: self.vault_out.to_account_info(),
+                    to: self.user_destination.to_account_info(),
+                    authority: self.pool.to_account_info(),
+                },
+            )
+            .with_signer(&[&pool_seeds]),
+            quote.amount_out,
+        )?;
+        Ok(())
+    }
+
+    fn transfer_from_user_ctx(
+        &self,
+        to: AccountInfo<'info>,
+    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.user_source.to_account_info(),
+                to,
+                authority: self.user.to_account_info(),
+            },
+        )
+    }
 }
 
 impl<'info> WithdrawProtocolFees<'info> {
-    fn transfer_fee_to_admin_a_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+    fn transfer_fee_to_treasury_a_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
         CpiContext::new(
             self.token_program.to_account_info(),
             Transfer {
                 from: self.fee_vault_a.to_account_info(),
-                to: self.admin_ata_a.to_account_info(),
+                to: self.treasury_a.to_account_info(),
                 authority: self.pool.to_account_info(),
             },
         )
     }
 
-    fn transfer_fee_to_admin_b_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+    fn transfer_fee_to_treasury_b_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
         CpiContext::new(
             self.token_program.to_account_info(),
             Transfer {
                 from: self.fee_vault_b.to_account_info(),
-                to: self.admin_ata_b.to_account_info(),
+                to: self.treasury_b.to_account_info(),
                 authority: self.pool.to_account_info(),
             },
         )
     }
 }
 
-fn quote_swap_out(
+impl<'info> DistributeProtocolFees<'info> {
+    fn transfer_fee_ctx(
+        &self,
+        fee_vault: &Account<'info, TokenAccount>,
+        treasury: &AccountInfo<'info>,
+    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: fee_vault.to_account_info(),
+                to: treasury.clone(),
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+}
+
+impl<'info> Quote<'info> {
+    fn reserves(&self, direction: SwapDirection) -> (u64, u64) {
+        match direction {
+            SwapDirection::AtoB => (self.vault_a.amount, self.vault_b.amount),
+            SwapDirection::BtoA => (self.vault_b.amount, self.vault_a.amount),
+        }
+    }
+}
+
+impl<'info> FlashLoan<'info> {
+    fn transfer_to_borrower_a_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.vault_a.to_account_info(),
+                to: self.borrower_ata_a.to_account_info(),
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+
+    fn transfer_to_borrower_b_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.vault_b.to_account_info(),
+                to: self.borrower_ata_b.to_account_info(),
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+
+    fn transfer_to_fee_vault_a_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.vault_a.to_account_info(),
+                to: self.fee_vault_a.to_account_info(),
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+
+    fn transfer_to_fee_vault_b_ctx(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.vault_b.to_account_info(),
+                to: self.fee_vault_b.to_account_info(),
+                authority: self.pool.to_account_info(),
+            },
+        )
+    }
+}
+
+fn compute_deposit(
+    amount_a: u64,
+    amount_b: u64,
+    reserve_a: u64,
+    reserve_b: u64,
+    total_lp: u64,
+) -> Result<DepositQuote> {
+    if total_lp == 0 {
+        return Ok(DepositQuote {
+            amount_a,
+            amount_b,
+            // Floor: the first LP mints at most sqrt(k).
+            lp_amount: integer_sqrt((amount_a as u128) * (amount_b as u128))?,
+        });
+    }
+    require!(reserve_a > 0 && reserve_b > 0, AmmError::InsufficientLiquidity);
+
+    // Accept imbalanced deposits and mint LP from the limiting side. The
+    // paired amount rounds up and the LP minted rounds down. An ideal B
+    // beyond u64 can never be covered, so B is the limiting side then.
+    match mul_div_ceil(amount_a, reserve_b, reserve_a) {
+        Ok(ideal_b) if amount_b >= ideal_b => Ok(DepositQuote {
+            amount_a,
+            amount_b: ideal_b,
+            lp_amount: mul_div_floor(amount_a, total_lp, reserve_a)?,
+        }),
+        // `amount_b < ideal_b` implies `amount_b * reserve_a < amount_a *
+        // reserve_b`, so the rounded-up A still fits within `amount_a`.
+        _ => Ok(DepositQuote {
+            amount_a: mul_div_ceil(amount_b, reserve_a, reserve_b)?,
+            amount_b,
+            lp_amount: mul_div_floor(amount_b, total_lp, reserve_b)?,
+        }),
+    }
+}
+
+/// Relative distance, in bps of the pool ratio, between `amount_a / amount_b`
+/// and `reserve_a / reserve_b`, rounded down.
+fn ratio_deviation_bps(
+    amount_a: u64,
+    amount_b: u64,
+    reserve_a: u64,
+    reserve_b: u64,
+) -> Result<u64> {
+    require!(reserve_a > 0 && reserve_b > 0, AmmError::InsufficientLiquidity);
+    let supplied = (amount_a as u128) * (reserve_b as u128);
+    let pool = (amount_b as u128) * (reserve_a as u128);
+    let diff = supplied.abs_diff(pool);
+    let deviation = match diff.checked_mul(BPS_DENOMINATOR as u128) {
+        Some(scaled) => scaled / pool,
+        // Only reachable for huge products, where dropping the low digits of
+        // the divisor no longer matters.
+        None => diff / (pool / BPS_DENOMINATOR as u128).max(1),
+    };
+    Ok(deviation.min(u64::MAX as u128) as u64)
+}
+
+/// Token amounts needed to mint exactly `lp_amount`, rounded up so the new LP
+/// is never backed by less than its pro-rata share.
+fn compute_deposit_exact_lp(
+    lp_amount: u64,
+    reserve_a: u64,
+    reserve_b: u64,
+    total_lp: u64,
+) -> Result<DepositQuote> {
+    require!(total_lp > 0, AmmError::InsufficientLiquidity);
+    require!(reserve_a > 0 && reserve_b > 0, AmmError::InsufficientLiquidity);
+
+    Ok(DepositQuote {
+        amount_a: mul_div_ceil(lp_amount, reserve_a, total_lp)?,
+        amount_b: mul_div_ceil(lp_amount, reserve_b, total_lp)?,
+        lp_amount,
+    })
+}
+
+/// Splits `amount_in` of the input token into the largest swap portion that
+/// still leaves enough input to pair with the swap output at the post-swap
+/// ratio, then deposits both legs. The remaining input never exceeds what the
+/// output pairs with, so all of the swap output is deposited and any rounding
+/// dust stays with the user rather than the pool.
+fn compute_deposit_single_sided(
+    amount_in: u64,
+    side: TokenSide,
+    reserve_a: u64,
+    reserve_b: u64,
+    total_lp: u64,
+    fee_bps: u16,
+    protocol_fee_bps: u16,
+) -> Result<SingleSidedDeposit> {
+    require!(total_lp > 0, AmmError::InsufficientLiquidity);
+    let (reserve_in, reserve_out) = match side {
+        TokenSide::A => (reserve_a, reserve_b),
+        TokenSide::B => (reserve_b, reserve_a),
+    };
+
+    let split = |swap_amount: u64| -> Result<(SwapQuote, u64, u64, bool)> {
+        let swap = compute_swap(
+            swap_amount,
+            reserve_in,
+            reserve_out,
+            fee_bps,
+            protocol_fee_bps,
+        )?;
+        let post_reserve_in = reserve_in
+            .checked_add(swap.amount_in - swap.protocol_fee)
+            .ok_or(AmmError::MathOverflow)?;
+        let post_reserve_out = reserve_out - swap.amount_out;
+        let remaining_in = amount_in - swap_amount;
+        let balanced = (remaining_in as u128) * (post_reserve_out as u128)
+            >= (swap.amount_out as u128) * (post_reserve_in as u128);
+        Ok((swap, post_reserve_in, post_reserve_out, balanced))
+    };
+
+    let mut low = 0u64;
+    let mut high = amount_in;
+    while low < high {
+        let mid = high - (high - low) / 2;
+        if split(mid)?.3 {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    let (swap, post_reserve_in, post_reserve_out, _) = split(low)?;
+    let remaining_in = amount_in - low;
+    if swap.amount_out == 0 {
+        return Ok(SingleSidedDeposit {
+            swap,
+            deposit: DepositQuote {
+                amount_a: 0,
+                amount_b: 0,
+                lp_amount: 0,
+            },
+        });
+    }
+    let deposit = match side {
+        TokenSide::A => compute_deposit(
+            remaining_in,
+            swap.amount_out,
+            post_reserve_in,
+            post_reserve_out,
+            total_lp,
+        )?,
+        TokenSide::B => compute_deposit(
+            swap.amount_out,
+            remaining_in,
+            post_reserve_out,
+            post_reserve_in,
+            total_lp,
+        )?,
+    };
+    Ok(SingleSidedDeposit { swap, deposit })
+}
+
+fn compute_withdraw(
+    lp_amount: u64,
+    reserve_a: u64,
+    reserve_b: u64,
+    total_lp: u64,
+) -> Result<WithdrawQuote> {
+    require!(total_lp > 0, AmmError::InsufficientLiquidity);
+
+    // Floor: the burned LP is never paid more than its pro-rata share.
+    Ok(WithdrawQuote {
+        lp_amount,
+        amount_a: mul_div_floor(lp_amount, reserve_a, total_lp)?,
+        amount_b: mul_div_floor(lp_amount, reserve_b, total_lp)?,
+    })
+}
+
+/// Pro-rata withdrawal of `lp_amount`, then a swap of the side not wanted
+/// into `side` against the reserves left after the withdrawal.
+fn compute_withdraw_single_sided(
+    lp_amount: u64,
+    side: TokenSide,
+    reserve_a: u64,
+    reserve_b: u64,
+    total_lp: u64,
+    fee_bps: u16,
+    protocol_fee_bps: u16,
+) -> Result<SingleSidedWithdraw> {
+    let withdraw = compute_withdraw(lp_amount, reserve_a, reserve_b, total_lp)?;
+    let (kept, sold, reserve_kept, reserve_sold) = match side {
+        TokenSide::A => (withdraw.amount_a, withdraw.amount_b, reserve_a, reserve_b),
+        TokenSide::B => (withdraw.amount_b, withdraw.amount_a, reserve_b, reserve_a),
+    };
+    let swap = compute_swap(
+        sold,
+        reserve_sold - sold,
+        reserve_kept - kept,
+        fee_bps,
+        protocol_fee_bps,
+    )?;
+    let amount_out = kept
+        .checked_add(swap.amount_out)
+        .ok_or(AmmError::MathOverflow)?;
+
+    Ok(SingleSidedWithdraw {
+        withdraw,
+        swap,
+        amount_out,
+    })
+}
+
+fn compute_swap(
     amount_in: u64,
     reserve_in: u64,
     reserve_out: u64,
     fee_bps: u16,
-) -> Result<u64> {
-    let amount_in_with_fee = (amount_in as u128)
-        .checked_mul((BPS_DENOMINATOR - fee_bps as u64) as u128)
-        .ok_or(AmmError::MathOverflow)?
-        .checked_div(BPS_DENOMINATOR as u128)
+    protocol_fee_bps: u16,
+) -> Result<SwapQuote> {
+    require!(reserve_in > 0 && reserve_out > 0, AmmError::InsufficientLiquidity);
+    require!(protocol_fee_bps <= fee_bps, AmmError::InvalidFee);
+
+    // Floor: the protocol fee leaves the pool, so any remainder stays in it.
+    let protocol_fee = mul_div_floor(amount_in, protocol_fee_bps as u64, BPS_DENOMINATOR)?;
+    let amount_in_to_pool = amount_in
+        .checked_sub(protocol_fee)
+        .ok_or(AmmError::MathOverflow)?;
+    let lp_fee_bps = fee_bps
+        .checked_sub(protocol_fee_bps)
         .ok_or(AmmError::MathOverflow)?;
 
-    let numerator = amount_in_with_fee
-        .checked_mul(reserve_out as u128)
+    let amount_out = quote_swap_out(amount_in_to_pool, reserve_in, reserve_out, lp_fee_bps)?;
+    require!(amount_out < reserve_out, AmmError::InsufficientLiquidity);
+    // Reported only; rounds up as the complement of the floored fee-adjusted input.
+    let lp_fee = amount_in_to_pool
+        .checked_sub(apply_lp_fee(amount_in_to_pool, lp_fee_bps)?)
         .ok_or(AmmError::MathOverflow)?;
-    let denominator = (reserve_in as u128)
+
+    Ok(SwapQuote {
+        amount_in,
+        amount_out,
+        protocol_fee,
+        lp_fee,
+        price_impact_bps: price_impact_bps(amount_in, amount_out, reserve_in, reserve_out)?,
+    })
+}
+
+/// Inverse of `compute_swap`: the input needed for at least `amount_out`, with
+/// each step rounded up so the pool is never short.
+fn compute_swap_exact_out(
+    amount_out: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee_bps: u16,
+    protocol_fee_bps: u16,
+) -> Result<SwapQuote> {
+    require!(reserve_in > 0 && reserve_out > 0, AmmError::InsufficientLiquidity);
+    require!(amount_out < reserve_out, AmmError::InsufficientLiquidity);
+    require!(protocol_fee_bps <= fee_bps, AmmError::InvalidFee);
+    let lp_fee_bps = fee_bps
+        .checked_sub(protocol_fee_bps)
+        .ok_or(AmmError::MathOverflow)?;
+
+    let amount_in_with_fee = mul_div_ceil(amount_out, reserve_in, reserve_out - amount_out)?;
+    let amount_in_to_pool = mul_div_ceil(
+        amount_in_with_fee,
+        BPS_DENOMINATOR,
+        BPS_DENOMINATOR - lp_fee_bps as u64,
+    )?;
+    let amount_in = mul_div_ceil(
+        amount_in_to_pool,
+        BPS_DENOMINATOR,
+        BPS_DENOMINATOR - protocol_fee_bps as u64,
+    )?;
+
+    compute_swap(
+        amount_in,
+        reserve_in,
+        reserve_out,
+        fee_bps,
+        protocol_fee_bps,
+    )
+}
+
+/// Largest fill of at most `amount_in` whose post-trade price, output per
+/// input in Q64.64, stays at or above `min_price`. The price only falls as
+/// the input grows, so a binary search over the input finds the exact fill.
+fn compute_swap_to_price_limit(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee_bps: u16,
+    protocol_fee_bps: u16,
+    min_price: Q64x64,
+) -> Result<SwapQuote> {
+    let within_limit = |amount: u64| -> Result<Option<SwapQuote>> {
+        let quote = compute_swap(amount, reserve_in, reserve_out, fee_bps, protocol_fee_bps)?;
+        let post_reserve_in = reserve_in
+            .checked_add(quote.amount_in - quote.protocol_fee)
+            .ok_or(AmmError::MathOverflow)?;
+        let post_price = Q64x64::from_ratio(reserve_out - quote.amount_out, post_reserve_in)?;
+        Ok((post_price >= min_price).then_some(quote))
+    };
+
+    if let Some(quote) = within_limit(amount_in)? {
+        return Ok(quote);
+    }
+    let mut filled = 0u64;
+    let mut rejected = amount_in;
+    while rejected - filled > 1 {
+        let mid = filled + (rejected - filled) / 2;
+        if within_limit(mid)?.is_some() {
+            filled = mid;
+        } else {
+            rejected = mid;
+        }
+    }
+    let quote = within_limit(filled)?.ok_or(AmmError::PriceLimitReached)?;
+    require!(quote.amount_out > 0, AmmError::PriceLimitReached);
+    Ok(quote)
+}
+
+/// Input left after the LP fee, rounded down so the fee is never undercharged.
+fn apply_lp_fee(amount_in: u64, fee_bps: u16) -> Result<u64> {
+    mul_div_floor(amount_in, BPS_DENOMINATOR - fee_bps as u64, BPS_DENOMINATOR)
+}
+
+/// How far `amount_out` falls short of `amount_in` at the pre-trade spot price.
+fn price_impact_bps(
+    amount_in: u64,
+    amount_out: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+) -> Result<u64> {
+    require!(reserve_in > 0, AmmError::InsufficientLiquidity);
+    // Kept wide: the spot-price output can exceed u64 for lopsided reserves.
+    let spot_out = (amount_in as u128) * (reserve_out as u128) / reserve_in as u128;
+    if spot_out == 0 {
+        return Ok(0);
+    }
+    // Floor on the filled share, so the reported impact rounds up.
+    let filled_bps = math::to_u64((amount_out as u128) * (BPS_DENOMINATOR as u128) / spot_out)?;
+    Ok(BPS_DENOMINATOR.saturating_sub(filled_bps))
+}
+
+/// Constant-product output for `amount_in`, rounded down so `k` never shrinks.
+fn quote_swap_out(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee_bps: u16,
+) -> Result<u64> {
+    let amount_in_with_fee = apply_lp_fee(amount_in, fee_bps)?;
+    let denominator = reserve_in
         .checked_add(amount_in_with_fee)
         .ok_or(AmmError::MathOverflow)?;
 
-    numerator
-        .checked_div(denominator)
-        .ok_or(AmmError::MathOverflow)
-        .map(|v| v as u64)
+    mul_div_floor(amount_in_with_fee, reserve_out, denominator)
+}
+
+/// Flash fee owed on `amount`, rounded up so the pool never undercharges.
+fn flash_fee(amount: u64, flash_fee_bps: u16) -> Result<u64> {
+    mul_div_ceil(amount, flash_fee_bps as u64, BPS_DENOMINATOR)
+}
+
+/// Protocol share of a flash fee, in the same proportion as
+/// `protocol_fee_bps` takes of `fee_bps` on swaps, rounded down. The rest
+/// stays with LPs.
+fn flash_protocol_fee(fee: u64, fee_bps: u16, protocol_fee_bps: u16) -> Result<u64> {
+    if fee_bps == 0 {
+        return Ok(0);
+    }
+    mul_div_floor(fee, protocol_fee_bps as u64, fee_bps as u64)
+}
+
+/// Spot price of A in B, rounded down.
+fn spot_price(reserve_a: u64, reserve_b: u64) -> Result<Q64x64> {
+    require!(reserve_a > 0 && reserve_b > 0, AmmError::InsufficientLiquidity);
+    Q64x64::from_ratio(reserve_b, reserve_a)
+}
+
+/// `spot_price` for an event, which reports an empty pool as zero.
+fn event_spot_price(reserve_a: u64, reserve_b: u64) -> u128 {
+    spot_price(reserve_a, reserve_b).map_or(0, Q64x64::raw)
+}
+
+/// Reserves `(a, b)` after `quote` is swapped in `direction` against
+/// `reserve_in` and `reserve_out`.
+fn post_swap_reserves(
+    direction: SwapDirection,
+    reserve_in: u64,
+    reserve_out: u64,
+    quote: &SwapQuote,
+) -> Result<(u64, u64)> {
+    let post_in = quote
+        .amount_in
+        .checked_sub(quote.protocol_fee)
+        .and_then(|amount| reserve_in.checked_add(amount))
+        .ok_or(AmmError::MathOverflow)?;
+    let post_out = reserve_out
+        .checked_sub(quote.amount_out)
+        .ok_or(AmmError::MathOverflow)?;
+    Ok(match direction {
+        SwapDirection::AtoB => (post_in, post_out),
+        SwapDirection::BtoA => (post_out, post_in),
+    })
 }
 
-fn integer_sqrt(value: u128) -> u64 {
-    if value == 0 {
-        return 0;
+fn check_price_range(
+    reserve_a: u64,
+    reserve_b: u64,
+    min_price: Option<u128>,
+    max_price: Option<u128>,
+) -> Result<()> {
+    if min_price.is_none() && max_price.is_none() {
+        return Ok(());
     }
-    let mut z = value;
-    let mut x = value / 2 + 1;
-    while x < z {
-        z = x;
-        x = (value / x + x) / 2;
+    let price = spot_price(reserve_a, reserve_b)?;
+    if let Some(min_price) = min_price {
+        require!(price.raw() >= min_price, AmmError::PriceOutOfRange);
     }
-    z as u64
+    if let Some(max_price) = max_price {
+        require!(price.raw() <= max_price, AmmError::PriceOutOfRange);
+    }
+    Ok(())
+}
+
+/// A fee distribution needs at least one recipient, each with a nonzero
+/// share, and shares that add up to the whole payout.
+fn check_fee_shares(recipients: &[FeeShare]) -> Result<()> {
+    require!(
+        !recipients.is_empty() && recipients.len() <= FeeDistribution::MAX_RECIPIENTS,
+        AmmError::InvalidFeeDistribution
+    );
+    require!(
+        recipients.iter().all(|share| share.share_bps > 0),
+        AmmError::InvalidFeeDistribution
+    );
+    let total: u64 = recipients.iter().map(|share| share.share_bps as u64).sum();
+    require!(total == BPS_DENOMINATOR, AmmError::InvalidFeeDistribution);
+    Ok(())
+}
+
+fn check_deadline(deadline: Option<Deadline>) -> Result<()> {
+    let Some(deadline) = deadline else {
+        return Ok(());
+    };
+    let clock = Clock::get()?;
+    let expired = match deadline {
+        Deadline::UnixTimestamp(timestamp) => clock.unix_timestamp > timestamp,
+        Deadline::MaxSlot(slot) => clock.slot > slot,
+    };
+    require!(!expired, AmmError::Expired);
+    Ok(())
 }
 
 #[error_code]
@@ -985,4 +3296,384 @@ pub enum AmmError {
     InvalidFee,
     #[msg("Pool is paused")]
     PoolPaused,
+    #[msg("Transaction deadline has passed")]
+    Expired,
+    #[msg("Flash loan was not repaid with its fee")]
+    FlashLoanNotRepaid,
+    #[msg("Invalid flash loan receiver program")]
+    InvalidFlashReceiver,
+    #[msg("Price limit reached before any input could be filled")]
+    PriceLimitReached,
+    #[msg("Pool price is outside the requested range")]
+    PriceOutOfRange,
+    #[msg("Vault does not belong to the pool")]
+    InvalidVault,
+    #[msg("Fee distribution needs 1 to 8 nonzero shares summing to 10,000 bps")]
+    InvalidFeeDistribution,
+    #[msg("Token account does not belong to the fee recipient")]
+    InvalidFeeRecipient,
+    #[msg("Protocol fees are paid out through the fee distribution")]
+    FeesAreDistributed,
+    #[msg("Pool is already at the current layout")]
+    PoolUpToDate,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::widening_mul;
+    use proptest::prelude::*;
+
+    #[test]
+    fn zero_copy_pool_reads_serialized_pool() {
+        let pool = Pool {
+            mint_a: Pubkey::new_unique(),
+            mint_b: Pubkey::new_unique(),
+            vault_a: Pubkey::new_unique(),
+            vault_b: Pubkey::new_unique(),
+            lp_mint: Pubkey::new_unique(),
+            fee_vault_a: Pubkey::new_unique(),
+            fee_vault_b: Pubkey::new_unique(),
+            admin: Pubkey::new_unique(),
+            bump: 254,
+            fee_bps: 30,
+            protocol_fee_bps: 5,
+            paused: true,
+            flash_fee_bps: 9,
+            stats: PoolStats {
+                volume_a: u64::MAX as u128 + 1,
+                swap_count: 2,
+                protocol_fees_b: 7,
+                ..PoolStats::default()
+            },
+            max_referral_fee_bps: 3,
+            referral_fee_mode: ReferralFeeMode::OnTop,
+            fee_recipient: Pubkey::new_unique(),
+        };
+        let mut data = Vec::new();
+        pool.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), Pool::LEN);
+        assert_eq!(data[..8], ZeroCopyPool::DISCRIMINATOR);
+
+        let view: &ZeroCopyPool = bytemuck::from_bytes(&data[8..]);
+        assert_eq!(view.fee_vault_b, pool.fee_vault_b);
+        assert_eq!(view.admin, pool.admin);
+        assert_eq!(view.fee_recipient, pool.fee_recipient);
+        assert_eq!(view.signer_seeds(), pool.signer_seeds());
+        let (fee_bps, protocol_fee_bps, flash_fee_bps) =
+            (view.fee_bps, view.protocol_fee_bps, view.flash_fee_bps);
+        assert_eq!((fee_bps, protocol_fee_bps, flash_fee_bps), (30, 5, 9));
+        let max_referral_fee_bps = view.max_referral_fee_bps;
+        assert_eq!((max_referral_fee_bps, view.referral_fee_mode), (3, 1));
+        assert_eq!(view.paused, 1);
+        assert_eq!(view.stats(), pool.stats);
+    }
+
+    #[test]
+    fn stats_count_swaps_on_the_input_side() {
+        let mut stats = PoolStats::default();
+        let quote = compute_swap(10_000, 1_000_000, 4_000_000, 30, 5).unwrap();
+        stats.record_swap(SwapDirection::AtoB, &quote).unwrap();
+        stats.record_swap(SwapDirection::AtoB, &quote).unwrap();
+        let quote_b = compute_swap(40_000, 4_000_000, 1_000_000, 30, 5).unwrap();
+        stats.record_swap(SwapDirection::BtoA, &quote_b).unwrap();
+
+        assert_eq!(stats.swap_count, 3);
+        assert_eq!((stats.volume_a, stats.volume_b), (20_000, 40_000));
+        assert_eq!(
+            (stats.lp_fees_a, stats.protocol_fees_a),
+            (2 * quote.lp_fee as u128, 2 * quote.protocol_fee as u128)
+        );
+        assert_eq!(
+            (stats.lp_fees_b, stats.protocol_fees_b),
+            (quote_b.lp_fee as u128, quote_b.protocol_fee as u128)
+        );
+
+        stats.volume_b = u128::MAX;
+        assert!(stats.record_swap(SwapDirection::BtoA, &quote_b).is_err());
+    }
+
+    #[test]
+    fn fee_shares_must_cover_the_whole_payout() {
+        let share = |share_bps| FeeShare {
+            owner: Pubkey::new_unique(),
+            share_bps,
+        };
+        assert!(check_fee_shares(&[share(10_000)]).is_ok());
+        assert!(check_fee_shares(&[share(2_500), share(7_500)]).is_ok());
+        assert!(check_fee_shares(&[share(1_250); 8]).is_ok());
+
+        assert!(check_fee_shares(&[]).is_err());
+        assert!(check_fee_shares(&[share(2_500), share(7_499)]).is_err());
+        assert!(check_fee_shares(&[share(0), share(10_000)]).is_err());
+        let mut nine = vec![share(1_000); 8];
+        nine.push(share(2_000));
+        assert!(check_fee_shares(&nine).is_err());
+    }
+
+    #[test]
+    fn ratio_deviation_is_relative_to_pool_ratio() {
+        assert_eq!(ratio_deviation_bps(100, 200, 1_000, 2_000).unwrap(), 0);
+        assert_eq!(ratio_deviation_bps(101, 200, 1_000, 2_000).unwrap(), 100);
+        assert_eq!(ratio_deviation_bps(100, 202, 1_000, 2_000).unwrap(), 99);
+        assert_eq!(
+            ratio_deviation_bps(u64::MAX, 1, u64::MAX, u64::MAX).unwrap(),
+            u64::MAX
+        );
+        assert_eq!(
+            ratio_deviation_bps(u64::MAX, 1, 1, u64::MAX).unwrap(),
+            u64::MAX
+        );
+    }
+
+    #[test]
+    fn price_range_brackets_spot_price() {
+        let two = 2u128 << 64;
+        assert_eq!(spot_price(1_000, 2_000).unwrap().raw(), two);
+        assert!(check_price_range(1_000, 2_000, Some(two), Some(two)).is_ok());
+        assert!(check_price_range(1_000, 2_000, Some(two + 1), None).is_err());
+        assert!(check_price_range(1_000, 2_000, None, Some(two - 1)).is_err());
+        assert!(check_price_range(0, 0, None, None).is_ok());
+    }
+
+    #[test]
+    fn deposit_rounds_paired_amount_up() {
+        // 1 A is worth a third of a B; flooring would take the LP for free.
+        let quote = compute_deposit(1, 1, 3, 1, 100).unwrap();
+        assert_eq!(
+            (quote.amount_a, quote.amount_b, quote.lp_amount),
+            (1, 1, 33)
+        );
+        let quote = compute_deposit(10, 1, 3, 1, 100).unwrap();
+        assert_eq!(
+            (quote.amount_a, quote.amount_b, quote.lp_amount),
+            (3, 1, 100)
+        );
+    }
+
+    fn side_strategy() -> impl Strategy<Value = TokenSide> {
+        prop_oneof![Just(TokenSide::A), Just(TokenSide::B)]
+    }
+
+    #[derive(Clone, Debug)]
+    enum PoolOp {
+        Swap(u64, TokenSide),
+        Deposit(u64, u64),
+        Withdraw(u64),
+    }
+
+    fn pool_op_strategy() -> impl Strategy<Value = PoolOp> {
+        prop_oneof![
+            (1u64..1_000_000_000_000, side_strategy())
+                .prop_map(|(amount, side)| PoolOp::Swap(amount, side)),
+            (1u64..1_000_000_000_000, 1u64..1_000_000_000_000)
+                .prop_map(|(amount_a, amount_b)| PoolOp::Deposit(amount_a, amount_b)),
+            (1u64..10_000).prop_map(PoolOp::Withdraw),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn exact_lp_deposit_pays_at_least_pro_rata(
+            reserve_a in 1u64..u64::MAX / 2,
+            reserve_b in 1u64..u64::MAX / 2,
+            total_lp in 1u64..u64::MAX / 2,
+            lp_amount in 1u64..u64::MAX / 2,
+        ) {
+            let Ok(quote) = compute_deposit_exact_lp(lp_amount, reserve_a, reserve_b, total_lp)
+            else {
+                return Ok(());
+            };
+            prop_assert!(
+                quote.amount_a as u128 * total_lp as u128
+                    >= lp_amount as u128 * reserve_a as u128
+            );
+            prop_assert!(
+                quote.amount_b as u128 * total_lp as u128
+                    >= lp_amount as u128 * reserve_b as u128
+            );
+        }
+
+        #[test]
+        fn single_sided_withdraw_keeps_k_per_lp(
+            reserve_a in 1_000u64..1_000_000_000,
+            reserve_b in 1_000u64..1_000_000_000,
+            lp_share_bps in 1u64..9_999,
+            side in side_strategy(),
+        ) {
+            let total_lp = integer_sqrt(reserve_a as u128 * reserve_b as u128).unwrap();
+            let lp_amount = (total_lp as u128 * lp_share_bps as u128 / 10_000) as u64;
+            prop_assume!(lp_amount > 0);
+            let Ok(SingleSidedWithdraw { withdraw, swap, amount_out }) =
+                compute_withdraw_single_sided(
+                    lp_amount,
+                    side,
+                    reserve_a,
+                    reserve_b,
+                    total_lp,
+                    FEE_BPS,
+                    PROTOCOL_FEE_BPS,
+                )
+            else {
+                return Ok(());
+            };
+
+            let (post_a, post_b, kept) = match side {
+                TokenSide::A => (
+                    reserve_a - amount_out,
+                    reserve_b - swap.protocol_fee,
+                    withdraw.amount_a,
+                ),
+                TokenSide::B => (
+                    reserve_a - swap.protocol_fee,
+                    reserve_b - amount_out,
+                    withdraw.amount_b,
+                ),
+            };
+            prop_assert!(amount_out >= kept);
+            let post_lp = (total_lp - lp_amount) as u128;
+            prop_assert!(
+                post_a as u128 * post_b as u128 * (total_lp as u128).pow(2)
+                    >= reserve_a as u128 * reserve_b as u128 * post_lp.pow(2)
+            );
+        }
+
+        #[test]
+        fn single_sided_deposit_leaks_no_value(
+            reserve_a in 1_000u64..1_000_000_000_000,
+            reserve_b in 1_000u64..1_000_000_000_000,
+            amount_in in 1u64..1_000_000_000_000,
+            side in side_strategy(),
+        ) {
+            let total_lp = integer_sqrt(reserve_a as u128 * reserve_b as u128).unwrap();
+            let Ok(SingleSidedDeposit { swap, deposit }) = compute_deposit_single_sided(
+                amount_in,
+                side,
+                reserve_a,
+                reserve_b,
+                total_lp,
+                FEE_BPS,
+                PROTOCOL_FEE_BPS,
+            ) else {
+                return Ok(());
+            };
+            prop_assume!(deposit.lp_amount > 0);
+
+            let (reserve_in, reserve_out, deposit_in, deposit_out) = match side {
+                TokenSide::A => (reserve_a, reserve_b, deposit.amount_a, deposit.amount_b),
+                TokenSide::B => (reserve_b, reserve_a, deposit.amount_b, deposit.amount_a),
+            };
+            // Every swapped-out token is deposited back into the pool.
+            prop_assert_eq!(deposit_out, swap.amount_out);
+            let charged = swap.amount_in + deposit_in;
+            prop_assert!(charged <= amount_in);
+
+            // Exiting straight away and selling the other side back must not
+            // return more of the input token than was charged.
+            let post_reserve_in = reserve_in + swap.amount_in - swap.protocol_fee + deposit_in;
+            let post_lp = total_lp + deposit.lp_amount;
+            let exit_in = (deposit.lp_amount as u128 * post_reserve_in as u128
+                / post_lp as u128) as u64;
+            let exit_out = (deposit.lp_amount as u128 * reserve_out as u128
+                / post_lp as u128) as u64;
+            let sold_back = if exit_out == 0 {
+                0
+            } else {
+                compute_swap(
+                    exit_out,
+                    reserve_out - exit_out,
+                    post_reserve_in - exit_in,
+                    FEE_BPS,
+                    PROTOCOL_FEE_BPS,
+                )
+                .map(|quote| quote.amount_out)
+                .unwrap_or(0)
+            };
+            prop_assert!(exit_in + sold_back <= charged);
+        }
+
+        #[test]
+        fn k_per_lp_squared_never_decreases(
+            reserve_a in 1_000u64..1_000_000_000_000,
+            reserve_b in 1_000u64..1_000_000_000_000,
+            ops in prop::collection::vec(pool_op_strategy(), 1..64),
+        ) {
+            let (mut reserve_a, mut reserve_b) = (reserve_a, reserve_b);
+            let mut lp_supply = integer_sqrt(reserve_a as u128 * reserve_b as u128).unwrap();
+
+            for op in ops {
+                let (post_a, post_b, post_lp) = match op {
+                    PoolOp::Swap(amount_in, side) => {
+                        let (reserve_in, reserve_out) = match side {
+                            TokenSide::A => (reserve_a, reserve_b),
+                            TokenSide::B => (reserve_b, reserve_a),
+                        };
+                        let Ok(quote) = compute_swap(
+                            amount_in,
+                            reserve_in,
+                            reserve_out,
+                            FEE_BPS,
+                            PROTOCOL_FEE_BPS,
+                        ) else {
+                            continue;
+                        };
+                        let Some(post_in) =
+                            reserve_in.checked_add(quote.amount_in - quote.protocol_fee)
+                        else {
+                            continue;
+                        };
+                        let post_out = reserve_out - quote.amount_out;
+                        match side {
+                            TokenSide::A => (post_in, post_out, lp_supply),
+                            TokenSide::B => (post_out, post_in, lp_supply),
+                        }
+                    }
+                    PoolOp::Deposit(amount_a, amount_b) => {
+                        let Ok(quote) =
+                            compute_deposit(amount_a, amount_b, reserve_a, reserve_b, lp_supply)
+                        else {
+                            continue;
+                        };
+                        if quote.lp_amount == 0 {
+                            continue;
+                        }
+                        match (
+                            reserve_a.checked_add(quote.amount_a),
+                            reserve_b.checked_add(quote.amount_b),
+                            lp_supply.checked_add(quote.lp_amount),
+                        ) {
+                            (Some(post_a), Some(post_b), Some(post_lp)) => {
+                                (post_a, post_b, post_lp)
+                            }
+                            _ => continue,
+                        }
+                    }
+                    PoolOp::Withdraw(share_bps) => {
+                        let lp_amount = mul_div_floor(lp_supply, share_bps, BPS_DENOMINATOR).unwrap();
+                        if lp_amount == 0 {
+                            continue;
+                        }
+                        let quote =
+                            compute_withdraw(lp_amount, reserve_a, reserve_b, lp_supply).unwrap();
+                        (
+                            reserve_a - quote.amount_a,
+                            reserve_b - quote.amount_b,
+                            lp_supply - lp_amount,
+                        )
+                    }
+                };
+
+                let before = widening_mul(
+                    reserve_a as u128 * reserve_b as u128,
+                    post_lp as u128 * post_lp as u128,
+                );
+                let after = widening_mul(
+                    post_a as u128 * post_b as u128,
+                    lp_supply as u128 * lp_supply as u128,
+                );
+                prop_assert!(after >= before, "{:?} decreased k / lp_supply^2", op);
+                (reserve_a, reserve_b, lp_supply) = (post_a, post_b, post_lp);
+            }
+        }
+    }
 }
@@ -0,0 +1,160 @@
+//! Checked integer and fixed-point helpers used by the pool math.
+//!
+//! Every helper widens to `u128` for the intermediate product and narrows back
+//! with [`to_u64`], so an out-of-range result is a `MathOverflow` error rather
+//! than a silent truncation.
+//!
+//! Rounding policy: anything paid out of the pool or minted to a user rounds
+//! down, anything a user owes the pool rounds up. Rounding dust therefore
+//! always accrues to LPs, and `reserve_a * reserve_b / lp_supply^2` never
+//! decreases. Call sites in the program note which direction they take.
+
+use anchor_lang::prelude::*;
+
+use crate::AmmError;
+
+/// Unsigned fixed-point number with 64 integer and 64 fractional bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Q64x64(u128);
+
+impl Q64x64 {
+    pub const FRACTIONAL_BITS: u32 = 64;
+    pub const ONE: Q64x64 = Q64x64(1 << Self::FRACTIONAL_BITS);
+
+    pub const fn from_raw(raw: u128) -> Self {
+        Q64x64(raw)
+    }
+
+    pub const fn raw(self) -> u128 {
+        self.0
+    }
+
+    /// `numerator / denominator`, rounded down.
+    pub fn from_ratio(numerator: u64, denominator: u64) -> Result<Self> {
+        require!(denominator > 0, AmmError::MathOverflow);
+        Ok(Q64x64(
+            ((numerator as u128) << Self::FRACTIONAL_BITS) / denominator as u128,
+        ))
+    }
+}
+
+/// Narrows a `u128` intermediate back to a token amount.
+pub fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| error!(AmmError::MathOverflow))
+}
+
+/// `a * b / denominator`, rounded down.
+pub fn mul_div_floor(a: u64, b: u64, denominator: u64) -> Result<u64> {
+    require!(denominator > 0, AmmError::MathOverflow);
+    to_u64((a as u128) * (b as u128) / denominator as u128)
+}
+
+/// `a * b / denominator`, rounded up.
+pub fn mul_div_ceil(a: u64, b: u64, denominator: u64) -> Result<u64> {
+    require!(denominator > 0, AmmError::MathOverflow);
+    to_u64(((a as u128) * (b as u128)).div_ceil(denominator as u128))
+}
+
+/// Largest `x` with `x * x <= value`.
+pub fn integer_sqrt(value: u128) -> Result<u64> {
+    if value < 2 {
+        return to_u64(value);
+    }
+    // Newton's method from above: `x` decreases monotonically to the floor of
+    // the root, and `x <= value / 2 + 1` keeps `x + value / x` from overflowing.
+    let mut x = value;
+    let mut next = value / 2 + (value & 1);
+    while next < x {
+        x = next;
+        next = (x + value / x) / 2;
+    }
+    to_u64(x)
+}
+
+/// Full 256-bit product of two `u128`s as `(high, low)` halves, which
+/// compare in the same order as the products themselves.
+pub fn widening_mul(x: u128, y: u128) -> (u128, u128) {
+    let mask = u64::MAX as u128;
+    let (x_hi, x_lo) = (x >> 64, x & mask);
+    let (y_hi, y_lo) = (y >> 64, y & mask);
+    let lo_lo = x_lo * y_lo;
+    let hi_lo = x_hi * y_lo;
+    let lo_hi = x_lo * y_hi;
+    let mid = (lo_lo >> 64) + (hi_lo & mask) + (lo_hi & mask);
+    let lo = (lo_lo & mask) | (mid << 64);
+    let hi = x_hi * y_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+    (hi, lo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_floor_at_u64_extremes() {
+        assert_eq!(
+            mul_div_floor(u64::MAX, u64::MAX, u64::MAX).unwrap(),
+            u64::MAX
+        );
+        assert_eq!(mul_div_floor(u64::MAX, 1, 2).unwrap(), u64::MAX / 2);
+        assert_eq!(mul_div_floor(1, 1, u64::MAX).unwrap(), 0);
+        assert!(mul_div_floor(u64::MAX, 2, 1).is_err());
+        assert!(mul_div_floor(u64::MAX, u64::MAX, u64::MAX - 1).is_err());
+        assert!(mul_div_floor(1, 1, 0).is_err());
+    }
+
+    #[test]
+    fn mul_div_ceil_at_u64_extremes() {
+        assert_eq!(
+            mul_div_ceil(u64::MAX, u64::MAX, u64::MAX).unwrap(),
+            u64::MAX
+        );
+        assert_eq!(mul_div_ceil(u64::MAX, 1, 2).unwrap(), u64::MAX / 2 + 1);
+        assert_eq!(mul_div_ceil(1, 1, u64::MAX).unwrap(), 1);
+        assert_eq!(mul_div_ceil(0, u64::MAX, 1).unwrap(), 0);
+        assert!(mul_div_ceil(u64::MAX, u64::MAX, u64::MAX - 1).is_err());
+        assert!(mul_div_ceil(1, 1, 0).is_err());
+    }
+
+    #[test]
+    fn to_u64_rejects_truncation() {
+        assert_eq!(to_u64(u64::MAX as u128).unwrap(), u64::MAX);
+        assert!(to_u64(u64::MAX as u128 + 1).is_err());
+        assert!(to_u64(u128::MAX).is_err());
+    }
+
+    #[test]
+    fn integer_sqrt_is_floor_root() {
+        for (value, root) in [(0, 0), (1, 1), (2, 1), (3, 1), (4, 2), (8, 2), (9, 3)] {
+            assert_eq!(integer_sqrt(value).unwrap(), root);
+        }
+        let max = u64::MAX as u128;
+        assert_eq!(integer_sqrt(max * max).unwrap(), u64::MAX);
+        assert_eq!(integer_sqrt(max * max - 1).unwrap(), u64::MAX - 1);
+        assert_eq!(integer_sqrt(u128::MAX).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn widening_mul_at_u128_extremes() {
+        assert_eq!(widening_mul(0, u128::MAX), (0, 0));
+        assert_eq!(widening_mul(1, u128::MAX), (0, u128::MAX));
+        assert_eq!(widening_mul(u64::MAX as u128, u64::MAX as u128).0, 0);
+        assert_eq!(widening_mul(1 << 64, 1 << 64), (1, 0));
+        assert_eq!(widening_mul(u128::MAX, 2), (1, u128::MAX - 1));
+        // (2^128 - 1)^2 = 2^256 - 2^129 + 1.
+        assert_eq!(widening_mul(u128::MAX, u128::MAX), (u128::MAX - 1, 1));
+        assert!(widening_mul(u128::MAX, u128::MAX - 1) < widening_mul(u128::MAX, u128::MAX));
+    }
+
+    #[test]
+    fn q64x64_from_ratio_at_u64_extremes() {
+        assert_eq!(Q64x64::from_ratio(1, 1).unwrap(), Q64x64::ONE);
+        assert_eq!(
+            Q64x64::from_ratio(u64::MAX, 1).unwrap().raw(),
+            (u64::MAX as u128) << 64
+        );
+        assert_eq!(Q64x64::from_ratio(1, u64::MAX).unwrap().raw(), 1);
+        assert_eq!(Q64x64::from_ratio(0, u64::MAX).unwrap().raw(), 0);
+        assert!(Q64x64::from_ratio(1, 0).is_err());
+    }
+}
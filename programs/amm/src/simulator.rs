@@ -0,0 +1,299 @@
+//! Off-chain model of a single pool.
+//!
+//! [`PoolSimulator`] runs the same `compute_*` functions and the same checks
+//! as the `swap`, `deposit_liquidity` and `withdraw_liquidity` handlers, so a
+//! sequence of operations applied here leaves exactly the balances the
+//! program would. Token transfers are reduced to updates of the tracked
+//! reserves, LP supply and fee vault balances, and swaps are added to
+//! `stats` as the program adds them to the pool's.
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    compute_deposit, compute_swap, compute_withdraw, math::mul_div_floor, AmmError, DepositQuote,
+    Pool, PoolStats, ReferralFeeMode, SwapDirection, SwapQuote, WithdrawQuote, BPS_DENOMINATOR,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolSimulator {
+    /// Balance of `vault_a`.
+    pub reserve_a: u64,
+    /// Balance of `vault_b`.
+    pub reserve_b: u64,
+    /// Supply of `lp_mint`.
+    pub lp_supply: u64,
+    pub fee_bps: u16,
+    pub protocol_fee_bps: u16,
+    pub paused: bool,
+    pub max_referral_fee_bps: u16,
+    pub referral_fee_mode: ReferralFeeMode,
+    /// Balance of `fee_vault_a`.
+    pub protocol_fees_a: u64,
+    /// Balance of `fee_vault_b`.
+    pub protocol_fees_b: u64,
+    pub stats: PoolStats,
+}
+
+impl PoolSimulator {
+    /// An unpaused pool with the given balances, no accrued protocol fees and
+    /// no trading history.
+    pub fn new(
+        reserve_a: u64,
+        reserve_b: u64,
+        lp_supply: u64,
+        fee_bps: u16,
+        protocol_fee_bps: u16,
+    ) -> Self {
+        PoolSimulator {
+            reserve_a,
+            reserve_b,
+            lp_supply,
+            fee_bps,
+            protocol_fee_bps,
+            ..Default::default()
+        }
+    }
+
+    /// Snapshot of an on-chain pool. `Pool` does not store balances, so the
+    /// vault, LP mint and fee vault amounts are passed in alongside it.
+    pub fn from_pool(
+        pool: &Pool,
+        reserve_a: u64,
+        reserve_b: u64,
+        lp_supply: u64,
+        protocol_fees_a: u64,
+        protocol_fees_b: u64,
+    ) -> Self {
+        PoolSimulator {
+            reserve_a,
+            reserve_b,
+            lp_supply,
+            fee_bps: pool.fee_bps,
+            protocol_fee_bps: pool.protocol_fee_bps,
+            paused: pool.paused,
+            max_referral_fee_bps: pool.max_referral_fee_bps,
+            referral_fee_mode: pool.referral_fee_mode,
+            protocol_fees_a,
+            protocol_fees_b,
+            stats: pool.stats,
+        }
+    }
+
+    pub fn swap(
+        &mut self,
+        amount_in: u64,
+        min_amount_out: u64,
+        direction: SwapDirection,
+    ) -> Result<SwapQuote> {
+        self.swap_with_referrer(amount_in, min_amount_out, direction, 0)
+            .map(|(quote, _)| quote)
+    }
+
+    /// `swap` with a referrer paid `referral_fee_bps` of `amount_in`.
+    /// Returns the quote and the referral fee, which the fee vault gives up
+    /// or the user pays on top per `referral_fee_mode`.
+    pub fn swap_with_referrer(
+        &mut self,
+        amount_in: u64,
+        min_amount_out: u64,
+        direction: SwapDirection,
+        referral_fee_bps: u16,
+    ) -> Result<(SwapQuote, u64)> {
+        require!(!self.paused, AmmError::PoolPaused);
+        require!(amount_in > 0, AmmError::InvalidAmount);
+
+        let (reserve_in, reserve_out) = match direction {
+            SwapDirection::AtoB => (self.reserve_a, self.reserve_b),
+            SwapDirection::BtoA => (self.reserve_b, self.reserve_a),
+        };
+        let quote = compute_swap(
+            amount_in,
+            reserve_in,
+            reserve_out,
+            self.fee_bps,
+            self.protocol_fee_bps,
+        )?;
+        require!(
+            quote.amount_out >= min_amount_out,
+            AmmError::SlippageExceeded
+        );
+        require!(
+            referral_fee_bps <= self.max_referral_fee_bps,
+            AmmError::InvalidFee
+        );
+        let referral_fee = mul_div_floor(amount_in, referral_fee_bps as u64, BPS_DENOMINATOR)?;
+        let protocol_fee = match self.referral_fee_mode {
+            ReferralFeeMode::FromProtocolFee => quote
+                .protocol_fee
+                .checked_sub(referral_fee)
+                .ok_or(AmmError::MathOverflow)?,
+            ReferralFeeMode::OnTop => quote.protocol_fee,
+        };
+
+        let post_reserve_in = reserve_in
+            .checked_add(quote.amount_in - quote.protocol_fee)
+            .ok_or(AmmError::MathOverflow)?;
+        let post_reserve_out = reserve_out - quote.amount_out;
+        let mut stats = self.stats;
+        stats.record_swap(
+            direction,
+            &SwapQuote {
+                protocol_fee,
+                ..quote
+            },
+        )?;
+        match direction {
+            SwapDirection::AtoB => {
+                self.protocol_fees_a = checked_add(self.protocol_fees_a, protocol_fee)?;
+                self.reserve_a = post_reserve_in;
+                self.reserve_b = post_reserve_out;
+            }
+            SwapDirection::BtoA => {
+                self.protocol_fees_b = checked_add(self.protocol_fees_b, protocol_fee)?;
+                self.reserve_b = post_reserve_in;
+                self.reserve_a = post_reserve_out;
+            }
+        }
+        self.stats = stats;
+        Ok((quote, referral_fee))
+    }
+
+    pub fn deposit_liquidity(
+        &mut self,
+        amount_a: u64,
+        amount_b: u64,
+        min_lp_out: u64,
+    ) -> Result<DepositQuote> {
+        require!(!self.paused, AmmError::PoolPaused);
+        require!(amount_a > 0 && amount_b > 0, AmmError::InvalidAmount);
+
+        let quote = compute_deposit(
+            amount_a,
+            amount_b,
+            self.reserve_a,
+            self.reserve_b,
+            self.lp_supply,
+        )?;
+        require!(quote.lp_amount >= min_lp_out, AmmError::SlippageExceeded);
+
+        let reserve_a = checked_add(self.reserve_a, quote.amount_a)?;
+        let reserve_b = checked_add(self.reserve_b, quote.amount_b)?;
+        self.lp_supply = checked_add(self.lp_supply, quote.lp_amount)?;
+        self.reserve_a = reserve_a;
+        self.reserve_b = reserve_b;
+        Ok(quote)
+    }
+
+    pub fn withdraw_liquidity(
+        &mut self,
+        lp_amount: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<WithdrawQuote> {
+        require!(lp_amount > 0, AmmError::InvalidAmount);
+        // The SPL burn fails first on-chain when the holder has too little LP.
+        require!(lp_amount <= self.lp_supply, AmmError::InsufficientLiquidity);
+
+        let quote = compute_withdraw(lp_amount, self.reserve_a, self.reserve_b, self.lp_supply)?;
+        require!(quote.amount_a >= min_amount_a, AmmError::SlippageExceeded);
+        require!(quote.amount_b >= min_amount_b, AmmError::SlippageExceeded);
+
+        self.lp_supply -= lp_amount;
+        self.reserve_a -= quote.amount_a;
+        self.reserve_b -= quote.amount_b;
+        Ok(quote)
+    }
+}
+
+fn checked_add(balance: u64, amount: u64) -> Result<u64> {
+    Ok(balance.checked_add(amount).ok_or(AmmError::MathOverflow)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FEE_BPS, PROTOCOL_FEE_BPS};
+
+    #[test]
+    fn operations_track_program_balances() {
+        let mut pool = PoolSimulator::new(0, 0, 0, FEE_BPS, PROTOCOL_FEE_BPS);
+        let seeded = pool.deposit_liquidity(1_000_000, 4_000_000, 0).unwrap();
+        assert_eq!(seeded.lp_amount, 2_000_000);
+
+        let swap = pool.swap(10_000, 0, SwapDirection::AtoB).unwrap();
+        assert_eq!(
+            swap,
+            compute_swap(10_000, 1_000_000, 4_000_000, FEE_BPS, PROTOCOL_FEE_BPS).unwrap()
+        );
+        assert_eq!(pool.reserve_a, 1_000_000 + 10_000 - swap.protocol_fee);
+        assert_eq!(pool.reserve_b, 4_000_000 - swap.amount_out);
+        assert_eq!(pool.protocol_fees_a, swap.protocol_fee);
+        assert_eq!(
+            pool.stats,
+            PoolStats {
+                volume_a: 10_000,
+                swap_count: 1,
+                lp_fees_a: swap.lp_fee as u128,
+                protocol_fees_a: swap.protocol_fee as u128,
+                ..PoolStats::default()
+            }
+        );
+
+        let withdraw = pool.withdraw_liquidity(2_000_000, 0, 0).unwrap();
+        assert_eq!(
+            (withdraw.amount_a, withdraw.amount_b),
+            (
+                1_000_000 + 10_000 - swap.protocol_fee,
+                4_000_000 - swap.amount_out
+            )
+        );
+        assert_eq!((pool.reserve_a, pool.reserve_b, pool.lp_supply), (0, 0, 0));
+    }
+
+    #[test]
+    fn referral_fees_come_from_the_protocol_fee_or_on_top() {
+        let mut pool =
+            PoolSimulator::new(1_000_000, 4_000_000, 2_000_000, FEE_BPS, PROTOCOL_FEE_BPS);
+        pool.max_referral_fee_bps = PROTOCOL_FEE_BPS;
+        let mut on_top = pool;
+        on_top.referral_fee_mode = ReferralFeeMode::OnTop;
+
+        let (swap, referral_fee) = pool
+            .swap_with_referrer(100_000, 0, SwapDirection::AtoB, 3)
+            .unwrap();
+        assert_eq!(referral_fee, 30);
+        assert_eq!(pool.protocol_fees_a, swap.protocol_fee - 30);
+        assert_eq!(pool.stats.protocol_fees_a, (swap.protocol_fee - 30) as u128);
+
+        // Paid on top, the referral fee leaves the protocol's cut whole.
+        let (same, referral_fee) = on_top
+            .swap_with_referrer(100_000, 0, SwapDirection::AtoB, 3)
+            .unwrap();
+        assert_eq!((same, referral_fee), (swap, 30));
+        assert_eq!(on_top.protocol_fees_a, swap.protocol_fee);
+        assert_eq!(
+            (on_top.reserve_a, on_top.reserve_b),
+            (pool.reserve_a, pool.reserve_b)
+        );
+
+        let before = pool;
+        assert!(pool
+            .swap_with_referrer(100_000, 0, SwapDirection::AtoB, PROTOCOL_FEE_BPS + 1)
+            .is_err());
+        assert_eq!(pool, before);
+    }
+
+    #[test]
+    fn rejected_operations_leave_state_untouched() {
+        let mut pool =
+            PoolSimulator::new(1_000_000, 1_000_000, 1_000_000, FEE_BPS, PROTOCOL_FEE_BPS);
+        let before = pool;
+        assert!(pool.swap(10_000, u64::MAX, SwapDirection::BtoA).is_err());
+        assert!(pool.deposit_liquidity(1_000, 0, 0).is_err());
+        assert!(pool.withdraw_liquidity(1_000_001, 0, 0).is_err());
+        pool.paused = true;
+        assert!(pool.swap(10_000, 0, SwapDirection::AtoB).is_err());
+        pool.paused = false;
+        assert_eq!(pool, before);
+    }
+}
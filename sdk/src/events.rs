@@ -0,0 +1,363 @@
+//! Decoding of amm events.
+//!
+//! The program emits each event as a self-CPI signed by its event authority:
+//! an inner instruction to the amm program whose data is `EVENT_IX_TAG_LE`
+//! followed by the event discriminator and its Borsh encoding. Inner
+//! instructions are kept in full by RPC nodes, unlike logs, so
+//! [`parse_cpi_events`] is the way to read them.
+//!
+//! Builds with the `log-events` feature also write each event as a
+//! `Program data: <base64>` log line, which [`parse_events`] decodes. Only
+//! lines logged while the amm program is the innermost running program are
+//! decoded, so events from other programs, including flash loan receivers,
+//! are ignored.
+
+use anchor_lang::{event::EVENT_IX_TAG_LE, prelude::*, Discriminator};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use amm::{
+    AdminUpdatedEvent, DepositEvent, FeeDistributionUpdatedEvent, FeeRecipientUpdatedEvent,
+    FlashFeeUpdatedEvent, FlashLoanEvent, InitializeEvent, PauseEvent, ProtocolFeeWithdrawEvent,
+    ReferralFeeUpdatedEvent, SwapEvent, WithdrawEvent,
+};
+
+pub enum AmmEvent {
+    Initialize(InitializeEvent),
+    Swap(SwapEvent),
+    Deposit(DepositEvent),
+    Withdraw(WithdrawEvent),
+    ProtocolFeeWithdraw(ProtocolFeeWithdrawEvent),
+    FlashLoan(FlashLoanEvent),
+    FlashFeeUpdated(FlashFeeUpdatedEvent),
+    ReferralFeeUpdated(ReferralFeeUpdatedEvent),
+    FeeRecipientUpdated(FeeRecipientUpdatedEvent),
+    FeeDistributionUpdated(FeeDistributionUpdatedEvent),
+    Pause(PauseEvent),
+    AdminUpdated(AdminUpdatedEvent),
+}
+
+impl AmmEvent {
+    /// Decodes one event from its discriminator-prefixed bytes. Returns
+    /// `None` for data that isn't an amm event and for malformed data.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
+        let (discriminator, payload) = data.split_at(8);
+        let event = if discriminator == SwapEvent::DISCRIMINATOR {
+            AmmEvent::Swap(deserialize(payload)?)
+        } else if discriminator == DepositEvent::DISCRIMINATOR {
+            AmmEvent::Deposit(deserialize(payload)?)
+        } else if discriminator == WithdrawEvent::DISCRIMINATOR {
+            AmmEvent::Withdraw(deserialize(payload)?)
+        } else if discriminator == InitializeEvent::DISCRIMINATOR {
+            AmmEvent::Initialize(deserialize(payload)?)
+        } else if discriminator == ProtocolFeeWithdrawEvent::DISCRIMINATOR {
+            AmmEvent::ProtocolFeeWithdraw(deserialize(payload)?)
+        } else if discriminator == FlashLoanEvent::DISCRIMINATOR {
+            AmmEvent::FlashLoan(deserialize(payload)?)
+        } else if discriminator == FlashFeeUpdatedEvent::DISCRIMINATOR {
+            AmmEvent::FlashFeeUpdated(deserialize(payload)?)
+        } else if discriminator == ReferralFeeUpdatedEvent::DISCRIMINATOR {
+            AmmEvent::ReferralFeeUpdated(deserialize(payload)?)
+        } else if discriminator == FeeRecipientUpdatedEvent::DISCRIMINATOR {
+            AmmEvent::FeeRecipientUpdated(deserialize(payload)?)
+        } else if discriminator == FeeDistributionUpdatedEvent::DISCRIMINATOR {
+            AmmEvent::FeeDistributionUpdated(deserialize(payload)?)
+        } else if discriminator == PauseEvent::DISCRIMINATOR {
+            AmmEvent::Pause(deserialize(payload)?)
+        } else if discriminator == AdminUpdatedEvent::DISCRIMINATOR {
+            AmmEvent::AdminUpdated(deserialize(payload)?)
+        } else {
+            return None;
+        };
+        Some(event)
+    }
+}
+
+fn deserialize<E: AnchorDeserialize>(mut payload: &[u8]) -> Option<E> {
+    E::deserialize(&mut payload).ok()
+}
+
+/// Every event the amm program emitted, in order, from a transaction's inner
+/// instructions given as `(program_id, data)`.
+///
+/// Only the program's event authority can sign for the event instruction, so
+/// an instruction to the amm program carrying the event tag is an event the
+/// program emitted.
+pub fn parse_cpi_events<'a, I>(instructions: I) -> Vec<AmmEvent>
+where
+    I: IntoIterator<Item = (&'a Pubkey, &'a [u8])>,
+{
+    instructions
+        .into_iter()
+        .filter(|(program_id, _)| **program_id == amm::ID)
+        .filter_map(|(_, data)| data.strip_prefix(&EVENT_IX_TAG_LE[..]))
+        .filter_map(AmmEvent::decode)
+        .collect()
+}
+
+/// Every event the amm program logged, in order. Only builds with the
+/// `log-events` feature log events.
+pub fn parse_events<I, S>(logs: I) -> Vec<AmmEvent>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let program_id = amm::ID.to_string();
+    let mut invocations: Vec<String> = Vec::new();
+    let mut events = Vec::new();
+
+    for log in logs {
+        let log = log.as_ref();
+        if let Some(data) = log.strip_prefix("Program data: ") {
+            if invocations.last() != Some(&program_id) {
+                continue;
+            }
+            if let Some(event) = STANDARD
+                .decode(data)
+                .ok()
+                .and_then(|bytes| AmmEvent::decode(&bytes))
+            {
+                events.push(event);
+            }
+        } else if let Some(rest) = log.strip_prefix("Program ") {
+            let mut words = rest.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(program), Some("invoke")) => invocations.push(program.to_string()),
+                (Some(_), Some("success")) | (Some(_), Some("failed:")) => {
+                    invocations.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Event;
+
+    fn data_log(event: &impl Event) -> String {
+        format!("Program data: {}", STANDARD.encode(event.data()))
+    }
+
+    #[test]
+    fn parses_only_amm_events() {
+        let user = Pubkey::new_unique();
+        let pool = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        let swap = SwapEvent {
+            version: amm::EVENT_VERSION,
+            user,
+            pool,
+            amount_in: 10_000,
+            amount_out: 9_870,
+            direction: amm::SwapDirection::BtoA,
+            protocol_fee: 5,
+            amount_in_unfilled: 0,
+            lp_fee: 25,
+            reserve_a: 990_130,
+            reserve_b: 4_009_995,
+            lp_supply: Some(2_000_000),
+            spot_price: 4 << 64,
+            stats: amm::PoolStats {
+                volume_b: 10_000,
+                swap_count: 1,
+                lp_fees_b: 25,
+                protocol_fees_b: 5,
+                ..Default::default()
+            },
+            referrer: None,
+            referral_fee: 0,
+        };
+        let deposit = DepositEvent {
+            version: amm::EVENT_VERSION,
+            user,
+            pool,
+            amount_a_in: 1_000,
+            amount_b_in: 4_000,
+            lp_minted: 2_000,
+            reserve_a: 1_000,
+            reserve_b: 4_000,
+            lp_supply: 2_000,
+            spot_price: 4 << 64,
+        };
+        let withdraw = WithdrawEvent {
+            version: amm::EVENT_VERSION,
+            user,
+            pool,
+            lp_burned: 2_000,
+            amount_a_out: 1_000,
+            amount_b_out: 4_000,
+            reserve_a: 0,
+            reserve_b: 0,
+            lp_supply: 0,
+            spot_price: 0,
+        };
+        let logs = vec![
+            format!("Program {} invoke [1]", amm::ID),
+            "Program log: Instruction: FlashLoan".to_string(),
+            format!("Program {} invoke [2]", receiver),
+            // Same bytes, but logged by the receiver.
+            data_log(&deposit),
+            format!("Program {} success", receiver),
+            data_log(&swap),
+            format!("Program {} consumed 12345 of 200000 compute units", amm::ID),
+            format!("Program {} success", amm::ID),
+            format!("Program {} invoke [1]", amm::ID),
+            data_log(&deposit),
+            "Program data: not base64!".to_string(),
+            data_log(&withdraw),
+            format!("Program {} success", amm::ID),
+        ];
+
+        let events = parse_events(&logs);
+        assert_eq!(events.len(), 3);
+        match &events[0] {
+            AmmEvent::Swap(event) => {
+                assert_eq!(event.user, user);
+                assert_eq!((event.amount_in, event.amount_out), (10_000, 9_870));
+                assert!(event.direction == amm::SwapDirection::BtoA);
+                assert_eq!(event.protocol_fee, 5);
+                assert_eq!((event.reserve_a, event.reserve_b), (990_130, 4_009_995));
+                assert_eq!(event.stats.swap_count, 1);
+            }
+            _ => panic!("expected a swap event"),
+        }
+        match &events[1] {
+            AmmEvent::Deposit(event) => {
+                assert_eq!(event.pool, pool);
+                assert_eq!(
+                    (event.amount_a_in, event.amount_b_in, event.lp_minted),
+                    (1_000, 4_000, 2_000)
+                );
+            }
+            _ => panic!("expected a deposit event"),
+        }
+        match &events[2] {
+            AmmEvent::Withdraw(event) => {
+                assert_eq!(event.version, amm::EVENT_VERSION);
+                assert_eq!((event.lp_burned, event.lp_supply), (2_000, 0));
+            }
+            _ => panic!("expected a withdraw event"),
+        }
+    }
+
+    #[test]
+    fn parses_only_amm_event_instructions() {
+        let deposit = DepositEvent {
+            version: amm::EVENT_VERSION,
+            user: Pubkey::new_unique(),
+            pool: Pubkey::new_unique(),
+            amount_a_in: 1_000,
+            amount_b_in: 4_000,
+            lp_minted: 2_000,
+            reserve_a: 1_000,
+            reserve_b: 4_000,
+            lp_supply: 2_000,
+            spot_price: 4 << 64,
+        };
+        let event_data = [&EVENT_IX_TAG_LE[..], &deposit.data()].concat();
+        let receiver = Pubkey::new_unique();
+        let token_program = anchor_spl::token::ID;
+        let instructions = [
+            (&token_program, &[3, 16, 39, 0, 0, 0, 0, 0, 0][..]),
+            // Same bytes, but sent to another program.
+            (&receiver, &event_data[..]),
+            (&amm::ID, &event_data[..]),
+            // An amm instruction that isn't an event.
+            (&amm::ID, &deposit.data()[..]),
+        ];
+
+        let events = parse_cpi_events(instructions);
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            AmmEvent::Deposit(event) => {
+                assert_eq!(event.pool, deposit.pool);
+                assert_eq!(event.lp_minted, 2_000);
+            }
+            _ => panic!("expected a deposit event"),
+        }
+    }
+
+    #[test]
+    fn decode_rejects_unknown_and_short_data() {
+        assert!(AmmEvent::decode(&[0; 7]).is_none());
+        assert!(AmmEvent::decode(&[0; 64]).is_none());
+        let mut truncated = SwapEvent::DISCRIMINATOR.to_vec();
+        truncated.extend_from_slice(&[0; 16]);
+        assert!(AmmEvent::decode(&truncated).is_none());
+    }
+
+    #[test]
+    fn decodes_admin_events() {
+        let pause = PauseEvent {
+            admin: Pubkey::new_unique(),
+            pool: Pubkey::new_unique(),
+            paused: true,
+        };
+        match AmmEvent::decode(&pause.data()) {
+            Some(AmmEvent::Pause(event)) => {
+                assert_eq!((event.pool, event.paused), (pause.pool, true));
+            }
+            _ => panic!("expected a pause event"),
+        }
+        let admin = AdminUpdatedEvent {
+            pool: Pubkey::new_unique(),
+            old_admin: Pubkey::new_unique(),
+            new_admin: Pubkey::new_unique(),
+        };
+        match AmmEvent::decode(&admin.data()) {
+            Some(AmmEvent::AdminUpdated(event)) => assert_eq!(event.new_admin, admin.new_admin),
+            _ => panic!("expected an admin update event"),
+        }
+        let referral = ReferralFeeUpdatedEvent {
+            admin: Pubkey::new_unique(),
+            pool: Pubkey::new_unique(),
+            max_referral_fee_bps: 5,
+            referral_fee_mode: amm::ReferralFeeMode::OnTop,
+        };
+        match AmmEvent::decode(&referral.data()) {
+            Some(AmmEvent::ReferralFeeUpdated(event)) => assert_eq!(
+                (event.max_referral_fee_bps, event.referral_fee_mode),
+                (5, amm::ReferralFeeMode::OnTop)
+            ),
+            _ => panic!("expected a referral fee update event"),
+        }
+        let recipient = FeeRecipientUpdatedEvent {
+            admin: Pubkey::new_unique(),
+            pool: Pubkey::new_unique(),
+            old_fee_recipient: Pubkey::new_unique(),
+            new_fee_recipient: Pubkey::new_unique(),
+        };
+        match AmmEvent::decode(&recipient.data()) {
+            Some(AmmEvent::FeeRecipientUpdated(event)) => {
+                assert_eq!(event.new_fee_recipient, recipient.new_fee_recipient)
+            }
+            _ => panic!("expected a fee recipient update event"),
+        }
+        let distribution = FeeDistributionUpdatedEvent {
+            admin: Pubkey::new_unique(),
+            pool: Pubkey::new_unique(),
+            recipients: vec![
+                amm::FeeShare {
+                    owner: Pubkey::new_unique(),
+                    share_bps: 6_000,
+                },
+                amm::FeeShare {
+                    owner: Pubkey::new_unique(),
+                    share_bps: 4_000,
+                },
+            ],
+        };
+        match AmmEvent::decode(&distribution.data()) {
+            Some(AmmEvent::FeeDistributionUpdated(event)) => {
+                assert_eq!(event.recipients, distribution.recipients)
+            }
+            _ => panic!("expected a fee distribution update event"),
+        }
+    }
+}
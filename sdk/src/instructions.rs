@@ -0,0 +1,974 @@
+//! One builder per `amm` instruction.
+//!
+//! Account lists come from the program's generated `amm::accounts` structs and
+//! instruction data from `amm::instruction`, so metas and argument encoding
+//! always match the deployed interface.
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::{instruction::Instruction, system_program},
+    InstructionData,
+};
+use anchor_spl::token;
+
+use amm::{
+    accounts, instruction as ix, Deadline, FeeShare, ReferralFeeMode, SwapDirection, TokenSide,
+};
+
+use crate::pda::{event_authority_address, fee_distribution_address, PoolAddresses, UserAccounts};
+
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: amm::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+fn deposit_accounts(pool: &PoolAddresses, user: &UserAccounts) -> accounts::DepositLiquidity {
+    accounts::DepositLiquidity {
+        user: user.owner,
+        pool: pool.pool,
+        mint_a: pool.mint_a,
+        mint_b: pool.mint_b,
+        vault_a: pool.vault_a,
+        vault_b: pool.vault_b,
+        lp_mint: pool.lp_mint,
+        user_ata_a: user.token_a,
+        user_ata_b: user.token_b,
+        user_lp: user.lp,
+        token_program: token::ID,
+        event_authority: event_authority_address().0,
+        program: amm::ID,
+    }
+}
+
+fn withdraw_accounts(pool: &PoolAddresses, user: &UserAccounts) -> accounts::WithdrawLiquidity {
+    accounts::WithdrawLiquidity {
+        user: user.owner,
+        pool: pool.pool,
+        mint_a: pool.mint_a,
+        mint_b: pool.mint_b,
+        vault_a: pool.vault_a,
+        vault_b: pool.vault_b,
+        lp_mint: pool.lp_mint,
+        user_ata_a: user.token_a,
+        user_ata_b: user.token_b,
+        user_lp: user.lp,
+        token_program: token::ID,
+        event_authority: event_authority_address().0,
+        program: amm::ID,
+    }
+}
+
+/// Swap accounts with the user's source and destination picked by `direction`.
+fn swap_accounts(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    direction: SwapDirection,
+) -> accounts::Swap {
+    let (user_source, user_destination) = match direction {
+        SwapDirection::AtoB => (user.token_a, user.token_b),
+        SwapDirection::BtoA => (user.token_b, user.token_a),
+    };
+    accounts::Swap {
+        user: user.owner,
+        pool: pool.pool,
+        mint_a: pool.mint_a,
+        mint_b: pool.mint_b,
+        vault_a: pool.vault_a,
+        vault_b: pool.vault_b,
+        fee_vault_a: pool.fee_vault_a,
+        fee_vault_b: pool.fee_vault_b,
+        user_source,
+        user_destination,
+        referrer: None,
+        token_program: token::ID,
+        event_authority: event_authority_address().0,
+        program: amm::ID,
+    }
+}
+
+fn quote_accounts(pool: &PoolAddresses) -> accounts::Quote {
+    accounts::Quote {
+        pool: pool.pool,
+        vault_a: pool.vault_a,
+        vault_b: pool.vault_b,
+        lp_mint: pool.lp_mint,
+    }
+}
+
+fn user_token(user: &UserAccounts, side: TokenSide) -> Pubkey {
+    match side {
+        TokenSide::A => user.token_a,
+        TokenSide::B => user.token_b,
+    }
+}
+
+/// Creates the pool for `pool.mint_a`/`pool.mint_b`, which must come from
+/// [`PoolAddresses::derive`].
+pub fn initialize(pool: &PoolAddresses, payer: &Pubkey, admin: &Pubkey) -> Instruction {
+    build(
+        accounts::Initialize {
+            payer: *payer,
+            admin: *admin,
+            pool: pool.pool,
+            mint_a: pool.mint_a,
+            mint_b: pool.mint_b,
+            vault_a: pool.vault_a,
+            vault_b: pool.vault_b,
+            lp_mint: pool.lp_mint,
+            fee_vault_a: pool.fee_vault_a,
+            fee_vault_b: pool.fee_vault_b,
+            system_program: system_program::ID,
+            token_program: token::ID,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::Initialize {},
+    )
+}
+
+pub fn deposit_liquidity(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_a: u64,
+    amount_b: u64,
+    min_lp_out: u64,
+) -> Instruction {
+    build(
+        deposit_accounts(pool, user),
+        ix::DepositLiquidity {
+            amount_a,
+            amount_b,
+            min_lp_out,
+        },
+    )
+}
+
+pub fn deposit_exact_lp(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    lp_amount: u64,
+    max_amount_a: u64,
+    max_amount_b: u64,
+) -> Instruction {
+    build(
+        deposit_accounts(pool, user),
+        ix::DepositExactLp {
+            lp_amount,
+            max_amount_a,
+            max_amount_b,
+        },
+    )
+}
+
+pub fn deposit_single_sided(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_in: u64,
+    side: TokenSide,
+    min_lp_out: u64,
+) -> Instruction {
+    build(
+        accounts::DepositSingleSided {
+            user: user.owner,
+            pool: pool.pool,
+            mint_a: pool.mint_a,
+            mint_b: pool.mint_b,
+            vault_a: pool.vault_a,
+            vault_b: pool.vault_b,
+            lp_mint: pool.lp_mint,
+            fee_vault_a: pool.fee_vault_a,
+            fee_vault_b: pool.fee_vault_b,
+            user_source: user_token(user, side),
+            user_lp: user.lp,
+            token_program: token::ID,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::DepositSingleSided {
+            amount_in,
+            side,
+            min_lp_out,
+        },
+    )
+}
+
+pub fn deposit_liquidity_with_deadline(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_a: u64,
+    amount_b: u64,
+    min_lp_out: u64,
+    deadline: Option<Deadline>,
+) -> Instruction {
+    build(
+        deposit_accounts(pool, user),
+        ix::DepositLiquidityWithDeadline {
+            amount_a,
+            amount_b,
+            min_lp_out,
+            deadline,
+        },
+    )
+}
+
+pub fn deposit_liquidity_strict(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_a: u64,
+    amount_b: u64,
+    min_lp_out: u64,
+    max_ratio_deviation_bps: u16,
+) -> Instruction {
+    build(
+        deposit_accounts(pool, user),
+        ix::DepositLiquidityStrict {
+            amount_a,
+            amount_b,
+            min_lp_out,
+            max_ratio_deviation_bps,
+        },
+    )
+}
+
+// Mirrors the instruction's arguments one to one, like every builder here.
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_liquidity_guarded(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_a: u64,
+    amount_b: u64,
+    min_lp_out: u64,
+    deadline: Option<Deadline>,
+    min_price: Option<u128>,
+    max_price: Option<u128>,
+) -> Instruction {
+    build(
+        deposit_accounts(pool, user),
+        ix::DepositLiquidityGuarded {
+            amount_a,
+            amount_b,
+            min_lp_out,
+            deadline,
+            min_price,
+            max_price,
+        },
+    )
+}
+
+pub fn withdraw_liquidity(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    lp_amount: u64,
+    min_amount_a: u64,
+    min_amount_b: u64,
+) -> Instruction {
+    build(
+        withdraw_accounts(pool, user),
+        ix::WithdrawLiquidity {
+            lp_amount,
+            min_amount_a,
+            min_amount_b,
+        },
+    )
+}
+
+pub fn withdraw_single_sided(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    lp_amount: u64,
+    side: TokenSide,
+    min_amount_out: u64,
+) -> Instruction {
+    build(
+        accounts::WithdrawSingleSided {
+            user: user.owner,
+            pool: pool.pool,
+            mint_a: pool.mint_a,
+            mint_b: pool.mint_b,
+            vault_a: pool.vault_a,
+            vault_b: pool.vault_b,
+            lp_mint: pool.lp_mint,
+            fee_vault_a: pool.fee_vault_a,
+            fee_vault_b: pool.fee_vault_b,
+            user_destination: user_token(user, side),
+            user_lp: user.lp,
+            token_program: token::ID,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::WithdrawSingleSided {
+            lp_amount,
+            side,
+            min_amount_out,
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_liquidity_guarded(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    lp_amount: u64,
+    min_amount_a: u64,
+    min_amount_b: u64,
+    deadline: Option<Deadline>,
+    min_price: Option<u128>,
+    max_price: Option<u128>,
+) -> Instruction {
+    build(
+        withdraw_accounts(pool, user),
+        ix::WithdrawLiquidityGuarded {
+            lp_amount,
+            min_amount_a,
+            min_amount_b,
+            deadline,
+            min_price,
+            max_price,
+        },
+    )
+}
+
+pub fn withdraw_liquidity_with_deadline(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    lp_amount: u64,
+    min_amount_a: u64,
+    min_amount_b: u64,
+    deadline: Option<Deadline>,
+) -> Instruction {
+    build(
+        withdraw_accounts(pool, user),
+        ix::WithdrawLiquidityWithDeadline {
+            lp_amount,
+            min_amount_a,
+            min_amount_b,
+            deadline,
+        },
+    )
+}
+
+pub fn swap(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_in: u64,
+    min_amount_out: u64,
+    direction: SwapDirection,
+) -> Instruction {
+    build(
+        swap_accounts(pool, user, direction),
+        ix::Swap {
+            amount_in,
+            min_amount_out,
+            direction,
+            referral_fee_bps: 0,
+        },
+    )
+}
+
+/// `swap` paying `referral_fee_bps` of `amount_in` to `referrer`, a token
+/// account for the input mint.
+pub fn swap_with_referrer(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_in: u64,
+    min_amount_out: u64,
+    direction: SwapDirection,
+    referrer: &Pubkey,
+    referral_fee_bps: u16,
+) -> Instruction {
+    build(
+        accounts::Swap {
+            referrer: Some(*referrer),
+            ..swap_accounts(pool, user, direction)
+        },
+        ix::Swap {
+            amount_in,
+            min_amount_out,
+            direction,
+            referral_fee_bps,
+        },
+    )
+}
+
+pub fn swap_with_price_limit(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_in: u64,
+    min_price: u128,
+    direction: SwapDirection,
+) -> Instruction {
+    build(
+        swap_accounts(pool, user, direction),
+        ix::SwapWithPriceLimit {
+            amount_in,
+            min_price,
+            direction,
+        },
+    )
+}
+
+pub fn swap_with_deadline(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_in: u64,
+    min_amount_out: u64,
+    direction: SwapDirection,
+    deadline: Option<Deadline>,
+) -> Instruction {
+    build(
+        swap_accounts(pool, user, direction),
+        ix::SwapWithDeadline {
+            amount_in,
+            min_amount_out,
+            direction,
+            deadline,
+        },
+    )
+}
+
+/// `swap` without the mints and the output-side fee vault.
+pub fn swap_v2(
+    pool: &PoolAddresses,
+    user: &UserAccounts,
+    amount_in: u64,
+    min_amount_out: u64,
+    direction: SwapDirection,
+) -> Instruction {
+    let (user_source, user_destination, vault_in, vault_out, fee_vault_in) = match direction {
+        SwapDirection::AtoB => (
+            user.token_a,
+            user.token_b,
+            pool.vault_a,
+            pool.vault_b,
+            pool.fee_vault_a,
+        ),
+        SwapDirection::BtoA => (
+            user.token_b,
+            user.token_a,
+            pool.vault_b,
+            pool.vault_a,
+            pool.fee_vault_b,
+        ),
+    };
+    build(
+        accounts::SwapV2 {
+            user: user.owner,
+            pool: pool.pool,
+            user_source,
+            user_destination,
+            vault_in,
+            vault_out,
+            fee_vault_in,
+            lp_mint: pool.lp_mint,
+            token_program: token::ID,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::SwapV2 {
+            amount_in,
+            min_amount_out,
+            direction,
+        },
+    )
+}
+
+pub fn quote_swap(pool: &PoolAddresses, amount_in: u64, direction: SwapDirection) -> Instruction {
+    build(
+        quote_accounts(pool),
+        ix::QuoteSwap {
+            amount_in,
+            direction,
+        },
+    )
+}
+
+pub fn quote_swap_exact_out(
+    pool: &PoolAddresses,
+    amount_out: u64,
+    direction: SwapDirection,
+) -> Instruction {
+    build(
+        quote_accounts(pool),
+        ix::QuoteSwapExactOut {
+            amount_out,
+            direction,
+        },
+    )
+}
+
+pub fn quote_deposit(pool: &PoolAddresses, amount_a: u64, amount_b: u64) -> Instruction {
+    build(
+        quote_accounts(pool),
+        ix::QuoteDeposit { amount_a, amount_b },
+    )
+}
+
+pub fn quote_withdraw(pool: &PoolAddresses, lp_amount: u64) -> Instruction {
+    build(quote_accounts(pool), ix::QuoteWithdraw { lp_amount })
+}
+
+/// Returns the pool's `PoolStats` as return data.
+pub fn pool_stats(pool: &PoolAddresses) -> Instruction {
+    build(quote_accounts(pool), ix::PoolStats {})
+}
+
+/// Pays protocol fees to `treasury.token_a` and `treasury.token_b`, which
+/// must belong to the pool's fee recipient. Needs no signature.
+pub fn withdraw_protocol_fees(
+    pool: &PoolAddresses,
+    treasury: &UserAccounts,
+    amount_a: u64,
+    amount_b: u64,
+) -> Instruction {
+    build(
+        accounts::WithdrawProtocolFees {
+            pool: pool.pool,
+            fee_distribution: fee_distribution_address(&pool.pool).0,
+            mint_a: pool.mint_a,
+            mint_b: pool.mint_b,
+            fee_vault_a: pool.fee_vault_a,
+            fee_vault_b: pool.fee_vault_b,
+            treasury_a: treasury.token_a,
+            treasury_b: treasury.token_b,
+            token_program: token::ID,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::WithdrawProtocolFees { amount_a, amount_b },
+    )
+}
+
+/// Lends to `borrower.token_a`/`borrower.token_b` and calls
+/// `receiver_program` with `data` and `receiver_accounts`, which the program
+/// forwards as the receiver instruction's accounts.
+pub fn flash_loan(
+    pool: &PoolAddresses,
+    borrower: &UserAccounts,
+    receiver_program: &Pubkey,
+    amount_a: u64,
+    amount_b: u64,
+    data: Vec<u8>,
+    receiver_accounts: Vec<AccountMeta>,
+) -> Instruction {
+    let mut instruction = build(
+        accounts::FlashLoan {
+            user: borrower.owner,
+            pool: pool.pool,
+            mint_a: pool.mint_a,
+            mint_b: pool.mint_b,
+            vault_a: pool.vault_a,
+            vault_b: pool.vault_b,
+            fee_vault_a: pool.fee_vault_a,
+            fee_vault_b: pool.fee_vault_b,
+            borrower_ata_a: borrower.token_a,
+            borrower_ata_b: borrower.token_b,
+            receiver_program: *receiver_program,
+            token_program: token::ID,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::FlashLoan {
+            amount_a,
+            amount_b,
+            data,
+        },
+    );
+    instruction.accounts.extend(receiver_accounts);
+    instruction
+}
+
+pub fn set_flash_fee(pool: &Pubkey, admin: &Pubkey, flash_fee_bps: u16) -> Instruction {
+    build(
+        accounts::SetFlashFee {
+            admin: *admin,
+            pool: *pool,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::SetFlashFee { flash_fee_bps },
+    )
+}
+
+pub fn set_referral_fee(
+    pool: &Pubkey,
+    admin: &Pubkey,
+    max_referral_fee_bps: u16,
+    referral_fee_mode: ReferralFeeMode,
+) -> Instruction {
+    build(
+        accounts::SetReferralFee {
+            admin: *admin,
+            pool: *pool,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::SetReferralFee {
+            max_referral_fee_bps,
+            referral_fee_mode,
+        },
+    )
+}
+
+pub fn set_fee_recipient(pool: &Pubkey, admin: &Pubkey, fee_recipient: &Pubkey) -> Instruction {
+    build(
+        accounts::SetFeeRecipient {
+            admin: *admin,
+            pool: *pool,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::SetFeeRecipient {
+            fee_recipient: *fee_recipient,
+        },
+    )
+}
+
+pub fn migrate_pool(pool: &Pubkey, admin: &Pubkey) -> Instruction {
+    build(
+        accounts::MigratePool {
+            admin: *admin,
+            pool: *pool,
+            system_program: system_program::ID,
+        },
+        ix::MigratePool {},
+    )
+}
+
+pub fn set_fee_distribution(
+    pool: &Pubkey,
+    admin: &Pubkey,
+    recipients: Vec<FeeShare>,
+) -> Instruction {
+    build(
+        accounts::SetFeeDistribution {
+            admin: *admin,
+            pool: *pool,
+            fee_distribution: fee_distribution_address(pool).0,
+            system_program: system_program::ID,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::SetFeeDistribution { recipients },
+    )
+}
+
+/// Pays out the fee vaults to `recipients`, which must list each share's
+/// token accounts in the pool's fee distribution order.
+pub fn distribute_protocol_fees(pool: &PoolAddresses, recipients: &[UserAccounts]) -> Instruction {
+    let mut instruction = build(
+        accounts::DistributeProtocolFees {
+            pool: pool.pool,
+            fee_distribution: fee_distribution_address(&pool.pool).0,
+            fee_vault_a: pool.fee_vault_a,
+            fee_vault_b: pool.fee_vault_b,
+            token_program: token::ID,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::DistributeProtocolFees {},
+    );
+    instruction
+        .accounts
+        .extend(recipients.iter().flat_map(|recipient| {
+            [
+                AccountMeta::new(recipient.token_a, false),
+                AccountMeta::new(recipient.token_b, false),
+            ]
+        }));
+    instruction
+}
+
+pub fn set_pause(pool: &Pubkey, admin: &Pubkey, paused: bool) -> Instruction {
+    build(
+        accounts::SetPause {
+            admin: *admin,
+            pool: *pool,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::SetPause { paused },
+    )
+}
+
+/// Hands the pool to `new_admin`, which must also sign.
+pub fn set_admin(pool: &Pubkey, admin: &Pubkey, new_admin: &Pubkey) -> Instruction {
+    build(
+        accounts::SetAdmin {
+            admin: *admin,
+            new_admin: *new_admin,
+            pool: *pool,
+            event_authority: event_authority_address().0,
+            program: amm::ID,
+        },
+        ix::SetAdmin {},
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Discriminator;
+
+    fn fixtures() -> (PoolAddresses, UserAccounts) {
+        let pool = PoolAddresses::derive(Pubkey::new_unique(), Pubkey::new_unique());
+        let user = UserAccounts::associated(Pubkey::new_unique(), &pool);
+        (pool, user)
+    }
+
+    #[test]
+    fn every_builder_targets_its_instruction() {
+        let (pool, user) = fixtures();
+        let admin = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        let cases = [
+            (
+                initialize(&pool, &admin, &admin),
+                ix::Initialize::DISCRIMINATOR,
+            ),
+            (
+                deposit_liquidity(&pool, &user, 1, 2, 3),
+                ix::DepositLiquidity::DISCRIMINATOR,
+            ),
+            (
+                deposit_exact_lp(&pool, &user, 1, 2, 3),
+                ix::DepositExactLp::DISCRIMINATOR,
+            ),
+            (
+                deposit_single_sided(&pool, &user, 1, TokenSide::A, 3),
+                ix::DepositSingleSided::DISCRIMINATOR,
+            ),
+            (
+                deposit_liquidity_with_deadline(&pool, &user, 1, 2, 3, None),
+                ix::DepositLiquidityWithDeadline::DISCRIMINATOR,
+            ),
+            (
+                deposit_liquidity_strict(&pool, &user, 1, 2, 3, 4),
+                ix::DepositLiquidityStrict::DISCRIMINATOR,
+            ),
+            (
+                deposit_liquidity_guarded(&pool, &user, 1, 2, 3, None, None, None),
+                ix::DepositLiquidityGuarded::DISCRIMINATOR,
+            ),
+            (
+                withdraw_liquidity(&pool, &user, 1, 2, 3),
+                ix::WithdrawLiquidity::DISCRIMINATOR,
+            ),
+            (
+                withdraw_single_sided(&pool, &user, 1, TokenSide::B, 3),
+                ix::WithdrawSingleSided::DISCRIMINATOR,
+            ),
+            (
+                withdraw_liquidity_guarded(&pool, &user, 1, 2, 3, None, None, None),
+                ix::WithdrawLiquidityGuarded::DISCRIMINATOR,
+            ),
+            (
+                withdraw_liquidity_with_deadline(&pool, &user, 1, 2, 3, None),
+                ix::WithdrawLiquidityWithDeadline::DISCRIMINATOR,
+            ),
+            (
+                swap(&pool, &user, 1, 2, SwapDirection::AtoB),
+                ix::Swap::DISCRIMINATOR,
+            ),
+            (
+                swap_with_referrer(&pool, &user, 1, 2, SwapDirection::AtoB, &receiver, 3),
+                ix::Swap::DISCRIMINATOR,
+            ),
+            (
+                swap_with_price_limit(&pool, &user, 1, 2, SwapDirection::AtoB),
+                ix::SwapWithPriceLimit::DISCRIMINATOR,
+            ),
+            (
+                swap_with_deadline(&pool, &user, 1, 2, SwapDirection::AtoB, None),
+                ix::SwapWithDeadline::DISCRIMINATOR,
+            ),
+            (
+                swap_v2(&pool, &user, 1, 2, SwapDirection::AtoB),
+                ix::SwapV2::DISCRIMINATOR,
+            ),
+            (
+                quote_swap(&pool, 1, SwapDirection::AtoB),
+                ix::QuoteSwap::DISCRIMINATOR,
+            ),
+            (
+                quote_swap_exact_out(&pool, 1, SwapDirection::AtoB),
+                ix::QuoteSwapExactOut::DISCRIMINATOR,
+            ),
+            (quote_deposit(&pool, 1, 2), ix::QuoteDeposit::DISCRIMINATOR),
+            (quote_withdraw(&pool, 1), ix::QuoteWithdraw::DISCRIMINATOR),
+            (pool_stats(&pool), ix::PoolStats::DISCRIMINATOR),
+            (
+                withdraw_protocol_fees(&pool, &user, 1, 2),
+                ix::WithdrawProtocolFees::DISCRIMINATOR,
+            ),
+            (
+                flash_loan(&pool, &user, &receiver, 1, 2, vec![], vec![]),
+                ix::FlashLoan::DISCRIMINATOR,
+            ),
+            (
+                set_flash_fee(&pool.pool, &admin, 9),
+                ix::SetFlashFee::DISCRIMINATOR,
+            ),
+            (
+                set_referral_fee(&pool.pool, &admin, 5, ReferralFeeMode::OnTop),
+                ix::SetReferralFee::DISCRIMINATOR,
+            ),
+            (
+                set_fee_recipient(&pool.pool, &admin, &receiver),
+                ix::SetFeeRecipient::DISCRIMINATOR,
+            ),
+            (
+                set_fee_distribution(&pool.pool, &admin, vec![]),
+                ix::SetFeeDistribution::DISCRIMINATOR,
+            ),
+            (
+                distribute_protocol_fees(&pool, &[user]),
+                ix::DistributeProtocolFees::DISCRIMINATOR,
+            ),
+            (
+                set_pause(&pool.pool, &admin, true),
+                ix::SetPause::DISCRIMINATOR,
+            ),
+            (
+                set_admin(&pool.pool, &admin, &receiver),
+                ix::SetAdmin::DISCRIMINATOR,
+            ),
+        ];
+        for (instruction, discriminator) in cases {
+            assert_eq!(instruction.program_id, amm::ID);
+            assert_eq!(instruction.data[..8], discriminator);
+        }
+    }
+
+    #[test]
+    fn swap_routes_user_accounts_by_direction() {
+        let (pool, user) = fixtures();
+        let instruction = swap(&pool, &user, 1_000, 990, SwapDirection::BtoA);
+
+        let args = ix::Swap::deserialize(&mut &instruction.data[8..]).unwrap();
+        assert_eq!((args.amount_in, args.min_amount_out), (1_000, 990));
+        assert!(args.direction == SwapDirection::BtoA);
+        assert_eq!(args.referral_fee_bps, 0);
+
+        let metas = &instruction.accounts;
+        assert_eq!(metas.len(), 14);
+        assert_eq!(metas[0], AccountMeta::new(user.owner, true));
+        assert_eq!(metas[1], AccountMeta::new(pool.pool, false));
+        assert_eq!(metas[2], AccountMeta::new_readonly(pool.mint_a, false));
+        assert_eq!(metas[8], AccountMeta::new(user.token_b, false));
+        assert_eq!(metas[9], AccountMeta::new(user.token_a, false));
+        // No referrer: the program id stands in for it.
+        assert_eq!(metas[10], AccountMeta::new_readonly(amm::ID, false));
+        assert_eq!(metas[11], AccountMeta::new_readonly(token::ID, false));
+        assert_eq!(
+            metas[12],
+            AccountMeta::new_readonly(event_authority_address().0, false)
+        );
+        assert_eq!(metas[13], AccountMeta::new_readonly(amm::ID, false));
+    }
+
+    #[test]
+    fn swap_with_referrer_passes_the_referrer() {
+        let (pool, user) = fixtures();
+        let referrer = Pubkey::new_unique();
+        let instruction =
+            swap_with_referrer(&pool, &user, 1_000, 990, SwapDirection::AtoB, &referrer, 5);
+
+        let args = ix::Swap::deserialize(&mut &instruction.data[8..]).unwrap();
+        assert_eq!(args.referral_fee_bps, 5);
+        assert_eq!(instruction.accounts.len(), 14);
+        assert_eq!(instruction.accounts[10], AccountMeta::new(referrer, false));
+    }
+
+    #[test]
+    fn swap_v2_passes_only_the_input_side_fee_vault() {
+        let (pool, user) = fixtures();
+        let instruction = swap_v2(&pool, &user, 1_000, 990, SwapDirection::BtoA);
+
+        assert_eq!(
+            instruction.accounts,
+            vec![
+                AccountMeta::new_readonly(user.owner, true),
+                AccountMeta::new(pool.pool, false),
+                AccountMeta::new(user.token_b, false),
+                AccountMeta::new(user.token_a, false),
+                AccountMeta::new(pool.vault_b, false),
+                AccountMeta::new(pool.vault_a, false),
+                AccountMeta::new(pool.fee_vault_b, false),
+                AccountMeta::new_readonly(pool.lp_mint, false),
+                AccountMeta::new_readonly(token::ID, false),
+                AccountMeta::new_readonly(event_authority_address().0, false),
+                AccountMeta::new_readonly(amm::ID, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn flash_loan_appends_receiver_accounts() {
+        let (pool, user) = fixtures();
+        let receiver = Pubkey::new_unique();
+        let forwarded = AccountMeta::new(Pubkey::new_unique(), false);
+        let instruction = flash_loan(
+            &pool,
+            &user,
+            &receiver,
+            5,
+            0,
+            vec![7, 8],
+            vec![forwarded.clone()],
+        );
+
+        let args = ix::FlashLoan::deserialize(&mut &instruction.data[8..]).unwrap();
+        assert_eq!((args.amount_a, args.amount_b), (5, 0));
+        assert_eq!(args.data, vec![7, 8]);
+        assert_eq!(instruction.accounts.len(), 15);
+        assert_eq!(
+            instruction.accounts[10],
+            AccountMeta::new_readonly(receiver, false)
+        );
+        assert_eq!(instruction.accounts[14], forwarded);
+    }
+
+    #[test]
+    fn distribute_protocol_fees_appends_recipient_accounts() {
+        let (pool, user) = fixtures();
+        let other = UserAccounts::associated(Pubkey::new_unique(), &pool);
+        let instruction = distribute_protocol_fees(&pool, &[user, other]);
+
+        let metas = &instruction.accounts;
+        assert_eq!(metas.len(), 11);
+        assert_eq!(
+            metas[1],
+            AccountMeta::new_readonly(fee_distribution_address(&pool.pool).0, false)
+        );
+        assert_eq!(
+            metas[7..],
+            [
+                AccountMeta::new(user.token_a, false),
+                AccountMeta::new(user.token_b, false),
+                AccountMeta::new(other.token_a, false),
+                AccountMeta::new(other.token_b, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_admin_requires_both_signatures() {
+        let pool = Pubkey::new_unique();
+        let admin = Pubkey::new_unique();
+        let new_admin = Pubkey::new_unique();
+        let instruction = set_admin(&pool, &admin, &new_admin);
+        assert_eq!(
+            instruction.accounts,
+            vec![
+                AccountMeta::new(admin, true),
+                AccountMeta::new_readonly(new_admin, true),
+                AccountMeta::new(pool, false),
+                AccountMeta::new_readonly(event_authority_address().0, false),
+                AccountMeta::new_readonly(amm::ID, false),
+            ]
+        );
+    }
+}
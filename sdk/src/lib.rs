@@ -0,0 +1,72 @@
+//! Client helpers for the `amm` program: PDA derivation, instruction
+//! builders, `Pool` decoding and event parsing.
+//!
+//! Everything here is built on the program's own Anchor types, so a change to
+//! an instruction's arguments or accounts breaks this crate at compile time
+//! instead of on-chain.
+
+pub mod events;
+pub mod instructions;
+pub mod pda;
+
+use anchor_lang::{prelude::*, AccountDeserialize};
+
+pub use amm::{
+    simulator::PoolSimulator, Deadline, DepositQuote, FeeDistribution, FeeShare, Pool, PoolStats,
+    ReferralFeeMode, SwapDirection, SwapQuote, TokenSide, WithdrawQuote, ID,
+};
+pub use events::{parse_cpi_events, parse_events, AmmEvent};
+pub use pda::{PoolAddresses, UserAccounts};
+
+/// Decodes a `Pool` account, checking its discriminator.
+pub fn decode_pool(data: &[u8]) -> Result<Pool> {
+    Pool::try_deserialize(&mut &data[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::AccountSerialize;
+
+    #[test]
+    fn decode_pool_round_trips_account_data() {
+        let pool = Pool {
+            mint_a: Pubkey::new_unique(),
+            mint_b: Pubkey::new_unique(),
+            vault_a: Pubkey::new_unique(),
+            vault_b: Pubkey::new_unique(),
+            lp_mint: Pubkey::new_unique(),
+            fee_vault_a: Pubkey::new_unique(),
+            fee_vault_b: Pubkey::new_unique(),
+            admin: Pubkey::new_unique(),
+            bump: 254,
+            fee_bps: 30,
+            protocol_fee_bps: 5,
+            paused: true,
+            flash_fee_bps: 9,
+            stats: PoolStats {
+                swap_count: 3,
+                ..PoolStats::default()
+            },
+            max_referral_fee_bps: 0,
+            referral_fee_mode: ReferralFeeMode::FromProtocolFee,
+            fee_recipient: Pubkey::new_unique(),
+        };
+        let mut data = Vec::with_capacity(Pool::LEN);
+        pool.try_serialize(&mut data).unwrap();
+        data.resize(Pool::LEN, 0);
+
+        let decoded = decode_pool(&data).unwrap();
+        assert_eq!(decoded.mint_a, pool.mint_a);
+        assert_eq!(decoded.fee_vault_b, pool.fee_vault_b);
+        assert_eq!(decoded.admin, pool.admin);
+        assert_eq!(decoded.bump, 254);
+        assert_eq!((decoded.fee_bps, decoded.protocol_fee_bps), (30, 5));
+        assert!(decoded.paused);
+        assert_eq!(decoded.flash_fee_bps, 9);
+        assert_eq!(decoded.stats.swap_count, 3);
+
+        data[0] ^= 1;
+        assert!(decode_pool(&data).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Program-derived addresses, using the same seeds as `Initialize` and
+//! `#[event_cpi]`.
+
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::get_associated_token_address;
+
+use amm::Pool;
+
+pub fn pool_address(mint_a: &Pubkey, mint_b: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"pool", mint_a.as_ref(), mint_b.as_ref()], &amm::ID)
+}
+
+pub fn vault_a_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault_a", pool.as_ref()], &amm::ID)
+}
+
+pub fn vault_b_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault_b", pool.as_ref()], &amm::ID)
+}
+
+pub fn lp_mint_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"lp_mint", pool.as_ref()], &amm::ID)
+}
+
+pub fn fee_vault_a_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"fee_vault_a", pool.as_ref()], &amm::ID)
+}
+
+pub fn fee_vault_b_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"fee_vault_b", pool.as_ref()], &amm::ID)
+}
+
+/// The pool's `FeeDistribution` account, which exists once
+/// `set_fee_distribution` has been called.
+pub fn fee_distribution_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"fee_distribution", pool.as_ref()], &amm::ID)
+}
+
+/// The PDA that signs the amm program's event CPIs. It is the same for
+/// every pool.
+pub fn event_authority_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"__event_authority"], &amm::ID)
+}
+
+/// Every account a pool owns, keyed by its mints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolAddresses {
+    pub pool: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub vault_a: Pubkey,
+    pub vault_b: Pubkey,
+    pub lp_mint: Pubkey,
+    pub fee_vault_a: Pubkey,
+    pub fee_vault_b: Pubkey,
+}
+
+impl PoolAddresses {
+    pub fn derive(mint_a: Pubkey, mint_b: Pubkey) -> Self {
+        let (pool, _) = pool_address(&mint_a, &mint_b);
+        PoolAddresses {
+            pool,
+            mint_a,
+            mint_b,
+            vault_a: vault_a_address(&pool).0,
+            vault_b: vault_b_address(&pool).0,
+            lp_mint: lp_mint_address(&pool).0,
+            fee_vault_a: fee_vault_a_address(&pool).0,
+            fee_vault_b: fee_vault_b_address(&pool).0,
+        }
+    }
+
+    /// Addresses recorded in a decoded `Pool` account, without re-deriving.
+    pub fn from_pool(pool_address: Pubkey, pool: &Pool) -> Self {
+        PoolAddresses {
+            pool: pool_address,
+            mint_a: pool.mint_a,
+            mint_b: pool.mint_b,
+            vault_a: pool.vault_a,
+            vault_b: pool.vault_b,
+            lp_mint: pool.lp_mint,
+            fee_vault_a: pool.fee_vault_a,
+            fee_vault_b: pool.fee_vault_b,
+        }
+    }
+}
+
+/// A wallet and its token accounts for one pool's mints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UserAccounts {
+    pub owner: Pubkey,
+    pub token_a: Pubkey,
+    pub token_b: Pubkey,
+    pub lp: Pubkey,
+}
+
+impl UserAccounts {
+    /// The owner's associated token accounts for `mint_a`, `mint_b` and the
+    /// LP mint.
+    pub fn associated(owner: Pubkey, pool: &PoolAddresses) -> Self {
+        UserAccounts {
+            owner,
+            token_a: get_associated_token_address(&owner, &pool.mint_a),
+            token_b: get_associated_token_address(&owner, &pool.mint_b),
+            lp: get_associated_token_address(&owner, &pool.lp_mint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derived_addresses_match_initialize_seeds() {
+        let mint_a = Pubkey::new_unique();
+        let mint_b = Pubkey::new_unique();
+        let addresses = PoolAddresses::derive(mint_a, mint_b);
+
+        let (pool, bump) = pool_address(&mint_a, &mint_b);
+        assert_eq!(addresses.pool, pool);
+        assert_eq!(
+            Pubkey::create_program_address(
+                &[b"pool", mint_a.as_ref(), mint_b.as_ref(), &[bump]],
+                &amm::ID
+            )
+            .unwrap(),
+            pool
+        );
+        for (address, seed) in [
+            (addresses.vault_a, &b"vault_a"[..]),
+            (addresses.vault_b, b"vault_b"),
+            (addresses.lp_mint, b"lp_mint"),
+            (addresses.fee_vault_a, b"fee_vault_a"),
+            (addresses.fee_vault_b, b"fee_vault_b"),
+            (fee_distribution_address(&pool).0, b"fee_distribution"),
+        ] {
+            let (expected, _) = Pubkey::find_program_address(&[seed, pool.as_ref()], &amm::ID);
+            assert_eq!(address, expected);
+        }
+
+        // Mint order is part of the seeds.
+        assert_ne!(PoolAddresses::derive(mint_b, mint_a).pool, pool);
+    }
+}
@@ -202,7 +202,7 @@ describe("amm", () => {
     const feeVaultBefore = await getAccount(connection, feeVaultA);
 
     await program.methods
-      .swap(amountIn, amountOut, { aToB: {} })
+      .swap(amountIn, amountOut, { aToB: {} }, 0)
       .accounts({
         user: payer.publicKey,
         pool: poolPda,
@@ -214,6 +214,7 @@ describe("amm", () => {
         feeVaultB,
         userSource: userAtaA.address,
         userDestination: userAtaB.address,
+        referrer: null,
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
       })
       .rpc();
@@ -242,14 +243,13 @@ describe("amm", () => {
     await program.methods
       .withdrawProtocolFees(protocolFee, new anchor.BN(0))
       .accounts({
-        admin: payer.publicKey,
         pool: poolPda,
         mintA,
         mintB,
         feeVaultA,
         feeVaultB,
-        adminAtaA: userAtaA.address,
-        adminAtaB: userAtaB.address,
+        treasuryA: userAtaA.address,
+        treasuryB: userAtaB.address,
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
       })
       .rpc();
@@ -274,7 +274,7 @@ describe("amm", () => {
     );
   });
 
-  it("rejects non-admin protocol fee withdrawal", async () => {
+  it("rejects protocol fee withdrawal to another owner", async () => {
     const {
       connection,
       payer,
@@ -326,7 +326,7 @@ describe("amm", () => {
       .div(reserveA.add(amountInWithFee));
 
     await program.methods
-      .swap(amountIn, amountOut, { aToB: {} })
+      .swap(amountIn, amountOut, { aToB: {} }, 0)
       .accounts({
         user: payer.publicKey,
         pool: poolPda,
@@ -338,6 +338,7 @@ describe("amm", () => {
         feeVaultB,
         userSource: userAtaA.address,
         userDestination: userAtaB.address,
+        referrer: null,
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
       })
       .rpc();
@@ -362,23 +363,21 @@ describe("amm", () => {
       await program.methods
         .withdrawProtocolFees(protocolFee, new anchor.BN(0))
         .accounts({
-          admin: attacker.publicKey,
           pool: poolPda,
           mintA,
           mintB,
           feeVaultA,
           feeVaultB,
-          adminAtaA: attackerAtaA.address,
-          adminAtaB: attackerAtaB.address,
+          treasuryA: attackerAtaA.address,
+          treasuryB: attackerAtaB.address,
           tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
         })
-        .signers([attacker])
         .rpc();
     } catch (err) {
       failed = true;
     }
 
-    assert(failed, "Non-admin withdrawal should fail");
+    assert(failed, "Withdrawal away from the fee recipient should fail");
   });
 
   it("accepts imbalanced deposits", async () => {
@@ -420,7 +419,9 @@ describe("amm", () => {
 
     const imbalancedA = new anchor.BN(1_000_000);
     const imbalancedB = new anchor.BN(3_000_000);
-    const idealB = imbalancedA.mul(reserveB).div(reserveA);
+    // The paired side is rounded up in the pool's favor.
+    const product = imbalancedA.mul(reserveB);
+    const idealB = product.add(reserveA).subn(1).div(reserveA);
 
     const userBefore = await getAccount(connection, userAtaB.address);
 
@@ -520,4 +521,704 @@ describe("amm", () => {
     assert(deltaA.eq(expectedA), "Withdrawn amount A should be proportional");
     assert(deltaB.eq(expectedB), "Withdrawn amount B should be proportional");
   });
+
+  it("rejects swaps past their deadline", async () => {
+    const {
+      connection,
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      feeVaultA,
+      feeVaultB,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        lpMint,
+        userAtaA: userAtaA.address,
+        userAtaB: userAtaB.address,
+        userLp: userLp.address,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const swapAccounts = {
+      user: payer.publicKey,
+      pool: poolPda,
+      mintA,
+      mintB,
+      vaultA,
+      vaultB,
+      feeVaultA,
+      feeVaultB,
+      userSource: userAtaA.address,
+      userDestination: userAtaB.address,
+      referrer: null,
+      tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+    };
+
+    const slot = await connection.getSlot("confirmed");
+    let failed = false;
+    try {
+      await program.methods
+        .swapWithDeadline(new anchor.BN(100_000), new anchor.BN(0), { aToB: {} }, {
+          maxSlot: { 0: new anchor.BN(slot - 1) },
+        })
+        .accounts(swapAccounts)
+        .rpc();
+    } catch (err) {
+      failed = true;
+      assert.include(String(err), "Expired");
+    }
+    assert(failed, "Swap past its max slot should fail");
+
+    const destBefore = await getAccount(connection, userAtaB.address);
+    const deadline = Math.floor(Date.now() / 1000) + 60;
+    await program.methods
+      .swapWithDeadline(new anchor.BN(100_000), new anchor.BN(0), { aToB: {} }, {
+        unixTimestamp: { 0: new anchor.BN(deadline) },
+      })
+      .accounts(swapAccounts)
+      .rpc();
+    const destAfter = await getAccount(connection, userAtaB.address);
+    assert(
+      destAfter.amount > destBefore.amount,
+      "Swap before its deadline should succeed"
+    );
+  });
+
+  it("rejects flash loans that are not repaid", async () => {
+    const {
+      connection,
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      feeVaultA,
+      feeVaultB,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        lpMint,
+        userAtaA: userAtaA.address,
+        userAtaB: userAtaB.address,
+        userLp: userLp.address,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    // The memo program accepts the callback but never repays the loan.
+    const memoProgram = new anchor.web3.PublicKey(
+      "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr"
+    );
+    const vaultBefore = await getAccount(connection, vaultA);
+
+    let failed = false;
+    try {
+      await program.methods
+        .flashLoan(new anchor.BN(500_000), new anchor.BN(0), Buffer.from("loan"))
+        .accounts({
+          user: payer.publicKey,
+          pool: poolPda,
+          mintA,
+          mintB,
+          vaultA,
+          vaultB,
+          feeVaultA,
+          feeVaultB,
+          borrowerAtaA: userAtaA.address,
+          borrowerAtaB: userAtaB.address,
+          receiverProgram: memoProgram,
+          tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+    } catch (err) {
+      failed = true;
+      assert.include(String(err), "FlashLoanNotRepaid");
+    }
+    assert(failed, "Unrepaid flash loan should fail");
+
+    const vaultAfter = await getAccount(connection, vaultA);
+    assert(
+      vaultAfter.amount === vaultBefore.amount,
+      "Vault balance should be unchanged after a failed loan"
+    );
+  });
+
+  it("quotes swaps exactly as executed", async () => {
+    const {
+      connection,
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      feeVaultA,
+      feeVaultB,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        lpMint,
+        userAtaA: userAtaA.address,
+        userAtaB: userAtaB.address,
+        userLp: userLp.address,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const quoteAccounts = { pool: poolPda, vaultA, vaultB, lpMint };
+    const amountIn = new anchor.BN(100_000);
+    const quote = await program.methods
+      .quoteSwap(amountIn, { bToA: {} })
+      .accounts(quoteAccounts)
+      .view();
+
+    const exactOut = await program.methods
+      .quoteSwapExactOut(quote.amountOut, { bToA: {} })
+      .accounts(quoteAccounts)
+      .view();
+    assert(
+      exactOut.amountIn.lte(amountIn),
+      "Exact-out quote should not need more input than the forward quote"
+    );
+    assert(
+      exactOut.amountOut.gte(quote.amountOut),
+      "Exact-out quote should deliver at least the requested output"
+    );
+
+    const destBefore = await getAccount(connection, userAtaA.address);
+    await program.methods
+      .swap(amountIn, quote.amountOut, { bToA: {} }, 0)
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        feeVaultA,
+        feeVaultB,
+        userSource: userAtaB.address,
+        userDestination: userAtaA.address,
+        referrer: null,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    const destAfter = await getAccount(connection, userAtaA.address);
+    const feeVaultAfter = await getAccount(connection, feeVaultB);
+
+    assert(
+      toBn(destAfter.amount - destBefore.amount).eq(quote.amountOut),
+      "Swap output should match the quote"
+    );
+    assert(
+      toBn(feeVaultAfter.amount).eq(quote.protocolFee),
+      "Protocol fee should match the quote"
+    );
+
+    const lpSupply = toBn((await getMint(connection, lpMint)).supply);
+    const withdrawQuote = await program.methods
+      .quoteWithdraw(lpSupply.divn(4))
+      .accounts(quoteAccounts)
+      .view();
+    const reserveA = toBn((await getAccount(connection, vaultA)).amount);
+    assert(
+      withdrawQuote.amountA.eq(lpSupply.divn(4).mul(reserveA).div(lpSupply)),
+      "Withdraw quote should be pro-rata"
+    );
+  });
+
+  it("partially fills swaps at a price limit", async () => {
+    const {
+      connection,
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      feeVaultA,
+      feeVaultB,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        lpMint,
+        userAtaA: userAtaA.address,
+        userAtaB: userAtaB.address,
+        userLp: userLp.address,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    // Spot is 2 B per A; stop once the pool price falls 1% below that.
+    const minPrice = new anchor.BN(198).shln(64).divn(100);
+    const amountIn = new anchor.BN(500_000);
+    const sourceBefore = await getAccount(connection, userAtaA.address);
+
+    await program.methods
+      .swapWithPriceLimit(amountIn, minPrice, { aToB: {} })
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        feeVaultA,
+        feeVaultB,
+        userSource: userAtaA.address,
+        userDestination: userAtaB.address,
+        referrer: null,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const sourceAfter = await getAccount(connection, userAtaA.address);
+    const filled = toBn(sourceBefore.amount - sourceAfter.amount);
+    assert(filled.gtn(0), "Some input should be filled");
+    assert(filled.lt(amountIn), "Fill should stop at the price limit");
+
+    const reserveA = toBn((await getAccount(connection, vaultA)).amount);
+    const reserveB = toBn((await getAccount(connection, vaultB)).amount);
+    assert(
+      reserveB.shln(64).gte(minPrice.mul(reserveA)),
+      "Post-trade price should respect the limit"
+    );
+  });
+
+  it("deposits a single token by swapping internally", async () => {
+    const {
+      connection,
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      feeVaultA,
+      feeVaultB,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        lpMint,
+        userAtaA: userAtaA.address,
+        userAtaB: userAtaB.address,
+        userLp: userLp.address,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const amountIn = new anchor.BN(200_000);
+    const lpBefore = await getAccount(connection, userLp.address);
+    const userBBefore = await getAccount(connection, userAtaB.address);
+    const vaultBBefore = await getAccount(connection, vaultB);
+
+    await program.methods
+      .depositSingleSided(amountIn, { a: {} }, new anchor.BN(1))
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        lpMint,
+        feeVaultA,
+        feeVaultB,
+        userSource: userAtaA.address,
+        userLp: userLp.address,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const lpAfter = await getAccount(connection, userLp.address);
+    const userBAfter = await getAccount(connection, userAtaB.address);
+    const vaultBAfter = await getAccount(connection, vaultB);
+    assert(lpAfter.amount > lpBefore.amount, "LP tokens should be minted");
+    assert(
+      userBAfter.amount === userBBefore.amount,
+      "Token B balance should be untouched"
+    );
+    assert(
+      vaultBAfter.amount === vaultBBefore.amount,
+      "Swapped B should be deposited straight back"
+    );
+  });
+
+  it("withdraws liquidity into a single token", async () => {
+    const {
+      connection,
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      feeVaultA,
+      feeVaultB,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        lpMint,
+        userAtaA: userAtaA.address,
+        userAtaB: userAtaB.address,
+        userLp: userLp.address,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const totalLp = toBn((await getMint(connection, lpMint)).supply);
+    const lpWithdraw = totalLp.divn(4);
+    const reserveA = toBn((await getAccount(connection, vaultA)).amount);
+    const proRataA = lpWithdraw.mul(reserveA).div(totalLp);
+
+    const beforeA = await getAccount(connection, userAtaA.address);
+    const beforeB = await getAccount(connection, userAtaB.address);
+
+    await program.methods
+      .withdrawSingleSided(lpWithdraw, { a: {} }, proRataA)
+      .accounts({
+        user: payer.publicKey,
+        pool: poolPda,
+        mintA,
+        mintB,
+        vaultA,
+        vaultB,
+        lpMint,
+        feeVaultA,
+        feeVaultB,
+        userDestination: userAtaA.address,
+        userLp: userLp.address,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const afterA = await getAccount(connection, userAtaA.address);
+    const afterB = await getAccount(connection, userAtaB.address);
+    assert(
+      toBn(afterA.amount - beforeA.amount).gt(proRataA),
+      "Payout should include the swapped B side"
+    );
+    assert(
+      afterB.amount === beforeB.amount,
+      "No token B should be paid out"
+    );
+  });
+
+  it("mints an exact LP amount within token maximums", async () => {
+    const {
+      connection,
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    const depositAccounts = {
+      user: payer.publicKey,
+      pool: poolPda,
+      mintA,
+      mintB,
+      vaultA,
+      vaultB,
+      lpMint,
+      userAtaA: userAtaA.address,
+      userAtaB: userAtaB.address,
+      userLp: userLp.address,
+      tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+    };
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts(depositAccounts)
+      .rpc();
+
+    const totalLp = toBn((await getMint(connection, lpMint)).supply);
+    const reserveA = toBn((await getAccount(connection, vaultA)).amount);
+    const lpAmount = new anchor.BN(123_457);
+    // Required amounts round up in the pool's favour.
+    const neededA = lpAmount.mul(reserveA).add(totalLp.subn(1)).div(totalLp);
+
+    let failed = false;
+    try {
+      await program.methods
+        .depositExactLp(lpAmount, neededA.subn(1), new anchor.BN(10_000_000))
+        .accounts(depositAccounts)
+        .rpc();
+    } catch (err) {
+      failed = true;
+      assert.include(String(err), "SlippageExceeded");
+    }
+    assert(failed, "Deposit above max_amount_a should fail");
+
+    const lpBefore = await getAccount(connection, userLp.address);
+    const beforeA = await getAccount(connection, userAtaA.address);
+    await program.methods
+      .depositExactLp(lpAmount, neededA, new anchor.BN(10_000_000))
+      .accounts(depositAccounts)
+      .rpc();
+    const lpAfter = await getAccount(connection, userLp.address);
+    const afterA = await getAccount(connection, userAtaA.address);
+
+    assert(
+      toBn(lpAfter.amount - lpBefore.amount).eq(lpAmount),
+      "Exactly the requested LP should be minted"
+    );
+    assert(
+      toBn(beforeA.amount - afterA.amount).eq(neededA),
+      "Token A spent should be the rounded-up requirement"
+    );
+  });
+
+  it("rejects strict deposits outside the ratio tolerance", async () => {
+    const {
+      connection,
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    const depositAccounts = {
+      user: payer.publicKey,
+      pool: poolPda,
+      mintA,
+      mintB,
+      vaultA,
+      vaultB,
+      lpMint,
+      userAtaA: userAtaA.address,
+      userAtaB: userAtaB.address,
+      userLp: userLp.address,
+      tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+    };
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts(depositAccounts)
+      .rpc();
+
+    // 2% more B than the pool ratio, against a 1% tolerance.
+    let failed = false;
+    try {
+      await program.methods
+        .depositLiquidityStrict(
+          new anchor.BN(1_000_000),
+          new anchor.BN(2_040_000),
+          new anchor.BN(0),
+          100
+        )
+        .accounts(depositAccounts)
+        .rpc();
+    } catch (err) {
+      failed = true;
+      assert.include(String(err), "NonProportionalDeposit");
+    }
+    assert(failed, "Deposit outside the tolerance should fail");
+
+    const beforeB = await getAccount(connection, userAtaB.address);
+    await program.methods
+      .depositLiquidityStrict(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_010_000),
+        new anchor.BN(0),
+        100
+      )
+      .accounts(depositAccounts)
+      .rpc();
+    const afterB = await getAccount(connection, userAtaB.address);
+    assert(
+      toBn(beforeB.amount - afterB.amount).eqn(2_000_000),
+      "Deposit within the tolerance should use the pool ratio"
+    );
+  });
+
+  it("guards deposits and withdrawals with a price range", async () => {
+    const {
+      payer,
+      mintA,
+      mintB,
+      poolPda,
+      vaultA,
+      vaultB,
+      lpMint,
+      userAtaA,
+      userAtaB,
+      userLp,
+    } = await setupPool();
+
+    const liquidityAccounts = {
+      user: payer.publicKey,
+      pool: poolPda,
+      mintA,
+      mintB,
+      vaultA,
+      vaultB,
+      lpMint,
+      userAtaA: userAtaA.address,
+      userAtaB: userAtaB.address,
+      userLp: userLp.address,
+      tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+    };
+    await program.methods
+      .depositLiquidity(
+        new anchor.BN(1_000_000),
+        new anchor.BN(2_000_000),
+        new anchor.BN(0)
+      )
+      .accounts(liquidityAccounts)
+      .rpc();
+
+    // Spot price is exactly 2 B per A in Q64.64.
+    const two = new anchor.BN(2).shln(64);
+    let failed = false;
+    try {
+      await program.methods
+        .depositLiquidityGuarded(
+          new anchor.BN(100_000),
+          new anchor.BN(200_000),
+          new anchor.BN(0),
+          null,
+          two.addn(1),
+          null
+        )
+        .accounts(liquidityAccounts)
+        .rpc();
+    } catch (err) {
+      failed = true;
+      assert.include(String(err), "PriceOutOfRange");
+    }
+    assert(failed, "Deposit below min_price should fail");
+
+    await program.methods
+      .withdrawLiquidityGuarded(
+        new anchor.BN(1_000),
+        new anchor.BN(0),
+        new anchor.BN(0),
+        null,
+        two,
+        two
+      )
+      .accounts(liquidityAccounts)
+      .rpc();
+  });
 });